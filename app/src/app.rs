@@ -3,37 +3,251 @@ use std::convert::Infallible;
 use std::fmt::{Debug, Formatter};
 use std::ops::IndexMut;
 use std::path::PathBuf;
-use std::str::FromStr;
 use egui::Widget;
+use futures::FutureExt;
 use serde_derive::{Deserialize, Serialize};
 use tokio::time::Instant;
 use crate::get_runtime;
-use crate::osc::OscCreateData;
+use popup::{confirm_popup_creator, popup_creator, popup_creator_collapsible, PopupFunc};
+
+mod popup;
+use crate::osc::{AppliedParams, AppliedParamsHandle, LastAvatarHandle, LastUnlockHandle, MultiplexerSwitch, RewriteRule, OscCreateData, OscHandlesHandle, OscNotice, OscNotices, OscStatus, OscStatusHandle, StatsHandle, OscValueKind, ParamFilterMode, ParamState, UnlockOutcome};
+
+/// How long in-flight sends get to finish on Disconnect/Reconnect, before the OSC Thread is aborted.
+const OSC_SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How long sending a Message from the "Send a OSC Message" popup may take.
+const MANUAL_SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often the Keys Folder is checked for existence at most.
+const KEYS_FOLDER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long to wait before the first automatic restart of the OSC Thread. This doubles with every further restart.
+const AUTO_RESTART_BASE_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+/// How often the OSC Thread is restarted automatically in a row, before giving up.
+const AUTO_RESTART_MAX_RETRIES: u32 = 5;
+/// How long a restarted OSC Thread has to keep running, for the retries to start over.
+const AUTO_RESTART_STABLE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The level of log messages printed to the console.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    #[default]
+    Trace,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 5] = [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl From<LogLevel> for tracing_subscriber::filter::LevelFilter {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::Error => Self::ERROR,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Trace => Self::TRACE,
+        }
+    }
+}
+
+/// The appearance of the Ui.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+enum Theme {
+    /// Follows the theme of the operating system, if it can be detected. Otherwise Dark is used.
+    #[default]
+    System,
+    Dark,
+    Light,
+}
+
+impl Theme {
+    const ALL: [Theme; 3] = [Theme::System, Theme::Dark, Theme::Light];
+
+    fn is_dark(&self, frame: &eframe::Frame) -> bool {
+        match self {
+            Theme::System => frame.info().system_theme.is_none_or(|theme| theme == eframe::Theme::Dark),
+            Theme::Dark => true,
+            Theme::Light => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Theme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Theme::System => write!(f, "Follow System"),
+            Theme::Dark => write!(f, "Dark"),
+            Theme::Light => write!(f, "Light"),
+        }
+    }
+}
+
+/// How many unlock attempts are kept in the Unlock History by default.
+const DEFAULT_UNLOCK_HISTORY_LEN: usize = 50;
+
+/// An unlock attempt in the Unlock History.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct UnlockHistoryEntry {
+    time: chrono::DateTime<chrono::Local>,
+    id: String,
+    outcome: UnlockOutcome,
+}
+
+/// The cached result of checking the Keys Folder in the Ui.
+struct KeysFolderCheck {
+    path: String,
+    checked: Instant,
+    error: Option<String>,
+}
 
 #[derive(Deserialize, Serialize)]
 #[serde(default)]
 pub struct App<'a>{
     logs_visible: bool,
+    stats_visible: bool,
+    log_level: LogLevel,
+    theme: Theme,
     #[serde(skip)]
     collector:egui_tracing::Logs,
+    #[serde(skip)]
+    event_collector: egui_tracing::EventCollector,
     auto_connect_launch: bool,
-    ip:String,
+    start_minimized: bool,
+    /// Whether the OSC Thread is restarted, if it stops unexpectedly.
+    auto_restart: bool,
+    /// The hex encoded decryption Key in the Ui. Never saved.
+    #[cfg(feature = "user_decryption_key")]
+    #[serde(skip)]
+    user_key_hex: String,
+    /// The hex encoded decryption IV in the Ui. Never saved.
+    #[cfg(feature = "user_decryption_key")]
+    #[serde(skip)]
+    user_iv_hex: String,
+    /// How many automatic restarts happened in a row.
+    #[serde(skip)]
+    auto_restart_attempts: u32,
+    /// When the last automatic restart happened.
+    #[serde(skip)]
+    auto_restart_last: Option<Instant>,
+    /// When the next automatic restart is due, if one is scheduled.
+    #[serde(skip)]
+    auto_restart_at: Option<Instant>,
+    #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+    close_to_tray: bool,
+    #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+    #[serde(skip)]
+    tray: Option<crate::tray::Tray>,
+    #[serde(alias = "ip")]
+    recv_ip:String,
+    /// Empty means the same as `recv_ip`.
+    send_ip:String,
     path:String,
     #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
     #[serde(skip)]
     file_picker_thread: Option<tokio::task::JoinHandle<Option<PathBuf>>>,
+    #[serde(skip)]
+    keys_folder_check: Option<KeysFolderCheck>,
+    /// The path, that is being scanned, and the scan counting the Key files in it.
+    #[serde(skip)]
+    key_count_thread: Option<(String, tokio::task::JoinHandle<std::io::Result<usize>>)>,
+    /// The path, that was last scanned, and the amount of Key files in it.
+    #[serde(skip)]
+    key_count: Option<(String, std::io::Result<usize>)>,
+    #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
+    #[serde(skip)]
+    log_save_thread: Option<tokio::task::JoinHandle<std::io::Result<Option<PathBuf>>>>,
     dex_use_bundles: bool,
+    dex_bundles_acknowledged: bool,
+    dex_use_default_key: bool,
+    dex_default_key_name: String,
+    dex_key_mapping_path: String,
+    dex_trigger_param: String,
+    dex_manifest_path: String,
+    dex_bundle_delay_ms: u64,
+    dex_send_spacing_ms: u64,
+    dex_send_target: String,
+    dex_reassert_interval_secs: u64,
+    dex_coalesce_sends: bool,
+    dex_only_send_deltas: bool,
+    dex_lenient_avatar_change: bool,
+    dex_skip_duplicate_changes: bool,
+    dex_change_addr: String,
+    dex_param_prefix: String,
+    dex_high_precision: bool,
+    dex_forward_to_multiplexer: bool,
+    dex_param_filter_mode: ParamFilterMode,
+    dex_param_filter: Vec<String>,
     osc_recv_port: u16,
     osc_send_port: u16,
     max_message_size: usize,
+    heartbeat_interval_secs: u64,
+    max_in_flight_sends: usize,
+    record_path: String,
+    bad_packet_dir: String,
+    /// The recording, that is replayed by the Replay button. This is not part of [OscCreateData], since it is not needed to Connect.
+    replay_path: String,
+    recv_bind_retries: u32,
+    allow_equal_ports: bool,
+    osc_send_bind_port: u16,
+    osc_bind_interface: String,
+    metrics_addr: String,
     osc_multiplexer_enabled: bool,
     osc_multiplexer_parse_packets: bool,
     dex_protect_enabled: bool,
     osc_multiplexer_rev_port: Vec<u16>,
+    osc_multiplexer_rewrite_rules: Vec<RewriteRule>,
     #[serde(skip)]
     osc_multiplexer_port_popup: Option<Box<PopupFunc<'a>>>,
     #[serde(skip)]
-    osc_thread: Option<tokio::task::JoinHandle<std::io::Result<()>>>,
+    dex_param_filter_popup: Option<Box<PopupFunc<'a>>>,
+    #[serde(skip)]
+    applied_params_popup: Option<Box<PopupFunc<'a>>>,
+    #[serde(skip)]
+    manual_send_popup: Option<Box<PopupFunc<'a>>>,
+    #[serde(skip)]
+    unlock_history_popup: Option<Box<PopupFunc<'a>>>,
+    /// The last unlock attempts, newest first. Only saved, if `persist_unlock_history` is set.
+    unlock_history: VecDeque<UnlockHistoryEntry>,
+    unlock_history_len: usize,
+    persist_unlock_history: bool,
+    #[serde(skip)]
+    osc_thread: Option<tokio::task::JoinHandle<Result<(), crate::osc::OscStartError>>>,
+    #[serde(skip)]
+    osc_shutdown: Option<tokio::sync::watch::Sender<bool>>,
+    #[serde(skip)]
+    osc_status: OscStatusHandle,
+    #[serde(skip)]
+    osc_handles: OscHandlesHandle,
+    #[serde(skip)]
+    last_unlock: LastUnlockHandle,
+    #[serde(skip)]
+    last_avatar: LastAvatarHandle,
+    #[serde(skip)]
+    applied_params: AppliedParamsHandle,
+    #[serde(skip)]
+    multiplexer_switch: MultiplexerSwitch,
+    #[serde(skip)]
+    osc_notices: OscNotices,
+    #[serde(skip)]
+    stats: StatsHandle,
+    #[serde(skip)]
+    reveal_key_thread: Option<(std::sync::Arc<str>, RevealKeyThread)>,
+    #[serde(skip)]
+    replay_thread: Option<tokio::task::JoinHandle<std::io::Result<usize>>>,
+    #[serde(skip)]
+    connection_test_thread: Option<tokio::task::JoinHandle<Result<std::net::SocketAddr, crate::osc::ConnectionTestError>>>,
     #[serde(skip)]
     osc_join_set: Option<tokio::task::JoinSet<Infallible>>,
     osc_create_data: OscCreateData,
@@ -44,21 +258,83 @@ impl<'a> Debug for App<'a>{
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut debug = f.debug_struct("App");
         debug.field("logs_visible", &self.logs_visible)
+            .field("stats_visible", &self.stats_visible)
+            .field("theme", &self.theme)
+            .field("log_level", &self.log_level)
             .field("collector",&self.collector)
             .field("auto_connect_launch",&self.auto_connect_launch)
-            .field("ip", &self.ip)
+            .field("start_minimized",&self.start_minimized)
+            .field("auto_restart",&self.auto_restart)
+            .field("auto_restart_attempts",&self.auto_restart_attempts)
+            .field("auto_restart_last",&self.auto_restart_last)
+            .field("auto_restart_at",&self.auto_restart_at)
+            .field("recv_ip", &self.recv_ip)
+            .field("send_ip", &self.send_ip)
             .field("path", &self.path);
+        #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+        debug.field("close_to_tray", &self.close_to_tray)
+            .field("tray.is_some()", &self.tray.is_some());
         #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
         debug.field("file_picker_thread.is_some()", &self.file_picker_thread.is_some());
+        #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
+        debug.field("log_save_thread.is_some()", &self.log_save_thread.is_some());
         debug
             .field("dex_use_bundles", &self.dex_use_bundles)
+            .field("dex_bundles_acknowledged", &self.dex_bundles_acknowledged)
+            .field("dex_use_default_key", &self.dex_use_default_key)
+            .field("dex_default_key_name", &self.dex_default_key_name)
+            .field("dex_key_mapping_path", &self.dex_key_mapping_path)
+            .field("dex_trigger_param", &self.dex_trigger_param)
+            .field("dex_manifest_path", &self.dex_manifest_path)
+            .field("dex_bundle_delay_ms", &self.dex_bundle_delay_ms)
+            .field("dex_send_spacing_ms", &self.dex_send_spacing_ms)
+            .field("dex_send_target", &self.dex_send_target)
+            .field("dex_reassert_interval_secs", &self.dex_reassert_interval_secs)
+            .field("dex_coalesce_sends", &self.dex_coalesce_sends)
+            .field("dex_only_send_deltas", &self.dex_only_send_deltas)
+            .field("dex_lenient_avatar_change", &self.dex_lenient_avatar_change)
+            .field("dex_skip_duplicate_changes", &self.dex_skip_duplicate_changes)
+            .field("dex_change_addr", &self.dex_change_addr)
+            .field("dex_param_prefix", &self.dex_param_prefix)
+            .field("dex_high_precision", &self.dex_high_precision)
+            .field("dex_forward_to_multiplexer", &self.dex_forward_to_multiplexer)
+            .field("dex_param_filter_mode", &self.dex_param_filter_mode)
+            .field("dex_param_filter", &self.dex_param_filter)
+            .field("keys_folder_check.error", &self.keys_folder_check.as_ref().map(|check|&check.error))
+            .field("key_count_thread.is_some()", &self.key_count_thread.is_some())
+            .field("key_count", &self.key_count)
             .field("osc_recv_port", &self.osc_recv_port)
             .field("osc_send_port", &self.osc_send_port)
             .field("max_message_size", &self.max_message_size)
+            .field("heartbeat_interval_secs", &self.heartbeat_interval_secs)
+            .field("max_in_flight_sends", &self.max_in_flight_sends)
+            .field("record_path", &self.record_path)
+            .field("bad_packet_dir", &self.bad_packet_dir)
+            .field("replay_path", &self.replay_path)
+            .field("recv_bind_retries", &self.recv_bind_retries)
+            .field("allow_equal_ports", &self.allow_equal_ports)
+            .field("osc_send_bind_port", &self.osc_send_bind_port)
+            .field("osc_bind_interface", &self.osc_bind_interface)
+            .field("metrics_addr", &self.metrics_addr)
             .field("osc_multiplexer_enabled", &self.osc_multiplexer_enabled)
             .field("dex_protect_enabled", &self.dex_protect_enabled)
             .field("osc_multiplexer_rev_port", &self.osc_multiplexer_rev_port)
+            .field("osc_multiplexer_rewrite_rules", &self.osc_multiplexer_rewrite_rules)
             .field("osc_thread", &self.osc_thread)
+            .field("osc_shutdown.is_some()", &self.osc_shutdown.is_some())
+            .field("osc_status", &*self.osc_status.lock())
+            .field("last_unlock", &*self.last_unlock.lock())
+            .field("last_avatar", &*self.last_avatar.lock())
+            .field("osc_handles", &self.osc_handles.lock().is_some())
+            .field("applied_params", &*self.applied_params.lock())
+            .field("unlock_history.len()", &self.unlock_history.len())
+            .field("unlock_history_len", &self.unlock_history_len)
+            .field("persist_unlock_history", &self.persist_unlock_history)
+            .field("multiplexer_switch", &self.multiplexer_switch)
+            .field("stats", &self.stats)
+            .field("reveal_key_thread", &self.reveal_key_thread)
+            .field("replay_thread", &self.replay_thread)
+            .field("connection_test_thread", &self.connection_test_thread)
             .field("osc_join_set", &self.osc_join_set)
             .field("osc_create_data", &self.osc_create_data)
             .field("popups.len()", &self.popups.len())
@@ -69,22 +345,95 @@ impl<'a> Default for App<'a>{
     fn default() -> Self {
         Self{
             logs_visible: false,
+            stats_visible: false,
+            log_level: LogLevel::default(),
+            theme: Theme::default(),
             collector:egui_tracing::Logs::new(egui_tracing::EventCollector::new()),
+            event_collector: egui_tracing::EventCollector::new(),
             auto_connect_launch: true,
-            ip:"127.0.0.1".to_string(),
+            start_minimized: false,
+            auto_restart: false,
+            #[cfg(feature = "user_decryption_key")]
+            user_key_hex: String::new(),
+            #[cfg(feature = "user_decryption_key")]
+            user_iv_hex: String::new(),
+            auto_restart_attempts: 0,
+            auto_restart_last: None,
+            auto_restart_at: None,
+            #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+            close_to_tray: false,
+            #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+            tray: None,
+            recv_ip:"127.0.0.1".to_string(),
+            send_ip:"".to_string(),
             path: "".to_string(),
             #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
             file_picker_thread: None,
+            keys_folder_check: None,
+            key_count_thread: None,
+            key_count: None,
+            #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
+            log_save_thread: None,
             dex_use_bundles: false,
+            dex_bundles_acknowledged: false,
+            dex_use_default_key: false,
+            dex_default_key_name: "_default".to_string(),
+            dex_key_mapping_path: "".to_string(),
+            dex_trigger_param: "".to_string(),
+            dex_manifest_path: "".to_string(),
+            dex_bundle_delay_ms: 0,
+            dex_send_spacing_ms: 0,
+            dex_send_target: String::new(),
+            dex_reassert_interval_secs: 0,
+            dex_coalesce_sends: false,
+            dex_only_send_deltas: false,
+            dex_lenient_avatar_change: false,
+            dex_skip_duplicate_changes: true,
+            dex_change_addr: crate::osc::DEFAULT_DEX_CHANGE_ADDR.to_string(),
+            dex_param_prefix: crate::osc::DEFAULT_DEX_PARAM_PREFIX.to_string(),
+            dex_high_precision: false,
+            dex_forward_to_multiplexer: false,
+            dex_param_filter_mode: ParamFilterMode::default(),
+            dex_param_filter: Vec::new(),
             osc_recv_port: crate::osc::OSC_RECV_PORT,
             osc_send_port: crate::osc::OSC_SEND_PORT,
             max_message_size: osc_handler::OSC_RECV_BUFFER_SIZE,
+            heartbeat_interval_secs: osc_handler::receiver::DEFAULT_HEARTBEAT_INTERVAL.map_or(0, |interval| interval.as_secs()),
+            max_in_flight_sends: crate::osc::DEFAULT_MAX_IN_FLIGHT_SENDS,
+            record_path: "".to_string(),
+            bad_packet_dir: "".to_string(),
+            replay_path: "".to_string(),
+            recv_bind_retries: 3,
+            allow_equal_ports: false,
+            osc_send_bind_port: 0,
+            osc_bind_interface: String::new(),
+            metrics_addr: "".to_string(),
             osc_multiplexer_enabled: false,
             osc_multiplexer_parse_packets: false,
             dex_protect_enabled: true,
             osc_multiplexer_rev_port: Vec::new(),
+            osc_multiplexer_rewrite_rules: Vec::new(),
             osc_multiplexer_port_popup: None,
+            dex_param_filter_popup: None,
+            applied_params_popup: None,
+            manual_send_popup: None,
+            unlock_history_popup: None,
+            unlock_history: VecDeque::new(),
+            unlock_history_len: DEFAULT_UNLOCK_HISTORY_LEN,
+            persist_unlock_history: false,
             osc_thread: None,
+            osc_shutdown: None,
+            osc_status: OscStatusHandle::default(),
+            last_unlock: LastUnlockHandle::default(),
+            last_avatar: LastAvatarHandle::default(),
+            osc_handles: OscHandlesHandle::default(),
+            applied_params: AppliedParamsHandle::default(),
+            multiplexer_switch: MultiplexerSwitch::default(),
+            osc_notices: OscNotices::default(),
+            stats: StatsHandle::default(),
+            reveal_key_thread: None,
+            replay_thread: None,
+            connection_test_thread: None,
             osc_join_set: None,
             osc_create_data: OscCreateData::default(),
             popups: VecDeque::new(),
@@ -93,20 +442,83 @@ impl<'a> Default for App<'a>{
 }
 
 impl<'a> TryFrom<&App<'a>> for OscCreateData {
-    type Error = std::net::AddrParseError;
+    type Error = crate::osc::OscConfigError;
 
     fn try_from(value: &App<'a>) -> Result<Self, Self::Error> {
-        Ok(OscCreateData{
-            ip: std::net::IpAddr::from_str(value.ip.as_str())?,
+        let (recv_ip, recv_scope_id) = crate::osc::parse_ip("Receive IP", &value.recv_ip)?;
+        let (send_ip, send_scope_id) = match value.send_ip.trim() {
+            "" => (None, 0),
+            send_ip => {
+                let (send_ip, send_scope_id) = crate::osc::parse_ip("Send IP", send_ip)?;
+                (Some(send_ip), send_scope_id)
+            }
+        };
+        if let Some(send_ip) = send_ip {
+            if send_ip.is_ipv4() != recv_ip.is_ipv4() {
+                return Err(crate::osc::OscConfigError::MixedFamilies { recv_ip, send_ip });
+            }
+        }
+        let metrics_addr = match value.metrics_addr.trim() {
+            "" => None,
+            text => Some(text.parse::<std::net::SocketAddr>().map_err(|error| crate::osc::OscConfigError::InvalidSocketAddr { field: "Metrics Address", text: text.to_string(), error })?),
+        };
+        let dex_send_target = match value.dex_send_target.trim() {
+            "" => None,
+            text => Some(text.parse::<std::net::SocketAddr>().map_err(|error| crate::osc::OscConfigError::InvalidSocketAddr { field: "DexProtect Send Target", text: text.to_string(), error })?),
+        };
+        let dex_change_addr = crate::osc::parse_osc_address("Avatar Change Address", &value.dex_change_addr, crate::osc::DEFAULT_DEX_CHANGE_ADDR)?;
+        let dex_param_prefix = crate::osc::parse_osc_address("Parameter Prefix", &value.dex_param_prefix, crate::osc::DEFAULT_DEX_PARAM_PREFIX)?;
+        let max_message_size = if value.max_message_size < osc_handler::OSC_MIN_MESSAGE_SIZE {
+            log::warn!("The Osc Max Message Size of {} bytes is too small. Using {} bytes instead.", value.max_message_size, osc_handler::OSC_MIN_MESSAGE_SIZE);
+            osc_handler::OSC_MIN_MESSAGE_SIZE
+        } else {
+            value.max_message_size
+        };
+        let data = OscCreateData{
+            recv_ip,
+            recv_scope_id,
+            send_ip,
+            send_scope_id,
             recv_port: value.osc_recv_port,
             send_port: value.osc_send_port,
-            max_message_size: value.max_message_size,
+            max_message_size,
+            heartbeat_interval_secs: value.heartbeat_interval_secs,
+            max_in_flight_sends: value.max_in_flight_sends,
+            record_path: (!value.record_path.is_empty()).then(|| PathBuf::from(&value.record_path)),
+            bad_packet_dir: (!value.bad_packet_dir.is_empty()).then(|| PathBuf::from(&value.bad_packet_dir)),
+            recv_bind_retries: value.recv_bind_retries,
+            allow_equal_ports: value.allow_equal_ports,
+            osc_send_bind_port: value.osc_send_bind_port,
+            osc_bind_interface: (!value.osc_bind_interface.trim().is_empty()).then(|| value.osc_bind_interface.trim().to_string()),
+            metrics_addr,
             dex_protect_enabled: value.dex_protect_enabled,
             dex_use_bundles: value.dex_use_bundles,
+            dex_use_default_key: value.dex_use_default_key,
+            dex_default_key_name: value.dex_default_key_name.clone(),
+            dex_key_mapping_path: (!value.dex_key_mapping_path.is_empty()).then(|| PathBuf::from(&value.dex_key_mapping_path)),
+            dex_trigger_param: crate::osc::trigger_address(&value.dex_trigger_param, &dex_param_prefix),
+            dex_manifest_path: (!value.dex_manifest_path.is_empty()).then(|| PathBuf::from(&value.dex_manifest_path)),
+            dex_bundle_delay_ms: value.dex_bundle_delay_ms,
+            dex_send_spacing_ms: value.dex_send_spacing_ms,
+            dex_send_target,
+            dex_reassert_interval_secs: value.dex_reassert_interval_secs,
+            dex_coalesce_sends: value.dex_coalesce_sends,
+            dex_only_send_deltas: value.dex_only_send_deltas,
+            dex_lenient_avatar_change: value.dex_lenient_avatar_change,
+            dex_skip_duplicate_changes: value.dex_skip_duplicate_changes,
+            dex_change_addr,
+            dex_param_prefix,
+            dex_high_precision: value.dex_high_precision,
+            dex_forward_to_multiplexer: value.dex_forward_to_multiplexer,
+            dex_param_filter_mode: value.dex_param_filter_mode,
+            dex_param_filter: value.dex_param_filter.clone(),
             path: PathBuf::from(&value.path),
             osc_multiplexer_rev_port: if value.osc_multiplexer_enabled {value.osc_multiplexer_rev_port.clone()} else {Vec::new()},
             osc_multiplexer_parse_packets: value.osc_multiplexer_parse_packets,
-        })
+            osc_multiplexer_rewrite_rules: if value.osc_multiplexer_enabled {value.osc_multiplexer_rewrite_rules.clone()} else {Vec::new()},
+        };
+        data.check_ports()?;
+        Ok(data)
     }
 }
 
@@ -127,13 +539,49 @@ impl<'a> App<'a> {
 
         #[cfg(not(debug_assertions))]
         log::info!("You are running a release build. Some log statements were disabled.");
-        slf.collector = egui_tracing::Logs::new(collector);
+        crate::set_log_level(slf.log_level.into());
+        slf.collector = egui_tracing::Logs::new(collector.clone());
+        slf.event_collector = collector;
+        #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+        match crate::tray::Tray::new(cc.egui_ctx.clone()) {
+            Ok(tray) => slf.tray = Some(tray),
+            Err(e) => log::error!("{e}. Closing to the tray is not available."),
+        }
         if slf.auto_connect_launch{
-            slf.spawn_osc_from_creation_data();
+            slf.spawn_osc_from_creation_data(None);
+        }
+        if slf.start_minimized {
+            slf.minimize_on_start(&cc.egui_ctx);
         }
         slf
     }
 
+    /// Gets the window out of the way on startup.
+    /// If closing to the tray is enabled, the window is hidden to the tray instead of being minimized.
+    fn minimize_on_start(&self, ctx: &egui::Context) {
+        #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+        if let Some(tray) = self.tray.as_ref().filter(|_| self.close_to_tray) {
+            tray.hide(ctx);
+            return;
+        }
+        log::info!("Starting minimized.");
+        ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(true));
+    }
+
+    /// Stops waiting for open file dialogs.
+    /// This doesn't close the dialogs themselves, but nothing is held on to after they are closed.
+    #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
+    fn abort_file_dialogs(&mut self) {
+        if let Some(file_picker_thread) = self.file_picker_thread.take() {
+            log::info!("Aborting the Folder Picker.");
+            file_picker_thread.abort();
+        }
+        if let Some(log_save_thread) = self.log_save_thread.take() {
+            log::info!("Aborting the Log Save Dialogue. The Logs are not saved.");
+            log_save_thread.abort();
+        }
+    }
+
     fn has_file_picker_thread(&self)->bool{
         #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
         return self.file_picker_thread.is_some();
@@ -141,6 +589,76 @@ impl<'a> App<'a> {
         false
     }
 
+    /// Re-checks the Keys Folder, if the last check is older than [KEYS_FOLDER_CHECK_INTERVAL].
+    /// This avoids querying the filesystem on every frame.
+    fn update_keys_folder_check(&mut self, ctx: &egui::Context) {
+        let stale = match &self.keys_folder_check {
+            Some(check) => check.checked.elapsed() >= KEYS_FOLDER_CHECK_INTERVAL,
+            None => true,
+        };
+        if stale {
+            let path = PathBuf::from(&self.path);
+            self.keys_folder_check = Some(KeysFolderCheck {
+                path: self.path.clone(),
+                checked: Instant::now(),
+                error: crate::osc::keys_folder_error(&path, std::fs::metadata(&path)),
+            });
+        }
+        if self.keys_folder_check.as_ref().is_some_and(|check| check.path != self.path) {
+            ctx.request_repaint_after(KEYS_FOLDER_CHECK_INTERVAL);
+        }
+    }
+
+    /// Returns the problem with the Keys Folder, if DexProtect is enabled and the folder is invalid.
+    /// The Keys Folder is not used, if a Key Manifest is configured.
+    fn keys_folder_error(&self) -> Option<&str> {
+        if !self.dex_protect_enabled || !self.dex_manifest_path.is_empty() {
+            return None;
+        }
+        self.keys_folder_check.as_ref().and_then(|check|check.error.as_deref())
+    }
+
+    /// Shows how many Key files are in the Keys Folder.
+    /// The Keys Folder is re-scanned in the background, when the path changes or Refresh is clicked.
+    fn key_count_ui(&mut self, ui:&mut egui::Ui){
+        if let Some((path, key_count_thread)) = self.key_count_thread.take() {
+            if key_count_thread.is_finished(){
+                match get_runtime().block_on(key_count_thread) {
+                    Ok(result) => self.key_count = Some((path, result)),
+                    Err(e) => {
+                        log::error!("Panic whilst counting the Key files: {}", e);
+                        self.handle_join_error(&e, "Critical Error whilst counting the Key files");
+                    }
+                }
+            }else{
+                self.key_count_thread = Some((path, key_count_thread));
+            }
+        }
+        let scanning = self.key_count_thread.is_some();
+        let refresh = ui.horizontal(|ui|{
+            match &self.key_count {
+                Some((path, result)) if !scanning && path == &self.path => match result {
+                    Ok(count) => {ui.label(format!("{count} .key files found"));},
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {ui.colored_label(egui::Color32::RED, "Folder not found");},
+                    Err(e) => {ui.colored_label(egui::Color32::RED, format!("Failed to count the .key files: {e}"));},
+                },
+                _ => {ui.label("Counting .key files...");},
+            }
+            ui.add_enabled(!scanning, egui::Button::new("Refresh")).clicked()
+        }).inner;
+        let outdated = self.key_count.as_ref().is_none_or(|(path, _)| path != &self.path);
+        if !scanning && (refresh || outdated) {
+            let path = self.path.clone();
+            let ctx = ui.ctx().clone();
+            let scan_path = PathBuf::from(&path);
+            self.key_count_thread = Some((path, get_runtime().spawn(async move {
+                let result = crate::osc::count_key_files(scan_path).await;
+                ctx.request_repaint();
+                result
+            })));
+        }
+    }
+
     fn handle_join_error(
         &mut self,
         error: &tokio::task::JoinError,
@@ -156,69 +674,469 @@ impl<'a> App<'a> {
         title: impl Into<egui::WidgetText> + 'a,
     ) {
         let error_string = error.to_string();
-        let label = label.into().clone();
+        let label = label.into();
+        let title = title.into();
+        //Everything needed for a bug report, so that users don't need to select the text themselves.
+        let developer_info = format!("{}\n{}\n{}", title.text(), label.text(), error_string);
         self.popups.push_front(popup_creator(title, move |_, ui| {
             ui.label(label.clone());
             ui.label("Some developer information below:");
             ui.label(&error_string);
+            if ui.button("Copy")
+                .on_hover_text("Copies the title, the message and the developer information, e.g. for a bug report.")
+                .clicked()
+            {
+                ui.output_mut(|o| o.copied_text = developer_info.clone());
+            }
         }));
     }
 
-    fn spawn_osc_from_creation_data(&mut self){
-        log::info!("Trying to connect to OSC on IP '{}'", self.osc_create_data.ip);
+    /// Spawns the OSC Thread.
+    /// If `previous` is given, it is awaited first, so that the old OSC Thread has released its sockets.
+    fn spawn_osc_from_creation_data(&mut self, previous: Option<tokio::task::JoinHandle<()>>){
+        log::info!("Trying to connect to OSC. Receiving on IP '{}', sending to IP '{}'", self.osc_create_data.recv_ip, self.osc_create_data.send_ip());
         let osc_create_data = self.osc_create_data.clone();
+        let (shutdown, shutdown_receiver) = tokio::sync::watch::channel(false);
+        self.osc_shutdown = Some(shutdown);
+        //A fresh handle per OSC Thread, so that a stopping OSC Thread can't overwrite the status of the new one.
+        let status = OscStatusHandle::new(egui::mutex::Mutex::new(OscStatus::Connecting));
+        self.osc_status = status.clone();
+        let handles = OscHandlesHandle::default();
+        self.osc_handles = handles.clone();
+        let last_unlock = self.last_unlock.clone();
+        let last_avatar = self.last_avatar.clone();
+        let applied_params = self.applied_params.clone();
+        let multiplexer_switch = self.multiplexer_switch.clone();
+        let notices = self.osc_notices.sender();
+        let stats = self.stats.clone();
         self.osc_thread = Some(tokio::spawn(async move {
-            let mut js = crate::osc::create_and_start_osc(&osc_create_data).await?;
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+            let mut runtime = match crate::osc::create_and_start_osc(&osc_create_data, shutdown_receiver, &status, &last_unlock, &last_avatar, &applied_params, &multiplexer_switch, notices, &stats).await {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    *status.lock() = OscStatus::Error(e.to_string());
+                    return Err(e);
+                }
+            };
             log::info!("Successfully connected to OSC and started all Handlers.");
-            loop{
-                match js.join_next().await {
-                    Some(Ok(_)) => {
-                        log::error!("Joined a Task that should never finish. This should never happen.\nIs there a bug in the rust language, or is the developer just stupid?");
+            *handles.lock() = Some(runtime.handles);
+            let result = loop{
+                match runtime.join_set.join_next().await {
+                    Some(Ok(())) => {
+                        log::debug!("An OSC Task stopped.");
                     },
                     Some(Err(e)) => {
                         log::error!("Panic in OSC Thread: {}", e);
-                        return Err(std::io::Error::new(std::io::ErrorKind::Other,e))
+                        *status.lock() = OscStatus::Error(e.to_string());
+                        break Err(std::io::Error::new(std::io::ErrorKind::Other,e).into())
                     },
-                    None => return Ok(()),
+                    None => break Ok(()),
+                }
+            };
+            *handles.lock() = None;
+            result
+        }));
+    }
+
+    /// Asks the OSC Thread to stop, which lets in-flight sends (e.g. a partially sent unlock) finish.
+    /// If the OSC Thread doesn't stop within [OSC_SHUTDOWN_TIMEOUT], it is aborted.
+    ///
+    /// Returns a handle, that finishes once the OSC Thread is gone.
+    fn stop_osc_thread(&mut self) -> Option<tokio::task::JoinHandle<()>> {
+        //A Disconnect or Reconnect by the user cancels a pending automatic restart.
+        self.auto_restart_at = None;
+        self.auto_restart_attempts = 0;
+        if let Some(shutdown) = self.osc_shutdown.take() {
+            let _ = shutdown.send(true);
+        }
+        *self.osc_status.lock() = OscStatus::Disconnected;
+        //Aborting the OSC Thread skips its cleanup, so the handles are released here.
+        *self.osc_handles.lock() = None;
+        let mut osc_thread = self.osc_thread.take()?;
+        log::info!("Stopping the OSC Thread.");
+        Some(get_runtime().spawn(async move {
+            match tokio::time::timeout(OSC_SHUTDOWN_TIMEOUT, &mut osc_thread).await {
+                Ok(_) => log::info!("OSC Thread stopped"),
+                Err(_) => {
+                    log::warn!("OSC Thread did not stop within {}ms. Aborting OSC thread.", OSC_SHUTDOWN_TIMEOUT.as_millis());
+                    osc_thread.abort();
+                    let _ = osc_thread.await;
+                    log::info!("OSC Thread aborted");
                 }
             }
+        }))
+    }
+
+    /// Pauses or resumes DexProtect in the running OSC Thread, so that this doesn't need a Reconnect.
+    /// If DexProtect wasn't started on Connect, enabling it still needs a Reconnect.
+    fn apply_dex_enabled(&mut self) {
+        let handles = self.osc_handles.lock();
+        if let Some(dex) = handles.as_ref().and_then(|handles| handles.dex.as_ref()) {
+            dex.set_enabled(self.dex_protect_enabled);
+            //Applied already, so this isn't shown as a pending change.
+            self.osc_create_data.dex_protect_enabled = self.dex_protect_enabled;
+        }
+    }
+
+    /// Returns the names of the settings, that were changed in the Ui since the last Connect.
+    /// Empty, if the OSC Thread isn't running or the settings in the Ui are invalid.
+    fn pending_changes(&self) -> Vec<&'static str> {
+        if self.osc_thread.is_none() {
+            return Vec::new();
+        }
+        OscCreateData::try_from(self)
+            .map(|data| data.changed_settings(&self.osc_create_data))
+            .unwrap_or_default()
+    }
+
+    /// Applies the Settings from the Ui and (re)starts the OSC Thread.
+    fn connect(&mut self){
+        let previous = self.stop_osc_thread();
+        match OscCreateData::try_from(&*self) {
+            Ok(osc_create_data) => {
+                self.osc_create_data = osc_create_data;
+                self.spawn_osc_from_creation_data(previous);
+            },
+            Err(e) => self.invalid_ip_popup(&e),
+        }
+    }
+
+    fn invalid_ip_popup(&mut self, e: &crate::osc::OscConfigError) {
+        log::error!("Invalid IP Settings: {}", e);
+        if let crate::osc::OscConfigError::EqualPorts { .. } = e {
+            self.handle_display_popup("The OSC Send Port and the OSC Receive Port must be different. For a loopback test, enable \"Allow equal Send and Receive Ports\".", e, "Equal OSC Ports");
+            return;
+        }
+        self.handle_display_popup("The IP Settings are invalid. Please fix them and try again.",e,"Error Parsing IP-Address")
+    }
+
+    /// Explains, that another Application is using the OSC Receive Port,
+    /// and offers to switch to the next free port.
+    fn recv_port_in_use_popup(&mut self, ip: std::net::IpAddr, port: u16, error: std::io::Error) {
+        let error_string = error.to_string();
+        let mut result: Option<Option<u16>> = None;
+        self.popups.push_front(popup_creator("OSC Receive Port in use", move |app, ui| {
+            ui.label(format!("Another Application is already using the OSC Receive Port {port}."));
+            ui.label("Please close the other Application, or change the OSC Receive Port.");
+            ui.label("If you change the OSC Receive Port, VRChat needs to be told to send to that port as well (e.g. with the --osc launch option).");
+            match result {
+                None => {
+                    if ui.button("Try the next free Port").clicked() {
+                        let free_port = crate::osc::find_free_recv_port(ip, port);
+                        if let Some(free_port) = free_port {
+                            log::info!("Switching the OSC Receive Port from {} to the free port {}", port, free_port);
+                            app.osc_recv_port = free_port;
+                            app.connect();
+                        } else {
+                            log::warn!("No free OSC Receive Port was found after {}", port);
+                        }
+                        result = Some(free_port);
+                    }
+                }
+                Some(Some(free_port)) => {
+                    ui.label(format!("Switched to the OSC Receive Port {free_port} and reconnected."));
+                }
+                Some(None) => {
+                    ui.label("No free Port was found. Please choose the OSC Receive Port manually.");
+                }
+            }
+            ui.label("Some developer information below:");
+            ui.label(&error_string);
         }));
     }
 
     fn check_osc_thread(&mut self){
-        if let Some(osc_thread) = self.osc_thread.take() {
-            if osc_thread.is_finished(){
-                match get_runtime().block_on(osc_thread){
-                    Ok(Ok(())) => {
+        if let Some(mut osc_thread) = self.osc_thread.take() {
+            //The JoinHandle is polled instead of blocked on, so that the Ui can never wait on the OSC Thread.
+            let result = (&mut osc_thread).now_or_never();
+            //Config errors won't go away by restarting.
+            let restartable = !matches!(result, None | Some(Ok(Err(crate::osc::OscStartError::Config(_)))));
+            if restartable && self.schedule_auto_restart() {
+                match result {
+                    Some(Ok(Ok(()))) => {
                         log::error!("OSC Thread finished unexpectedly");
-                        let time = Instant::now();
+                        *self.osc_status.lock() = OscStatus::Disconnected;
+                    }
+                    Some(Ok(Err(e))) => log::warn!("Error in OSC Thread: {}", e),
+                    Some(Err(e)) => {
+                        log::error!("Panic in OSC Thread: {}", e);
+                        *self.osc_status.lock() = OscStatus::Error(e.to_string());
+                    }
+                    None => {},
+                }
+                return;
+            }
+            match result {
+                None => {
+                    self.osc_thread = Some(osc_thread);
+                    if self.auto_restart_last.is_some_and(|last| last.elapsed() >= AUTO_RESTART_STABLE) {
+                        log::info!("The automatically restarted OSC Thread is running stable.");
+                        self.auto_restart_last = None;
+                        self.auto_restart_attempts = 0;
+                    }
+                }
+                Some(Ok(Ok(()))) => {
+                    log::error!("OSC Thread finished unexpectedly");
+                    *self.osc_status.lock() = OscStatus::Disconnected;
+                    let time = Instant::now();
+                    self.popups.push_back(popup_creator(
+                        "OSC Thread Exited",
+                        move |_, ui| {
+                            ui.label("The OSC Thread (the one that communicates with VRChat) exited unexpectedly.");
+                            ui.label(format!("This happened {:.1} ago. (this updates only when you move your mouse or something changes)", time.elapsed().as_secs_f32()));
+                        })
+                    )
+                }
+                Some(Ok(Err(crate::osc::OscStartError::RecvPortInUse { ip, port, error }))) => {
+                    log::warn!("The OSC Receive Port {} is already in use: {}", port, error);
+                    self.recv_port_in_use_popup(ip, port, error);
+                }
+                Some(Ok(Err(e))) => {
+                    log::warn!("Error in OSC Thread: {}",e);
+                    self.handle_display_popup("Osc Error:", &e, "Error in Osc");
+                }
+                Some(Err(e)) => {
+                    log::error!("Panic in OSC Thread: {}", e);
+                    *self.osc_status.lock() = OscStatus::Error(e.to_string());
+                    self.handle_join_error(&e, "Critical Error in Osc");
+                }
+            }
+        }
+    }
+    /// Schedules an automatic restart of the OSC Thread, if that is enabled.
+    /// The delay doubles with every restart in a row, to avoid a tight crash loop.
+    ///
+    /// Returns false, if the OSC Thread should not be restarted.
+    /// If that is because of too many restarts in a row, a popup tells the user.
+    fn schedule_auto_restart(&mut self) -> bool {
+        if !self.auto_restart {
+            return false;
+        }
+        if self.auto_restart_attempts >= AUTO_RESTART_MAX_RETRIES {
+            log::error!("The OSC Thread stopped {AUTO_RESTART_MAX_RETRIES} times in a row. Not restarting it again.");
+            let attempts = self.auto_restart_attempts;
+            self.auto_restart_attempts = 0;
+            self.auto_restart_last = None;
+            self.popups.push_back(popup_creator(
+                "OSC Thread Restarts Failed",
+                move |_, ui| {
+                    ui.label(format!("The OSC Thread (the one that communicates with VRChat) was restarted {attempts} times in a row, but kept stopping."));
+                    ui.label("It will not be restarted again automatically. Please check the logs and Connect again, once the problem is fixed.");
+                })
+            );
+            return false;
+        }
+        let delay = AUTO_RESTART_BASE_DELAY * 2u32.pow(self.auto_restart_attempts);
+        self.auto_restart_attempts += 1;
+        log::warn!("The OSC Thread stopped. Restarting it in {}s (attempt {} of {AUTO_RESTART_MAX_RETRIES}).", delay.as_secs(), self.auto_restart_attempts);
+        self.auto_restart_at = Some(Instant::now() + delay);
+        true
+    }
+    /// Restarts the OSC Thread, once a scheduled automatic restart is due.
+    fn check_auto_restart(&mut self, ctx: &egui::Context){
+        let Some(at) = self.auto_restart_at else {
+            return;
+        };
+        let now = Instant::now();
+        if now < at {
+            //Nothing else might cause a repaint, while the OSC Thread isn't running. Once a second keeps the countdown current.
+            ctx.request_repaint_after((at - now).min(std::time::Duration::from_secs(1)));
+            return;
+        }
+        self.auto_restart_at = None;
+        self.auto_restart_last = Some(now);
+        log::info!("Automatically restarting the OSC Thread.");
+        self.spawn_osc_from_creation_data(None);
+    }
+    /// Shows popups for everything the OSC Thread wants to tell the user about.
+    fn check_osc_notices(&mut self){
+        let notices: Vec<OscNotice> = self.osc_notices.try_iter().collect();
+        for notice in notices {
+            match notice {
+                OscNotice::KeysFolderUnavailable { path, error } => {
+                    let label = format!("The Keys Folder '{}' is no longer accessible. If it is on a network or removable drive, please check that it is still connected.\nNo Avatar will be unlocked until it is accessible again. You don't need to Reconnect.", path.display());
+                    self.handle_display_popup(label, &error, "Keys Folder not accessible");
+                }
+                OscNotice::Unlocked { id, outcome } => {
+                    self.unlock_history.push_front(UnlockHistoryEntry {
+                        time: chrono::Local::now(),
+                        id: id.to_string(),
+                        outcome,
+                    });
+                    self.unlock_history.truncate(self.unlock_history_len);
+                }
+            }
+        }
+    }
+    fn check_connection_test(&mut self){
+        if let Some(connection_test_thread) = self.connection_test_thread.take() {
+            if connection_test_thread.is_finished(){
+                match get_runtime().block_on(connection_test_thread){
+                    Ok(Ok(addr)) => {
                         self.popups.push_back(popup_creator(
-                            "OSC Thread Exited",
+                            "Connection Test Succeeded",
                             move |_, ui| {
-                                ui.label("The OSC Thread (the one that communicates with VRChat) exited unexpectedly.");
-                                ui.label(format!("This happened {:.1} ago. (this updates only when you move your mouse or something changes)", time.elapsed().as_secs_f32()));
+                                ui.label(format!("Received OSC Data from {addr}."));
+                                ui.label("VRChat (or another OSC Application) is reachable. You can Connect now.");
                             })
                         )
                     }
                     Ok(Err(e)) => {
-                        log::warn!("Error in OSC Thread: {}",e);
-                        self.handle_display_popup("Osc Error:", &e, "Error in Osc");
+                        log::warn!("OSC Connection Test failed: {}", e);
+                        let label = match &e {
+                            crate::osc::ConnectionTestError::Bind { error, .. } if error.kind() == std::io::ErrorKind::AddrInUse =>
+                                "The OSC Receive Port is already in use by another Application. Close that Application or change the OSC Receive Port.",
+                            crate::osc::ConnectionTestError::Bind { .. } =>
+                                "The OSC Receive Port could not be bound. Please check the IP and the OSC Receive Port.",
+                            crate::osc::ConnectionTestError::NothingReceived(_) =>
+                                "Nothing was received. VRChat might not be running, or OSC might be disabled in VRChat's Action Menu. Moving around in VRChat should cause it to send OSC Data.",
+                            _ => "The Connection Test failed.",
+                        };
+                        self.handle_display_popup(label, &e, "Connection Test Failed");
                     }
                     Err(e) => {
-                        log::error!("Panic in OSC Thread: {}", e);
-                        self.handle_join_error(&e, "Critical Error in Osc");
+                        log::error!("Panic in the OSC Connection Test: {}", e);
+                        self.handle_join_error(&e, "Critical Error in the Connection Test");
+                    }
+                }
+            }else{
+                self.connection_test_thread = Some(connection_test_thread);
+            }
+        }
+    }
+    fn check_replay(&mut self){
+        if let Some(replay_thread) = self.replay_thread.take() {
+            if replay_thread.is_finished(){
+                match get_runtime().block_on(replay_thread){
+                    Ok(Ok(count)) => log::info!("Replayed {count} recorded datagrams."),
+                    Ok(Err(e)) => {
+                        log::warn!("Failed to replay the recording '{}': {e}", self.replay_path);
+                        self.handle_display_popup("The recording could not be replayed.", &e, "Replay Failed");
+                    }
+                    Err(e) => {
+                        log::error!("Panic while replaying a recording: {}", e);
+                        self.handle_join_error(&e, "Critical Error while replaying a recording");
+                    }
+                }
+            }else{
+                self.replay_thread = Some(replay_thread);
+            }
+        }
+    }
+
+    fn check_reveal_key(&mut self){
+        if let Some((id, reveal_key_thread)) = self.reveal_key_thread.take() {
+            if reveal_key_thread.is_finished(){
+                match get_runtime().block_on(reveal_key_thread){
+                    Ok(Ok(params)) => {
+                        self.popups.push_back(popup_creator(
+                            format!("Decoded Key for {id}"),
+                            move |_, ui| {
+                                ui.label("Nothing was sent to VRChat. Don't share these values.");
+                                egui::ScrollArea::vertical().max_height(300.).show(ui, |ui|{
+                                    egui::Grid::new("revealed_key").striped(true).show(ui, |ui|{
+                                        ui.strong("Parameter");
+                                        ui.strong("Value");
+                                        ui.end_row();
+                                        for (name, value) in &params {
+                                            ui.label(name);
+                                            ui.label(value.to_string());
+                                            ui.end_row();
+                                        }
+                                    });
+                                });
+                            })
+                        )
+                    }
+                    Ok(Err(e)) => {
+                        log::warn!("Failed to reveal the Key for the Avatar id '{id}': {e}");
+                        self.handle_display_popup("The Key could not be read or parsed.", &e, "Failed to reveal the Key");
+                    }
+                    Err(e) => {
+                        log::error!("Panic while revealing a Key: {}", e);
+                        self.handle_join_error(&e, "Critical Error while revealing a Key");
                     }
                 }
             }else{
-                self.osc_thread = Some(osc_thread);
+                self.reveal_key_thread = Some((id, reveal_key_thread));
             }
         }
     }
+
     fn dex_protect_ui(&mut self, ui:&mut egui::Ui){
         ui.heading("DexProtect:");
         ui.horizontal(|ui|{
-            ui.checkbox(&mut self.dex_use_bundles, "Use Osc Bundles: ");
+            let bundles = ui.checkbox(&mut self.dex_use_bundles, "Use Osc Bundles: ");
+            if bundles.changed() && self.dex_use_bundles && !self.dex_bundles_acknowledged {
+                //Only enabled, once the user has acknowledged the known issue.
+                self.dex_use_bundles = false;
+                self.popups.push_back(confirm_popup_creator(
+                    "Use Osc Bundles?",
+                    "VRChat is known to handle parameters inside of Osc Bundles inconsistently.\nWith newer style Keys, this can leave Avatars only partially unlocked.\nOnly use Osc Bundles, if you know that your setup needs them.",
+                    "I understand, use Osc Bundles",
+                    |app| {
+                        log::info!("The known issues with Osc Bundles were acknowledged.");
+                        app.dex_use_bundles = true;
+                        app.dex_bundles_acknowledged = true;
+                    },
+                ));
+            }
             ui.hyperlink_to("This is known to cause issues with VRChat.", "https://feedback.vrchat.com/bug-reports/p/inconsistent-handling-of-osc-packets-inside-osc-bundles-and-osc-packages");
+            ui.add_enabled(self.dex_use_bundles, egui::DragValue::new(&mut self.dex_bundle_delay_ms).suffix("ms"))
+                .on_hover_text("Applies the Bundle this far in the future. 0 applies it immediately.");
+        });
+        ui.horizontal(|ui|{
+            ui.checkbox(&mut self.dex_coalesce_sends, "Coalesce Parameter Sends")
+                .on_hover_text(format!("Collects parameters for {}ms and only sends the latest value per parameter. This reduces the amount of OSC Messages sent to VRChat.", crate::osc::COALESCE_WINDOW.as_millis()));
+            ui.checkbox(&mut self.dex_only_send_deltas, "Only Send Changes")
+                .on_hover_text("Skips the parameters of a Key, that VRChat already reported with the value from the Key. Parameters, that VRChat didn't report since the last Avatar change, are always sent.");
+            ui.label("Resend Interval:");
+            ui.add(egui::DragValue::new(&mut self.dex_reassert_interval_secs).suffix("s"))
+                .on_hover_text("Sends the parameters of the last Key again this often, until the Avatar changes. This restores parameters, that VRChat has reset. 0 never sends them again.");
+            ui.label("Send Spacing:");
+            ui.add_enabled(!self.dex_use_bundles && !self.dex_coalesce_sends, egui::DragValue::new(&mut self.dex_send_spacing_ms).range(0..=1000).suffix("ms"))
+                .on_hover_text("Waits this long between sending the parameters of a Key. 0 sends them all at once.\nThis makes unlocking slower, but can help, if VRChat drops parameters, that are sent too quickly. Not used with Osc Bundles or Coalescing.");
+            ui.checkbox(&mut self.dex_high_precision, "High Precision")
+                .on_hover_text("Sends the values of a Key as 64-bit Doubles instead of 32-bit Floats. VRChat expects Floats, so only enable this, if another Application needs it.");
+            if ui.add_enabled(self.dex_param_filter_popup.is_none(), egui::Button::new("Manage Parameter Filter"))
+                .on_hover_text("Parameters, that a Key should never set (or the only ones it may set).")
+                .clicked()
+            {
+                self.dex_param_filter_popup = Some(popup_creator_collapsible("DexProtect Parameter Filter:", true, |app, ui|{
+                    egui::ComboBox::from_label("Mode")
+                        .selected_text(app.dex_param_filter_mode.to_string())
+                        .show_ui(ui, |ui|{
+                            for mode in [ParamFilterMode::Deny, ParamFilterMode::Allow] {
+                                ui.selectable_value(&mut app.dex_param_filter_mode, mode, mode.to_string());
+                            }
+                        });
+                    let mut i = 0;
+                    while i < app.dex_param_filter.len(){
+                        ui.horizontal(|ui|{
+                            ui.label(app.dex_param_prefix.as_str());
+                            ui.text_edit_singleline(app.dex_param_filter.index_mut(i));
+                            if ui.button("Delete")
+                                .on_hover_text("Delete this Parameter from the list, and replaces it with the last one.")
+                                .clicked()
+                            {
+                                app.dex_param_filter.swap_remove(i);
+                            }
+                        });
+                        i+=1;
+                    }
+                    if ui.button("Add Parameter").clicked() {
+                        app.dex_param_filter.push(String::new());
+                    }
+                }));
+            }
+        });
+        ui.horizontal(|ui|{
+            ui.checkbox(&mut self.dex_use_default_key, "Use a Default Key: ")
+                .on_hover_text("Avatars without their own Key file are unlocked with this Key file from the Keys Folder instead.");
+            ui.add_enabled(self.dex_use_default_key, egui::TextEdit::singleline(&mut self.dex_default_key_name));
+            ui.label(".key");
         });
         ui.horizontal(|ui|{
             ui.label("Keys Folder: ");
@@ -241,6 +1159,15 @@ impl<'a> App<'a> {
                 let mut resp = ui.add_enabled(self.file_picker_thread.is_none(), egui::Button::new("Browse"));
                 if !resp.enabled(){
                     resp = resp.on_hover_text("A Dialogue to Pick a Folder is currently open. Please use that one.");
+                    if ui.button("Cancel")
+                        .on_hover_text("Stops waiting for the Dialogue, e.g. if it got stuck or is hidden. Whatever is picked in it afterwards is ignored.")
+                        .clicked()
+                    {
+                        if let Some(file_picker_thread) = self.file_picker_thread.take() {
+                            log::info!("Cancelled the Folder Picker.");
+                            file_picker_thread.abort();
+                        }
+                    }
                 }
                 if resp.clicked(){
                     self.file_picker_thread = Some(get_runtime().spawn(async{
@@ -259,7 +1186,7 @@ impl<'a> App<'a> {
                             },
                             Ok(None) => log::info!("No Folder Picked."),
                             Err(e) => {
-                                log::error!("Panic whist picking a Folder: {}", e);
+                                log::error!("Panic while picking a Folder: {}", e);
                                 self.handle_join_error(&e, "Critical Error whilst picking a Folder");
                             }
                         }
@@ -269,15 +1196,216 @@ impl<'a> App<'a> {
                 }
             }
         });
+        if let Some(err) = self.keys_folder_error() {
+            ui.colored_label(egui::Color32::RED, err);
+        }
+        self.key_count_ui(ui);
+        ui.horizontal(|ui|{
+            ui.label("Key Manifest: ");
+            ui.add(egui::TextEdit::singleline(&mut self.dex_manifest_path).hint_text("Not used"))
+                .on_hover_text("A single (encrypted) file with one 'avatar id=key' pair per line. It is read once on Connect and replaces the Keys Folder. Leave this empty to use one Key file per Avatar from the Keys Folder.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Key Mapping: ");
+            ui.add(egui::TextEdit::singleline(&mut self.dex_key_mapping_path).hint_text("Not used"))
+                .on_hover_text("A file with one 'avatar id pattern=key' pair per line (e.g. 'avtr_abc*=myavatar.key'). Avatar ids matching a pattern use that Key instead of their own.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Avatar Change Address: ");
+            ui.add(egui::TextEdit::singleline(&mut self.dex_change_addr).hint_text(crate::osc::DEFAULT_DEX_CHANGE_ADDR))
+                .on_hover_text("The OSC Address, on which Avatar changes are received. Only change this, if VRChat (or the Application you use) sends Avatar changes somewhere else.");
+            ui.checkbox(&mut self.dex_lenient_avatar_change, "Lenient")
+                .on_hover_text("Uses the first String of an Avatar change as the Avatar id, even if there are more arguments. Without this, such Avatar changes are ignored.");
+            ui.checkbox(&mut self.dex_skip_duplicate_changes, "Skip Duplicates")
+                .on_hover_text("Ignores Avatar changes to the Avatar, that was just unlocked (e.g. the repeated Avatar changes on a world load). Use \"Unlock Again\" to unlock it anyway.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Parameter Prefix: ");
+            ui.add(egui::TextEdit::singleline(&mut self.dex_param_prefix).hint_text(crate::osc::DEFAULT_DEX_PARAM_PREFIX))
+                .on_hover_text("The OSC Address prefix of Avatar parameters. The Key parameters are sent to this prefix followed by their name.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Send Target: ");
+            ui.add(egui::TextEdit::singleline(&mut self.dex_send_target).hint_text("OSC Send IP and Port"))
+                .on_hover_text("An IP-Address and Port (e.g. 127.0.0.1:9010), that only DexProtect sends to, e.g. a mock for testing. Leave this empty to send to VRChat like everything else.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Unlock Trigger Parameter: ");
+            ui.add(egui::TextEdit::singleline(&mut self.dex_trigger_param).hint_text("Unlock on Avatar change"))
+                .on_hover_text("If set, Avatars are not unlocked on Avatar change anymore. Instead the current Avatar is unlocked, once this parameter (e.g. 'DexUnlock' or a full OSC address) is set to true.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Last Unlock:");
+            match &*self.last_unlock.lock() {
+                None => {ui.label("No Avatar Change yet");},
+                Some((id, outcome)) => {
+                    let color = unlock_outcome_color(outcome, ui);
+                    ui.label(id.as_ref());
+                    ui.colored_label(color, outcome.to_string());
+                }
+            }
+            if ui.add_enabled(self.applied_params_popup.is_none(), egui::Button::new("Show Applied Parameters"))
+                .on_hover_text("Shows the parameters sent for the last Key and whether VRChat confirmed them.")
+                .clicked()
+            {
+                self.applied_params_popup = Some(popup_creator_collapsible("Applied Parameters:", true, applied_params_ui));
+            }
+            if ui.add_enabled(self.unlock_history_popup.is_none(), egui::Button::new("Show Unlock History"))
+                .on_hover_text("Shows the last unlock attempts with their time and outcome.")
+                .clicked()
+            {
+                self.unlock_history_popup = Some(popup_creator_collapsible("Unlock History:", true, unlock_history_ui));
+            }
+            let last_id = self.last_unlock.lock().as_ref().map(|(id, _)| id.clone());
+            if ui.add_enabled(last_id.is_some() && self.reveal_key_thread.is_none(), egui::Button::new("Reveal Key"))
+                .on_hover_text("Decodes the Key for the current Avatar and shows its parameters, without sending anything. This is meant for troubleshooting.")
+                .clicked()
+            {
+                if let Some(id) = last_id {
+                    self.popups.push_back(confirm_popup_creator(
+                        "Reveal the decoded Key?",
+                        format!("This shows the parameters and values of the Key for the Avatar id '{id}'.\nAnyone who knows them can unlock the Avatar. Only continue, if you understand that this reveals secrets."),
+                        "I understand, Reveal",
+                        move |app| match OscCreateData::try_from(&*app) {
+                            Ok(data) => app.reveal_key_thread = Some((id.clone(), get_runtime().spawn(crate::osc::reveal_key(data, id.clone())))),
+                            Err(e) => app.invalid_ip_popup(&e),
+                        },
+                    ));
+                }
+            }
+        });
+        self.last_avatar_ui(ui);
         ui.add_space(10.)
     }
+    /// Renders the fields to supply a decryption Key and IV for testing.
+    #[cfg(feature = "user_decryption_key")]
+    fn user_decryption_key_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui|{
+            ui.label("Decryption Key (hex):");
+            ui.add(egui::TextEdit::singleline(&mut self.user_key_hex).password(true).desired_width(200.));
+            ui.label("IV (hex):");
+            ui.add(egui::TextEdit::singleline(&mut self.user_iv_hex).password(true).desired_width(120.));
+            if ui.button("Apply")
+                .on_hover_text(format!("Tries this Key first, when decrypting. This is for testing with your own Key and is not saved. It can also be set with the {} and {} environment variables.", crate::osc::USER_KEY_ENV, crate::osc::USER_IV_ENV))
+                .clicked()
+            {
+                match crate::osc::parse_user_decryption_key(&self.user_key_hex, &self.user_iv_hex) {
+                    Ok(key) => crate::osc::set_user_decryption_key(Some(key)),
+                    Err(e) => self.handle_display_popup("The decryption Key or IV is invalid.", &e, "Invalid Decryption Key"),
+                }
+            }
+            if ui.button("Clear").clicked() {
+                self.user_key_hex.clear();
+                self.user_iv_hex.clear();
+                crate::osc::set_user_decryption_key(None);
+            }
+        });
+    }
+    /// Renders the last seen Avatar id with buttons to copy it and to open the Keys Folder, where its Key belongs.
+    fn last_avatar_ui(&mut self, ui: &mut egui::Ui) {
+        let last_avatar = self.last_avatar.lock().clone();
+        ui.horizontal(|ui|{
+            ui.label("Last Avatar:");
+            match &last_avatar {
+                None => {ui.label("No Avatar Change yet");},
+                Some(id) => {ui.add(egui::Label::new(id.as_ref()).selectable(true));},
+            }
+            if ui.add_enabled(last_avatar.is_some(), egui::Button::new("Copy"))
+                .on_hover_text("Copies the Avatar id to the clipboard.")
+                .clicked()
+            {
+                if let Some(id) = &last_avatar {
+                    ui.output_mut(|o| o.copied_text = id.to_string());
+                }
+            }
+            let dex = self.osc_handles.lock().as_ref().and_then(|handles| handles.dex.clone());
+            let unlock = ui.add_enabled(last_avatar.is_some() && dex.is_some(), egui::Button::new("Unlock Again"))
+                .on_hover_text("Reads and sends the Key of this Avatar again, e.g. if VRChat reset some of its parameters.")
+                .on_disabled_hover_text("Needs a running OSC Thread with DexProtect enabled and an Avatar Change.");
+            if unlock.clicked() {
+                if let (Some(id), Some(dex)) = (&last_avatar, dex) {
+                    get_runtime().spawn(dex.force_unlock(id.clone()));
+                }
+            }
+            let mut resp = ui.add_enabled(last_avatar.is_some(), egui::Button::new("Open Keys Folder"));
+            if let Some(id) = &last_avatar {
+                resp = match crate::osc::key_path(std::path::Path::new(&self.path), id) {
+                    Some(key_path) => resp.on_hover_text(format!("The Key for this Avatar is expected at '{}'.", key_path.display())),
+                    None => resp.on_hover_text("The Avatar id is not a valid file name, so it can't have a Key file."),
+                };
+            }
+            if resp.clicked() {
+                if let Err(e) = opener::open(&self.path) {
+                    log::error!("Failed to open the Keys Folder '{}': {e}", self.path);
+                    self.handle_display_popup("Failed to open the Keys Folder.", &e, "Error opening the Keys Folder");
+                }
+            }
+        });
+    }
+    /// Renders the "Save Logs" button and handles the resulting save dialog.
+    #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
+    fn save_logs_ui(&mut self, ui: &mut egui::Ui) {
+        let mut resp = ui.add_enabled(self.log_save_thread.is_none(), egui::Button::new("Save Logs"));
+        if !resp.enabled(){
+            resp = resp.on_hover_text("A Dialogue to Save the Logs is currently open. Please use that one.");
+        }
+        if resp.clicked(){
+            let logs = format_logs(&self.event_collector);
+            self.log_save_thread = Some(get_runtime().spawn(async move {
+                let path = match rfd::AsyncFileDialog::new()
+                    .set_file_name("DexProtectOSC-RS.log")
+                    .save_file()
+                    .await {
+                    Some(file) => file.path().to_path_buf(),
+                    None => return Ok(None),
+                };
+                tokio::fs::write(&path, logs).await?;
+                Ok(Some(path))
+            }));
+        }
+        if let Some(log_save_thread) = self.log_save_thread.take(){
+            if log_save_thread.is_finished(){
+                match get_runtime().block_on(log_save_thread) {
+                    Ok(Ok(Some(path))) => log::info!("Saved the Logs to '{}'", path.display()),
+                    Ok(Ok(None)) => {
+                        log::info!("No File to save the Logs to was picked.");
+                        self.popups.push_back(popup_creator("Logs not saved", |_, ui| {
+                            ui.label("No File was picked, so the Logs were not saved.");
+                        }));
+                    },
+                    Ok(Err(e)) => {
+                        log::error!("Failed to save the Logs: {}", e);
+                        self.handle_display_popup("The Logs could not be written to the picked File.", &e, "Error saving Logs");
+                    }
+                    Err(e) => {
+                        log::error!("Panic while saving the Logs: {}", e);
+                        self.handle_join_error(&e, "Critical Error whilst saving the Logs");
+                    }
+                }
+            }else{
+                self.log_save_thread = Some(log_save_thread);
+            }
+        }
+    }
+
     fn multiplexer_ui(&mut self, ui: &mut egui::Ui) {
         ui.heading("Osc Multiplexer:");
         ui.label("All messages Received from the Osc Receive Port will be forwarded to the Ports specified in the list below.");
         ui.label("This allows you to use multiple Osc Applications, that need to Receive Messages, at the same time.");
 
-        ui.checkbox(&mut self.osc_multiplexer_parse_packets, "Parse Packets and Ignore Packets that can't be parsed");
-        if ui.add_enabled(self.osc_multiplexer_port_popup.is_none(), egui::Button::new("Manage Ports")).clicked() {
+        ui.horizontal(|ui|{
+            ui.checkbox(&mut self.osc_multiplexer_parse_packets, "Parse Packets and Ignore Packets that can't be parsed");
+            ui.checkbox(&mut self.dex_forward_to_multiplexer, "Also forward DexProtect's Messages")
+                .on_hover_text("The parameters DexProtect sends to VRChat are also forwarded, so that e.g. an overlay can react to unlocks.");
+            let enabled = self.multiplexer_switch.is_enabled();
+            if ui.button(if enabled {"Pause Forwarding"} else {"Resume Forwarding"})
+                .on_hover_text("Temporarily stops forwarding, without needing to Reconnect.")
+                .clicked()
+            {
+                self.multiplexer_switch.set_enabled(!enabled);
+            }
+        });
+        if ui.add_enabled(self.osc_multiplexer_port_popup.is_none(), egui::Button::new("Manage Ports and Rewrite Rules")).clicked() {
             self.osc_multiplexer_port_popup = Some(popup_creator_collapsible("Osc Multiplexer Ports:", true, |app, ui|{
                 let mut i = 0;
                 while i < app.osc_multiplexer_rev_port.len(){
@@ -297,16 +1425,102 @@ impl<'a> App<'a> {
                 if ui.button("Add Port").clicked() {
                     app.osc_multiplexer_rev_port.push(0);
                 }
+                ui.separator();
+                ui.label("Rewrite Rules:")
+                    .on_hover_text("Changes the address of Messages forwarded to a port, e.g. for Applications expecting a different parameter name.\nA '*' at the end of From matches every address starting with the rest, and replaces that part with To.\nThe first matching rule wins. Rewriting needs parsed Packets, so Packets, that can't be parsed, are not forwarded while there are rules.");
+                let mut remove = None;
+                for (i, rule) in app.osc_multiplexer_rewrite_rules.iter_mut().enumerate() {
+                    ui.horizontal(|ui|{
+                        ui.label("Port:");
+                        ui.add(egui::DragValue::new(&mut rule.port));
+                        ui.label("From:");
+                        ui.add(egui::TextEdit::singleline(&mut rule.from).hint_text("/avatar/parameters/Old*").desired_width(150.));
+                        ui.label("To:");
+                        ui.add(egui::TextEdit::singleline(&mut rule.to).hint_text("/avatar/parameters/New*").desired_width(150.));
+                        if ui.button("Delete").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    app.osc_multiplexer_rewrite_rules.remove(i);
+                }
+                if ui.button("Add Rewrite Rule").clicked() {
+                    app.osc_multiplexer_rewrite_rules.push(RewriteRule {
+                        port: app.osc_multiplexer_rev_port.first().copied().unwrap_or_default(),
+                        ..RewriteRule::default()
+                    });
+                }
             }));
         }
         ui.add_space(10.)
     }
 
+    /// Shows the totals since launch, which are kept across reconnects.
+    fn stats_ui(&self, ui: &mut egui::Ui) {
+        ui.heading("Statistics:");
+        let receiver = self.stats.receiver.snapshot();
+        let forwarded = self.stats.forwarded_snapshot();
+        egui::ScrollArea::vertical().show(ui, |ui|{
+            egui::Grid::new("stats").striped(true).show(ui, |ui|{
+                ui.label("Datagrams received");
+                ui.label(format!("{} ({} bytes)", receiver.datagrams, receiver.bytes));
+                ui.end_row();
+                ui.label("Packets / Messages");
+                ui.label(format!("{} / {}", receiver.packets, receiver.messages));
+                ui.end_row();
+                ui.label("Decode errors");
+                ui.label(receiver.decode_errors.to_string())
+                    .on_hover_text("How often received data could not be decoded and was discarded.");
+                ui.end_row();
+                ui.label("Bundles buffered / fired");
+                ui.label(format!("{} / {}", receiver.bundles_buffered, receiver.bundles_fired))
+                    .on_hover_text("Bundles with a timetag in the future are buffered, until their time has come.");
+                ui.end_row();
+                ui.label("Bundles pending / cleared");
+                ui.horizontal(|ui|{
+                    ui.label(format!("{} / {}", receiver.bundles_pending, receiver.bundles_cleared));
+                    let control = self.osc_handles.lock().as_ref().and_then(|handles| handles.receiver.clone());
+                    let clear = ui.add_enabled(
+                        control.is_some() && receiver.bundles_pending > 0,
+                        egui::Button::new(format!("Clear pending bundles ({})", receiver.bundles_pending)),
+                    ).on_hover_text("Drops all buffered bundles without applying them, e.g. after a misbehaving sender sent bundles far in the future.");
+                    if clear.clicked() {
+                        if let Some(control) = control {
+                            log::info!("Clearing the pending bundles was requested.");
+                            control.clear_pending_bundles();
+                        }
+                    }
+                });
+                ui.end_row();
+                for (port, count) in forwarded {
+                    ui.label(format!("Forwarded to port {port}"));
+                    ui.label(count.to_string());
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
     fn osc_control_ui(&mut self, ui: &mut egui::Ui){
         ui.heading("Generic Osc Controls:");
         ui.horizontal(|ui|{
-            ui.label("IP:");
-            ui.text_edit_singleline(&mut self.ip);
+            let status = self.osc_status.lock();
+            ui.colored_label(status.color(), "⏺");
+            ui.label(status.to_string());
+            if matches!(*status, OscStatus::Connecting) {
+                ui.ctx().request_repaint_after(std::time::Duration::from_millis(250));
+            }
+        });
+        ui.horizontal(|ui|{
+            ui.label("Receive IP:");
+            ui.text_edit_singleline(&mut self.recv_ip)
+                .on_hover_text("Use 0.0.0.0 (or :: for IPv6) to receive on all interfaces, e.g. if VRChat runs on another machine. IPv6 link-local Addresses need a scope id (e.g. fe80::1%3).");
+            ui.label("Send IP:");
+            egui::TextEdit::singleline(&mut self.send_ip)
+                .hint_text(self.recv_ip.as_str())
+                .ui(ui)
+                .on_hover_text("The IP of the machine VRChat runs on. Leave empty to use the Receive IP.");
         });
         ui.horizontal(|ui|{
             ui.label("OSC Receive Port:");
@@ -322,43 +1536,175 @@ impl<'a> App<'a> {
                 self.osc_send_port = crate::osc::OSC_SEND_PORT;
             }
         });
+        ui.horizontal(|ui|{
+            ui.label("OSC Send Source Port:");
+            ui.add(egui::DragValue::new(&mut self.osc_send_bind_port))
+                .on_hover_text("The local port DexProtect sends from. Some firewalls need a fixed port here. 0 picks a random free port.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Network Interface:");
+            ui.add(egui::TextEdit::singleline(&mut self.osc_bind_interface).hint_text("Any"))
+                .on_hover_text("The name of the network interface (e.g. 'eth0') OSC is received and sent on. Useful with multiple networks, e.g. a VPN and a LAN. Leave empty to only bind to the IP.");
+            if !crate::osc::INTERFACE_BINDING_SUPPORTED && !self.osc_bind_interface.trim().is_empty() {
+                ui.colored_label(egui::Color32::YELLOW, "Not supported on this platform. Only the IP is used.");
+            }
+        });
         ui.horizontal(|ui|{
             ui.label("Osc Max Message Size:");
             egui::DragValue::new(&mut self.max_message_size)
                 .speed(1)
-                .range(1..=usize::try_from(isize::MAX).unwrap_or(usize::MAX))
+                .range(osc_handler::OSC_MIN_MESSAGE_SIZE..=usize::try_from(isize::MAX).unwrap_or(usize::MAX))
                 .ui(ui);
             if ui.button("Reset to Default").clicked() {
                 self.max_message_size = osc_handler::OSC_RECV_BUFFER_SIZE;
             }
         });
+        ui.horizontal(|ui|{
+            ui.label("Max Sends in Flight:");
+            ui.add(egui::DragValue::new(&mut self.max_in_flight_sends))
+                .on_hover_text("How many OSC Messages DexProtect and each Multiplexer Port may be sending at the same time. Further Messages wait. This limits the load, when a lot is sent at once. 0 means no limit.");
+            if ui.button("Reset to Default").clicked() {
+                self.max_in_flight_sends = crate::osc::DEFAULT_MAX_IN_FLIGHT_SENDS;
+            }
+        });
+        ui.horizontal(|ui|{
+            ui.label("Heartbeat Log Interval:");
+            ui.add(egui::DragValue::new(&mut self.heartbeat_interval_secs).suffix("s"))
+                .on_hover_text("Periodically logs (at debug level) how much OSC Data was received, to show that the Receiver is still alive. 0 disables this.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Receive Port Bind Retries:");
+            ui.add(egui::DragValue::new(&mut self.recv_bind_retries).range(0..=10))
+                .on_hover_text("If the OSC Receive Port is in use (e.g. by an instance, that was just closed), binding it is retried this often, waiting longer each time.");
+        });
+        ui.checkbox(&mut self.allow_equal_ports, "Allow equal Send and Receive Ports")
+            .on_hover_text("Everything sent to the OSC Send Port is then received again. Only enable this for a loopback test.");
+        ui.horizontal(|ui|{
+            ui.label("Record Packets to:");
+            ui.add(egui::TextEdit::singleline(&mut self.record_path).hint_text("Disabled (a file)"))
+                .on_hover_text("Records every received OSC Packet with its timing into this file, so that the session can be replayed later to reproduce a problem. The file is overwritten on every Connect. Leave this empty to disable it.");
+        });
+        ui.horizontal(|ui|{
+            ui.label("Replay Recording:");
+            ui.add(egui::TextEdit::singleline(&mut self.replay_path).hint_text("A recorded file"));
+            let recv_addr = match &*self.osc_status.lock() {
+                OscStatus::Connected { recv_addr, .. } => Some(*recv_addr),
+                _ => None,
+            };
+            if ui.add_enabled(recv_addr.is_some() && self.replay_thread.is_none() && !self.replay_path.is_empty(), egui::Button::new("Replay"))
+                .on_hover_text("Sends the recorded OSC Packets to the OSC Receive Port with their original timing, as if VRChat sent them again.")
+                .on_disabled_hover_text("Connect first, enter a recorded file and wait for a running Replay to finish.")
+                .clicked()
+            {
+                if let Some(recv_addr) = recv_addr {
+                    self.replay_thread = Some(get_runtime().spawn(crate::osc::replay_recording(PathBuf::from(&self.replay_path), recv_addr)));
+                }
+            }
+        });
+        ui.horizontal(|ui|{
+            ui.label("Capture Bad Packets to:");
+            ui.add(egui::TextEdit::singleline(&mut self.bad_packet_dir).hint_text("Disabled (a folder)"))
+                .on_hover_text("Writes the raw bytes of every OSC Packet, that could not be decoded, into rotating files in this folder. This helps diagnosing misbehaving OSC Applications. Leave this empty to disable it.");
+        });
+        #[cfg(feature = "metrics")]
+        ui.horizontal(|ui|{
+            ui.label("Metrics Address:");
+            ui.add(egui::TextEdit::singleline(&mut self.metrics_addr).hint_text("Disabled (e.g. 127.0.0.1:9464)"))
+                .on_hover_text("Serves Prometheus metrics on http://<this address>/metrics. Leave this empty to disable the endpoint.");
+        });
         ui.label("Please note that the Settings in the Ui will only be applied after you Reconnect/Connect.");
+        let pending_changes = self.pending_changes();
         ui.horizontal(|ui|{
-            if ui.button(if self.osc_thread.is_some() {"Reconnect"} else {"Connect"}).clicked() {
-                if let Some(osc_thread) = self.osc_thread.take(){
-                    log::info!("OSC Thread is already running and a Reconnect was requested. Aborting OSC thread.");
-                    osc_thread.abort();
-                    log::info!("OSC Thread aborted");
+            let mut connect = ui.add_enabled(
+                self.keys_folder_error().is_none(),
+                egui::Button::new(if self.osc_thread.is_some() {"Reconnect"} else {"Connect"})
+                    .selected(!pending_changes.is_empty())
+            );
+            if !pending_changes.is_empty() {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("{} pending changes", pending_changes.len()))
+                    .on_hover_text(format!("These Settings were changed since the last Connect and are applied on Reconnect:\n{}", pending_changes.join("\n")));
+            }
+            if let Some(err) = self.keys_folder_error() {
+                connect = connect.on_disabled_hover_text(format!("DexProtect is enabled, but the Keys Folder is invalid: {err}"));
+            }
+            if connect.clicked() {
+                if self.osc_thread.is_some() {
+                    log::info!("OSC Thread is already running and a Reconnect was requested.");
+                }
+                self.connect();
+            }
+            if self.osc_thread.is_some() && ui.button("Disconnect").clicked() {
+                log::info!("OSC Thread is running and a Disconnect was requested.");
+                if self.applied_params.lock().as_ref().is_some_and(AppliedParams::in_progress) {
+                    log::info!("An unlock is in progress. Asking for confirmation before disconnecting.");
+                    self.popups.push_back(confirm_popup_creator(
+                        "Unlock in Progress",
+                        "An unlock is in progress. Disconnecting now might leave the Avatar only partially unlocked.\nDisconnect anyway?",
+                        "Disconnect",
+                        |app| {
+                            log::info!("Disconnecting, even though an unlock is in progress.");
+                            app.stop_osc_thread();
+                        },
+                    ));
+                } else {
+                    self.stop_osc_thread();
+                }
+            }
+            if let Some(at) = self.auto_restart_at {
+                ui.label(format!("Restarting in {}s", at.saturating_duration_since(Instant::now()).as_secs() + 1));
+                if ui.button("Cancel Restart").clicked() {
+                    log::info!("The automatic restart of the OSC Thread was cancelled.");
+                    self.auto_restart_at = None;
+                    self.auto_restart_attempts = 0;
                 }
+            }
+            let mut test = ui.add_enabled(
+                self.osc_thread.is_none() && self.connection_test_thread.is_none(),
+                egui::Button::new(if self.connection_test_thread.is_some() {"Testing Connection..."} else {"Test Connection"})
+            );
+            if self.osc_thread.is_some() {
+                test = test.on_disabled_hover_text("The Connection Test needs the OSC Receive Port. Please Disconnect first.");
+            } else if self.connection_test_thread.is_some() {
+                ui.ctx().request_repaint_after(std::time::Duration::from_millis(250));
+            } else {
+                test = test.on_hover_text(format!("Sends a harmless OSC Message and waits up to {}s for any OSC Data from VRChat.", crate::osc::CONNECTION_TEST_TIMEOUT.as_secs()));
+            }
+            if test.clicked() {
                 match OscCreateData::try_from(&*self) {
-                    Ok(osc_create_data) => {
-                        self.osc_create_data = osc_create_data;
-                        self.spawn_osc_from_creation_data();
+                    Ok(data) => {
+                        self.connection_test_thread = Some(get_runtime().spawn(crate::osc::test_connection(data.recv_addr(), data.send_addr())));
                     },
-                    Err(e) => {
-                        log::error!("\"{}\" is not a valid IP-Address. Rust error: \"{}\"",self.ip,  e);
-                        self.handle_display_popup(format!("\"{}\" is not a valid IP-Address", self.ip),&e,"Error Parsing IP-Address")
-                    }
+                    Err(e) => self.invalid_ip_popup(&e),
                 }
             }
-            if self.osc_thread.is_some() && ui.button("Disconnect").clicked() {
-                if let Some(osc_thread) = self.osc_thread.take(){
-                    log::info!("OSC Thread is already running and a Disconnect was requested. Aborting OSC thread.");
-                    osc_thread.abort();
-                    log::info!("OSC Thread aborted");
+            if ui.add_enabled(self.manual_send_popup.is_none(), egui::Button::new("Send a Message"))
+                .on_hover_text("Sends a single OSC Message to the Send Port, e.g. to test a parameter.")
+                .clicked()
+            {
+                self.manual_send_popup = Some(manual_send_popup());
+            }
+            if ui.button("Copy Headless Command")
+                .on_hover_text("Copies the command line, that runs DexProtect without the Ui with the current Settings, e.g. on a server.")
+                .clicked()
+            {
+                match OscCreateData::try_from(&*self) {
+                    Ok(data) => {
+                        let command = crate::cli::headless_command(&data);
+                        log::info!("Headless command for the current Settings: {command}");
+                        ui.output_mut(|o| o.copied_text = command.clone());
+                        self.popups.push_back(popup_creator("Headless Command Copied", move |_, ui| {
+                            ui.label("The following command was copied to the clipboard:");
+                            ui.code(&command);
+                            ui.label("The arguments are quoted for a POSIX shell (e.g. bash).");
+                        }));
+                    },
+                    Err(e) => self.invalid_ip_popup(&e),
                 }
             }
             ui.checkbox(&mut self.auto_connect_launch, "Auto-Connect on Launch");
+            ui.checkbox(&mut self.auto_restart, "Auto-Restart")
+                .on_hover_text(format!("Restarts the OSC Thread, if it stops unexpectedly. Gives up after {AUTO_RESTART_MAX_RETRIES} restarts in a row."));
+            ui.checkbox(&mut self.start_minimized, "Start Minimized");
         });
         ui.add_space(10.);
     }
@@ -367,20 +1713,53 @@ impl<'a> App<'a> {
 impl<'a> eframe::App for App<'a> {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         self.check_osc_thread();
+        self.check_auto_restart(ctx);
+        self.check_connection_test();
+        self.check_reveal_key();
+        self.check_replay();
+        //Applied every frame, so that Follow System notices when the system theme changes.
+        let dark = self.theme.is_dark(frame);
+        if ctx.style().visuals.dark_mode != dark {
+            ctx.set_visuals(if dark {egui::Visuals::dark()} else {egui::Visuals::light()});
+        }
+        self.check_osc_notices();
+        #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+        if let Some(tray) = &self.tray {
+            if self.close_to_tray && !tray.quit_requested() && ctx.input(|i|i.viewport().close_requested()) {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                tray.hide(ctx);
+            }
+        }
+        if self.dex_protect_enabled {
+            self.update_keys_folder_check(ctx);
+        }
+        if !crate::osc::decryption_keys_available() {
+            egui::TopBottomPanel::top("no_decryption_keys").show(ctx, |ui| {
+                ui.colored_label(egui::Color32::YELLOW, "This build has no decryption Keys. Only unencrypted legacy Keys will work. Encrypted Keys will fail to decode.");
+            });
+        }
+        #[cfg(feature = "user_decryption_key")]
+        egui::TopBottomPanel::top("user_decryption_key").show(ctx, |ui| {
+            self.user_decryption_key_ui(ui);
+        });
         egui::CentralPanel::default().show(ctx, |ui| {
             //create immutable copies
             let dex_protect_enabled = self.dex_protect_enabled;
             let osc_multiplexer_enabled = self.osc_multiplexer_enabled;
             let logs_visible = self.logs_visible;
+            let stats_visible = self.stats_visible;
             let mut strip_builder = egui_extras::StripBuilder::new(ui);
             if dex_protect_enabled {
-                strip_builder = strip_builder.size(egui_extras::Size::exact(80.));
+                strip_builder = strip_builder.size(egui_extras::Size::exact(if self.keys_folder_error().is_some() {240.} else {220.}));
             }
             if osc_multiplexer_enabled {
                 strip_builder = strip_builder.size(egui_extras::Size::exact(90.));
             }
-            strip_builder = strip_builder.size(egui_extras::Size::exact(130.))
+            strip_builder = strip_builder.size(egui_extras::Size::exact(if cfg!(feature = "metrics") {295.} else {275.}))
                 .size(egui_extras::Size::exact(25.));
+            if stats_visible {
+                strip_builder = strip_builder.size(egui_extras::Size::exact(150.));
+            }
             if logs_visible {
                 strip_builder = strip_builder.size(egui_extras::Size::remainder());
             }
@@ -403,10 +1782,46 @@ impl<'a> eframe::App for App<'a> {
                         if ui.button(if self.logs_visible {"Hide Logs"} else { "Show Logs"}).clicked() {
                         self.logs_visible = !self.logs_visible;
                         }
-                        ui.checkbox(&mut self.dex_protect_enabled, "Enable DexProtectOSC");
+                        if ui.button(if self.stats_visible {"Hide Statistics"} else { "Show Statistics"}).clicked() {
+                            self.stats_visible = !self.stats_visible;
+                        }
+                        #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
+                        self.save_logs_ui(ui);
+                        let log_level = self.log_level;
+                        egui::ComboBox::from_label("Console Log Level")
+                            .selected_text(self.log_level.to_string())
+                            .show_ui(ui, |ui|{
+                                for level in LogLevel::ALL {
+                                    ui.selectable_value(&mut self.log_level, level, level.to_string());
+                                }
+                            });
+                        if log_level != self.log_level {
+                            log::info!("Changing the Console Log Level to {}", self.log_level);
+                            crate::set_log_level(self.log_level.into());
+                        }
+                        egui::ComboBox::from_label("Theme")
+                            .selected_text(self.theme.to_string())
+                            .show_ui(ui, |ui|{
+                                for theme in Theme::ALL {
+                                    ui.selectable_value(&mut self.theme, theme, theme.to_string());
+                                }
+                            });
+                        #[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+                        ui.add_enabled(self.tray.is_some(), egui::Checkbox::new(&mut self.close_to_tray, "Close to Tray"))
+                            .on_hover_text("Closing the window hides it instead. Use the tray icon to show it again or to quit.")
+                            .on_disabled_hover_text("The tray icon could not be created. See the Logs for details.");
+                        if ui.checkbox(&mut self.dex_protect_enabled, "Enable DexProtectOSC").changed() {
+                            self.apply_dex_enabled();
+                        }
                         ui.checkbox(&mut self.osc_multiplexer_enabled, "Enable Osc Multiplexer (allows for multiple Osc send applications) ");
                     });
                 });
+                if stats_visible {
+                    strip.cell(|ui|{
+                        ctx.request_repaint_after_secs(1.);
+                        self.stats_ui(ui);
+                    });
+                }
                 if logs_visible {
                     strip.cell(|ui|{
                         ctx.request_repaint_after_secs(15.);
@@ -422,48 +1837,256 @@ impl<'a> eframe::App for App<'a> {
                 self.osc_multiplexer_port_popup = Some(popup);
             }
         }
-        self.popups = core::mem::take(&mut self.popups).into_iter().filter_map(|mut popup|{
+        if let Some(mut popup) = self.dex_param_filter_popup.take() {
+            if popup(self, ctx, frame) {
+                self.dex_param_filter_popup = Some(popup);
+            }
+        }
+        if let Some(mut popup) = self.applied_params_popup.take() {
+            if popup(self, ctx, frame) {
+                self.applied_params_popup = Some(popup);
+            }
+        }
+        if let Some(mut popup) = self.manual_send_popup.take() {
+            if popup(self, ctx, frame) {
+                self.manual_send_popup = Some(popup);
+            }
+        }
+        if let Some(mut popup) = self.unlock_history_popup.take() {
+            if popup(self, ctx, frame) {
+                self.unlock_history_popup = Some(popup);
+            }
+        }
+        let popups = core::mem::take(&mut self.popups).into_iter().filter_map(|mut popup|{
             if popup(self, ctx, frame) {
                 Some(popup)
             }else{
                 None
             }
         }).collect();
+        //Popups can open new Popups (e.g. by reconnecting), which must not be lost.
+        let new_popups = core::mem::replace(&mut self.popups, popups);
+        self.popups.extend(new_popups);
+    }
+
+    /// Makes sure nothing keeps running after the window is closed.
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        #[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
+        self.abort_file_dialogs();
+        if let Some(stop) = self.stop_osc_thread() {
+            if let Err(e) = get_runtime().block_on(stop) {
+                log::error!("Panic whilst stopping the OSC Thread on exit: {e}");
+            }
+        }
     }
 
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
-        eframe::set_value(storage,eframe::APP_KEY, self)
+        if self.persist_unlock_history {
+            eframe::set_value(storage,eframe::APP_KEY, self)
+        } else {
+            //save is also called periodically, so the history must be put back afterwards.
+            let unlock_history = core::mem::take(&mut self.unlock_history);
+            eframe::set_value(storage,eframe::APP_KEY, self);
+            self.unlock_history = unlock_history;
+        }
+    }
+}
+/// Formats all collected log events, one line per event.
+/// Each line contains the timestamp, level, target and message, followed by any other fields.
+#[cfg(all(feature = "file_dialog", not(target_arch = "wasm32")))]
+fn format_logs(collector: &egui_tracing::EventCollector) -> String {
+    use std::fmt::Write;
+    let mut out = String::new();
+    for event in collector.events() {
+        let _ = write!(out, "{} {:>5} {}: {}", event.time, event.level, event.target, event.fields.get("message").map(String::as_str).unwrap_or_default());
+        for (key, value) in event.fields.iter().filter(|(key, _)| **key != "message") {
+            let _ = write!(out, " {key}={value}");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Reveals the Key for an Avatar in the background, see [crate::osc::reveal_key].
+type RevealKeyThread = tokio::task::JoinHandle<Result<Vec<(String, crate::osc::KeyNumber)>, crate::osc::RevealKeyError>>;
+
+/// Creates the popup for sending a single OSC Message to the Send Port.
+/// While connected, the Message is sent with the sender of the OSC Thread and can also be forwarded to the Multiplexer.
+fn manual_send_popup<'a>() -> Box<PopupFunc<'a>> {
+    let mut address = "/avatar/parameters/".to_string();
+    let mut kind = OscValueKind::default();
+    let mut value = String::new();
+    let mut send_thread: Option<tokio::task::JoinHandle<Result<usize, String>>> = None;
+    let mut result: Option<Result<usize, String>> = None;
+    let mut to_multiplexer = false;
+    popup_creator_collapsible("Send a OSC Message:", true, move |app, ui|{
+        ui.horizontal(|ui|{
+            ui.label("Address:");
+            ui.text_edit_singleline(&mut address);
+        });
+        ui.horizontal(|ui|{
+            egui::ComboBox::from_label("Value:")
+                .selected_text(kind.to_string())
+                .show_ui(ui, |ui|{
+                    for value_kind in OscValueKind::ALL {
+                        ui.selectable_value(&mut kind, value_kind, value_kind.to_string());
+                    }
+                });
+            ui.text_edit_singleline(&mut value);
+        });
+        let parsed = crate::osc::parse_osc_value(kind, &value);
+        match &parsed {
+            Ok(arg) => {ui.label(format!("Sends: {}", crate::osc::format_osc_value(arg)));},
+            Err(e) => {ui.colored_label(egui::Color32::RED, e.to_string());},
+        }
+        if !address.starts_with('/') {
+            ui.colored_label(egui::Color32::RED, "The Address has to start with a '/'.");
+        }
+        let handles = app.osc_handles.lock().clone();
+        let has_multiplexer = handles.as_ref().is_some_and(|handles| handles.multiplexer.is_some());
+        ui.add_enabled(has_multiplexer, egui::Checkbox::new(&mut to_multiplexer, "Also forward to the Multiplexer"))
+            .on_disabled_hover_text("Only available while connected with the Multiplexer enabled.");
+        if let Some(thread) = send_thread.take() {
+            if thread.is_finished() {
+                result = Some(get_runtime().block_on(thread).unwrap_or_else(|e| Err(e.to_string())));
+            } else {
+                send_thread = Some(thread);
+                ui.ctx().request_repaint_after(std::time::Duration::from_millis(100));
+            }
+        }
+        let send = ui.add_enabled(parsed.is_ok() && address.starts_with('/') && send_thread.is_none(), egui::Button::new("Send"));
+        if let (true, Ok(arg)) = (send.clicked(), parsed) {
+            match OscCreateData::try_from(&*app) {
+                Ok(data) => {
+                    let send_addr = data.send_addr();
+                    let packet = rosc::OscPacket::Message(rosc::OscMessage{
+                        addr: address.clone(),
+                        args: vec![arg],
+                    });
+                    let (sender, multiplexer) = match handles {
+                        Some(handles) => (handles.sender, handles.multiplexer.filter(|_| to_multiplexer)),
+                        None => (None, None),
+                    };
+                    let target = sender.as_ref().and_then(|sender| sender.peer_addr().ok()).unwrap_or(send_addr);
+                    log::info!("Sending a OSC Message from the Ui to {target}: {packet:?}");
+                    result = None;
+                    send_thread = Some(get_runtime().spawn(async move {
+                        let sender = match sender {
+                            Some(sender) => sender,
+                            None => std::sync::Arc::new(crate::osc::OscSender::from_addr(send_addr).await.map_err(|e| e.to_string())?),
+                        };
+                        let sent = sender.send_with_timeout(&packet, MANUAL_SEND_TIMEOUT).await.map_err(|e| e.to_string())?;
+                        if let Some(multiplexer) = multiplexer {
+                            multiplexer.forward(&packet).await;
+                        }
+                        Ok(sent)
+                    }));
+                }
+                Err(e) => app.invalid_ip_popup(&e),
+            }
+        }
+        match &result {
+            Some(Ok(bytes)) => {ui.label(format!("Sent {bytes} bytes."));},
+            Some(Err(e)) => {ui.colored_label(egui::Color32::RED, format!("Failed to send: {e}"));},
+            None => {},
+        }
+    })
+}
+
+/// Renders the parameters sent for the last Key and whether VRChat confirmed them.
+fn unlock_history_ui(app: &mut App, ui: &mut egui::Ui) {
+    ui.horizontal(|ui|{
+        ui.label("Keep the last");
+        if ui.add(egui::DragValue::new(&mut app.unlock_history_len).range(1..=10_000)).changed() {
+            app.unlock_history.truncate(app.unlock_history_len);
+        }
+        ui.label("unlock attempts");
+    });
+    ui.checkbox(&mut app.persist_unlock_history, "Keep the Unlock History after a restart");
+    if ui.add_enabled(!app.unlock_history.is_empty(), egui::Button::new("Clear")).clicked() {
+        app.unlock_history.clear();
+    }
+    if app.unlock_history.is_empty() {
+        ui.label("No Avatar was attempted to be unlocked yet.");
+        return;
     }
+    egui::ScrollArea::vertical().max_height(300.).show(ui, |ui|{
+        egui::Grid::new("unlock_history").striped(true).show(ui, |ui|{
+            ui.strong("Time");
+            ui.strong("Avatar");
+            ui.strong("Outcome");
+            ui.end_row();
+            for entry in &app.unlock_history {
+                ui.label(entry.time.format("%Y-%m-%d %H:%M:%S").to_string());
+                ui.label(&entry.id);
+                ui.colored_label(unlock_outcome_color(&entry.outcome, ui), entry.outcome.to_string());
+                ui.end_row();
+            }
+        });
+    });
 }
-type PopupFunc<'a> = dyn FnMut(&'_ mut App,&'_ egui::Context, &'_ mut eframe::Frame) -> bool + 'a;
 
-fn get_id() -> u64 {
-    static ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-    ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+fn unlock_outcome_color(outcome: &UnlockOutcome, ui: &egui::Ui) -> egui::Color32 {
+    match outcome {
+        UnlockOutcome::Applied { failed: 0, .. } => egui::Color32::GREEN,
+        UnlockOutcome::Applied { .. } => egui::Color32::YELLOW,
+        UnlockOutcome::NoKey => ui.visuals().text_color(),
+        _ => egui::Color32::RED,
+    }
 }
 
-fn popup_creator<'a>(
-    title: impl Into<egui::WidgetText> + 'a,
-    add_content: impl FnMut(&mut App, &mut egui::Ui) + 'a,
-) -> Box<PopupFunc<'a>> {
-    popup_creator_collapsible(title, false, add_content)
+fn applied_params_ui(app: &mut App, ui: &mut egui::Ui) {
+    let applied_params = app.applied_params.lock();
+    let applied = match &*applied_params {
+        None => {
+            ui.label("No Key has been applied since the last Avatar change.");
+            return;
+        }
+        Some(applied) => applied,
+    };
+    ui.label(format!("Avatar: {}", applied.id));
+    let mut pending = false;
+    egui::ScrollArea::vertical().max_height(300.).show(ui, |ui|{
+        egui::Grid::new("applied_params").striped(true).show(ui, |ui|{
+            ui.strong("Parameter");
+            ui.strong("Value");
+            ui.strong("Status");
+            ui.end_row();
+            for (name, (value, state)) in &applied.params {
+                ui.label(name);
+                ui.label(value.to_string());
+                match state {
+                    ParamState::Confirmed => ui.colored_label(egui::Color32::GREEN, "Confirmed"),
+                    ParamState::Mismatched => ui.colored_label(egui::Color32::RED, "Different value received"),
+                    ParamState::SendFailed => ui.colored_label(egui::Color32::RED, "Failed to send"),
+                    ParamState::Pending => {
+                        pending = true;
+                        ui.label(format!("Pending for {:.1}s", applied.sent.elapsed().as_secs_f32()))
+                    }
+                };
+                ui.end_row();
+            }
+        });
+    });
+    if pending {
+        ui.ctx().request_repaint_after(std::time::Duration::from_millis(100));
+    }
 }
 
-fn popup_creator_collapsible<'a>(
-    title: impl Into<egui::WidgetText> + 'a,
-    collapsible: bool,
-    mut add_content: impl FnMut(&mut App, &mut egui::Ui) + 'a,
-) -> Box<PopupFunc<'a>> {
-    let title = title.into();
-    let id = get_id();
-    let mut open = true;
-    Box::new(move |app:&'_ mut App,ctx: &'_ egui::Context, _: &'_ mut eframe::Frame| {
-        egui::Window::new(title.clone())
-            .resizable(false)
-            .collapsible(collapsible)
-            .open(&mut open)
-            .id(egui::Id::new(id))
-            .show(ctx, |ui|add_content(app,ui));
-        open
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_message_size(max_message_size: usize) -> usize {
+        let app = App { max_message_size, ..App::default() };
+        OscCreateData::try_from(&app).unwrap().max_message_size
+    }
+
+    #[test]
+    fn too_small_max_message_size_is_clamped() {
+        assert_eq!(max_message_size(1), osc_handler::OSC_MIN_MESSAGE_SIZE);
+        assert_eq!(max_message_size(osc_handler::OSC_MIN_MESSAGE_SIZE - 1), osc_handler::OSC_MIN_MESSAGE_SIZE);
+        assert_eq!(max_message_size(osc_handler::OSC_MIN_MESSAGE_SIZE), osc_handler::OSC_MIN_MESSAGE_SIZE);
+        assert_eq!(max_message_size(65536), 65536);
+    }
 }
@@ -0,0 +1,75 @@
+//! The popup windows of the App.
+//!
+//! A popup is a closure, that renders its window every frame and returns, whether it is still open.
+use super::App;
+
+pub(super) type PopupFunc<'a> = dyn FnMut(&'_ mut App,&'_ egui::Context, &'_ mut eframe::Frame) -> bool + 'a;
+
+fn get_id() -> u64 {
+    static ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+}
+
+/// Creates a popup, that shows `add_content` until it is closed.
+pub(super) fn popup_creator<'a>(
+    title: impl Into<egui::WidgetText> + 'a,
+    add_content: impl FnMut(&mut App, &mut egui::Ui) + 'a,
+) -> Box<PopupFunc<'a>> {
+    popup_creator_collapsible(title, false, add_content)
+}
+
+/// Like [popup_creator], but the popup can be collapsed, if `collapsible` is true.
+pub(super) fn popup_creator_collapsible<'a>(
+    title: impl Into<egui::WidgetText> + 'a,
+    collapsible: bool,
+    mut add_content: impl FnMut(&mut App, &mut egui::Ui) + 'a,
+) -> Box<PopupFunc<'a>> {
+    let title = title.into();
+    let id = get_id();
+    let mut open = true;
+    Box::new(move |app:&'_ mut App,ctx: &'_ egui::Context, _: &'_ mut eframe::Frame| {
+        egui::Window::new(title.clone())
+            .resizable(false)
+            .collapsible(collapsible)
+            .open(&mut open)
+            .id(egui::Id::new(id))
+            .show(ctx, |ui|add_content(app,ui));
+        open
+    })
+}
+
+/// Creates a popup, that asks for confirmation.
+/// `on_confirm` is called, if the user clicks the `confirm` button. The popup closes on either button.
+pub(super) fn confirm_popup_creator<'a>(
+    title: impl Into<egui::WidgetText> + 'a,
+    label: impl Into<egui::WidgetText> + 'a,
+    confirm: impl Into<egui::WidgetText> + 'a,
+    mut on_confirm: impl FnMut(&mut App) + 'a,
+) -> Box<PopupFunc<'a>> {
+    let title = title.into();
+    let label = label.into();
+    let confirm = confirm.into();
+    let id = get_id();
+    let mut open = true;
+    let mut decided = false;
+    Box::new(move |app:&'_ mut App,ctx: &'_ egui::Context, _: &'_ mut eframe::Frame| {
+        egui::Window::new(title.clone())
+            .resizable(false)
+            .collapsible(false)
+            .open(&mut open)
+            .id(egui::Id::new(id))
+            .show(ctx, |ui|{
+                ui.label(label.clone());
+                ui.horizontal(|ui|{
+                    if ui.button(confirm.clone()).clicked() {
+                        on_confirm(app);
+                        decided = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        decided = true;
+                    }
+                });
+            });
+        open && !decided
+    })
+}
@@ -0,0 +1,536 @@
+//! Subcommands, that run without the Ui.
+//!
+//! The application is built for the windows subsystem, so on Windows the output only shows up, if it is redirected (e.g. `> out.txt`).
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing_subscriber::filter::LevelFilter;
+use crate::osc::{KeyParseError, OscCreateData, ParamFilterMode, RewriteRule};
+
+const USAGE: &str = "Usage:
+    dex_protect_osc_rs                                  Starts the Ui.
+    dex_protect_osc_rs decrypt <file>                   Decrypts a Key file and prints its parameters.
+    dex_protect_osc_rs headless [<options>] [<folder>]  Runs DexProtect with the given Keys Folder, until Ctrl-C (or SIGTERM) is received.
+
+Options of headless (everything else uses the default settings):
+    --recv-ip <ip>          The IP to receive OSC on (IPv6 link-local addresses need a scope id, e.g. fe80::1%3).
+    --send-ip <ip>          The IP to send OSC to. Defaults to the Receive IP.
+    --recv-port <port>      The OSC Receive Port.
+    --send-port <port>      The OSC Send Port.
+    --forward-port <port>   Forwards everything received to this port (Osc Multiplexer). Can be given multiple times.
+    --parse-packets         Only forwards Packets, that can be parsed.
+    --forward-dex           Also forwards DexProtect's Messages.
+    --no-dex                Disables DexProtect, so that only the Multiplexer runs.
+    --bundles               Sends the Key as an Osc Bundle.
+    --bundle-delay <ms>     Delays applying the Osc Bundle.
+    --default-key <name>    The Key used for Avatars without their own Key.
+    --trigger <param>       Only unlocks, once this parameter is set to true.
+    --key-mapping <file>    Maps Avatar id patterns to Keys.
+    --manifest <file>       A Key Manifest with the Keys of many Avatars.
+    --send-spacing <ms>     Waits this long between sending the parameters of a Key.
+    --send-target <addr>    An IP and Port, that only DexProtect sends to.
+    --coalesce              Deduplicates parameter sends to the same address within a short window.
+    --only-deltas           Only sends parameters, that VRChat doesn't already have.
+    --no-skip-duplicates    Unlocks the Avatar again on every Avatar change, even if it was just unlocked.
+    --allow-equal-ports     Allows the Send Port to be the Receive Port on the same IP, e.g. for a loopback test.
+    --max-message-size <bytes>  The largest OSC Message, that can be received.
+    --heartbeat <s>         How often the receive loop logs a heartbeat. 0 disables it.
+    --max-in-flight <n>     How many sends may be in flight at the same time. 0 means no limit.
+    --record <file>         Records every received datagram into this file.
+    --bad-packet-dir <dir>  Writes undecodable data into this directory.
+    --bind-retries <n>      How often binding the Receive Port is retried, if it is in use.
+    --send-bind-port <port> The local port DexProtect sends from.
+    --bind-interface <name> The network interface to bind to (only on Linux).
+    --metrics-addr <addr>   The IP and Port of the metrics endpoint.
+    --reassert <s>          Sends the parameters of the last Key again in this interval.
+    --lenient-avatar-change Uses the first String of Avatar changes with additional arguments.
+    --change-addr <addr>    The OSC address, on which Avatar changes are received.
+    --param-prefix <prefix> The OSC address prefix of Avatar parameters.
+    --high-precision        Sends Key values as 64-bit Doubles.
+    --param-filter <name>   Never sets this parameter. Can be given multiple times.
+    --param-filter-allow    Only sets the parameters of --param-filter instead.
+    --rewrite <port>:<from>=<to>  Rewrites the address <from> forwarded to <port>. Can be given multiple times.";
+
+/// How long the OSC Tasks get to finish in-flight unlocks after a shutdown signal, before they are aborted.
+const HEADLESS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs the subcommand in `args` (without the program name).
+/// Returns `None`, if there is no subcommand, so that the Ui should be started.
+pub(crate) fn run(args: &[String]) -> Option<ExitCode> {
+    let (command, args) = args.split_first()?;
+    Some(match command.as_str() {
+        "decrypt" => match args {
+            [path] => decrypt(path),
+            _ => usage_error("decrypt needs exactly one Key file."),
+        },
+        "headless" => match parse_headless_args(args) {
+            Ok(osc_create_data) => headless(osc_create_data),
+            Err(e) => usage_error(&e),
+        },
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            ExitCode::SUCCESS
+        }
+        command => usage_error(&format!("Unknown subcommand '{command}'.")),
+    })
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("{message}\n{USAGE}");
+    ExitCode::from(2)
+}
+
+/// Decrypts, decodes and parses a Key file like an unlock would, and prints the parameters in the order of the Key.
+/// Nothing is sent.
+fn decrypt(path: &str) -> ExitCode {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Failed to read the Key file '{path}': {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let (params, error) = match crate::osc::parse_key_file(bytes) {
+        Ok(params) => (params, None),
+        Err(KeyParseError::UnevenEntries(params)) => (params, Some("The Key has an uneven amount of entries. The last value has no name, which suggests corruption.".to_string())),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    let width = params.iter().map(|(name, _)| name.len()).max().unwrap_or_default();
+    for (name, value) in &params {
+        println!("{name:width$}  {value}");
+    }
+    match error {
+        None => {
+            println!("The Key '{path}' has {} parameters.", params.len());
+            ExitCode::SUCCESS
+        }
+        Some(error) => {
+            eprintln!("The Key '{path}' is invalid: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parses the options of the headless subcommand (see [USAGE]) into the settings to run with.
+fn parse_headless_args(args: &[String]) -> Result<OscCreateData, String> {
+    fn parse<T: FromStr>(flag: &str, text: &str) -> Result<T, String> where T::Err: Display {
+        text.parse().map_err(|e| format!("The value '{text}' of {flag} is invalid: {e}"))
+    }
+    let mut data = OscCreateData::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = arg.as_str();
+        let mut value = || args.next().map(String::as_str).ok_or_else(|| format!("{flag} needs a value."));
+        match flag {
+            "--recv-ip" => (data.recv_ip, data.recv_scope_id) = crate::osc::parse_ip("Receive IP", value()?).map_err(|e| e.to_string())?,
+            "--send-ip" => {
+                let (send_ip, send_scope_id) = crate::osc::parse_ip("Send IP", value()?).map_err(|e| e.to_string())?;
+                data.send_ip = Some(send_ip);
+                data.send_scope_id = send_scope_id;
+            }
+            "--recv-port" => data.recv_port = parse(flag, value()?)?,
+            "--send-port" => data.send_port = parse(flag, value()?)?,
+            "--forward-port" => data.osc_multiplexer_rev_port.push(parse(flag, value()?)?),
+            "--parse-packets" => data.osc_multiplexer_parse_packets = true,
+            "--forward-dex" => data.dex_forward_to_multiplexer = true,
+            "--no-dex" => data.dex_protect_enabled = false,
+            "--bundles" => data.dex_use_bundles = true,
+            "--bundle-delay" => data.dex_bundle_delay_ms = parse(flag, value()?)?,
+            "--default-key" => {
+                data.dex_use_default_key = true;
+                data.dex_default_key_name = value()?.to_string();
+            }
+            "--trigger" => data.dex_trigger_param = Some(value()?.to_string()),
+            "--key-mapping" => data.dex_key_mapping_path = Some(PathBuf::from(value()?)),
+            "--manifest" => data.dex_manifest_path = Some(PathBuf::from(value()?)),
+            "--send-spacing" => data.dex_send_spacing_ms = parse(flag, value()?)?,
+            "--send-target" => data.dex_send_target = Some(parse(flag, value()?)?),
+            "--coalesce" => data.dex_coalesce_sends = true,
+            "--only-deltas" => data.dex_only_send_deltas = true,
+            "--no-skip-duplicates" => data.dex_skip_duplicate_changes = false,
+            "--allow-equal-ports" => data.allow_equal_ports = true,
+            "--max-message-size" => {
+                data.max_message_size = parse(flag, value()?)?;
+                if data.max_message_size < osc_handler::OSC_MIN_MESSAGE_SIZE {
+                    return Err(format!("{flag} must be at least {} bytes.", osc_handler::OSC_MIN_MESSAGE_SIZE));
+                }
+            }
+            "--heartbeat" => data.heartbeat_interval_secs = parse(flag, value()?)?,
+            "--max-in-flight" => data.max_in_flight_sends = parse(flag, value()?)?,
+            "--record" => data.record_path = Some(PathBuf::from(value()?)),
+            "--bad-packet-dir" => data.bad_packet_dir = Some(PathBuf::from(value()?)),
+            "--bind-retries" => data.recv_bind_retries = parse(flag, value()?)?,
+            "--send-bind-port" => data.osc_send_bind_port = parse(flag, value()?)?,
+            "--bind-interface" => data.osc_bind_interface = Some(value()?.to_string()),
+            "--metrics-addr" => data.metrics_addr = Some(parse(flag, value()?)?),
+            "--reassert" => data.dex_reassert_interval_secs = parse(flag, value()?)?,
+            "--lenient-avatar-change" => data.dex_lenient_avatar_change = true,
+            "--change-addr" => data.dex_change_addr = value()?.to_string(),
+            "--param-prefix" => data.dex_param_prefix = value()?.to_string(),
+            "--high-precision" => data.dex_high_precision = true,
+            "--param-filter" => data.dex_param_filter.push(value()?.to_string()),
+            "--param-filter-allow" => data.dex_param_filter_mode = ParamFilterMode::Allow,
+            "--rewrite" => {
+                let rule = value()?;
+                let (port, from, to) = rule.split_once(':')
+                    .and_then(|(port, rewrite)| rewrite.split_once('=').map(|(from, to)| (port, from, to)))
+                    .ok_or_else(|| format!("The value '{rule}' of {flag} is not of the form <port>:<from>=<to>."))?;
+                data.osc_multiplexer_rewrite_rules.push(RewriteRule { port: parse(flag, port)?, from: from.to_string(), to: to.to_string() });
+            }
+            path if !path.starts_with("--") && data.path.as_os_str().is_empty() => data.path = PathBuf::from(path),
+            other => return Err(format!("Unknown or repeated argument '{other}' for headless.")),
+        }
+    }
+    if let Some(send_ip) = data.send_ip {
+        if send_ip.is_ipv4() != data.recv_ip.is_ipv4() {
+            return Err(crate::osc::OscConfigError::MixedFamilies { recv_ip: data.recv_ip, send_ip }.to_string());
+        }
+    }
+    data.check_ports().map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+/// Returns the command line for running the headless subcommand with `data`.
+/// Only settings, that differ from the default, are included.
+/// Arguments are quoted for a POSIX shell (see [shell_quote]).
+pub(crate) fn headless_command(data: &OscCreateData) -> String {
+    fn ip(ip: IpAddr, scope_id: u32) -> String {
+        match scope_id {
+            0 => ip.to_string(),
+            scope_id => format!("{ip}%{scope_id}"),
+        }
+    }
+    let default = OscCreateData::default();
+    let mut args = vec!["dex_protect_osc_rs".to_string(), "headless".to_string()];
+    let mut option = |flag: &str, value: Option<String>| {
+        args.push(flag.to_string());
+        args.extend(value);
+    };
+    if data.recv_ip != default.recv_ip || data.recv_scope_id != default.recv_scope_id {
+        option("--recv-ip", Some(ip(data.recv_ip, data.recv_scope_id)));
+    }
+    if let Some(send_ip) = data.send_ip {
+        option("--send-ip", Some(ip(send_ip, data.send_scope_id)));
+    }
+    if data.recv_port != default.recv_port {
+        option("--recv-port", Some(data.recv_port.to_string()));
+    }
+    if data.send_port != default.send_port {
+        option("--send-port", Some(data.send_port.to_string()));
+    }
+    for port in &data.osc_multiplexer_rev_port {
+        option("--forward-port", Some(port.to_string()));
+    }
+    if data.osc_multiplexer_parse_packets {
+        option("--parse-packets", None);
+    }
+    if data.dex_forward_to_multiplexer {
+        option("--forward-dex", None);
+    }
+    if !data.dex_protect_enabled {
+        option("--no-dex", None);
+    }
+    if data.dex_use_bundles {
+        option("--bundles", None);
+    }
+    if data.dex_bundle_delay_ms != default.dex_bundle_delay_ms {
+        option("--bundle-delay", Some(data.dex_bundle_delay_ms.to_string()));
+    }
+    if data.dex_use_default_key {
+        option("--default-key", Some(data.dex_default_key_name.clone()));
+    }
+    if let Some(trigger) = &data.dex_trigger_param {
+        option("--trigger", Some(trigger.clone()));
+    }
+    if let Some(path) = &data.dex_key_mapping_path {
+        option("--key-mapping", Some(path.display().to_string()));
+    }
+    if let Some(path) = &data.dex_manifest_path {
+        option("--manifest", Some(path.display().to_string()));
+    }
+    if data.dex_send_spacing_ms != default.dex_send_spacing_ms {
+        option("--send-spacing", Some(data.dex_send_spacing_ms.to_string()));
+    }
+    if let Some(target) = data.dex_send_target {
+        option("--send-target", Some(target.to_string()));
+    }
+    if data.dex_coalesce_sends {
+        option("--coalesce", None);
+    }
+    if data.dex_only_send_deltas {
+        option("--only-deltas", None);
+    }
+    if !data.dex_skip_duplicate_changes {
+        option("--no-skip-duplicates", None);
+    }
+    if data.allow_equal_ports {
+        option("--allow-equal-ports", None);
+    }
+    if data.max_message_size != default.max_message_size {
+        option("--max-message-size", Some(data.max_message_size.to_string()));
+    }
+    if data.heartbeat_interval_secs != default.heartbeat_interval_secs {
+        option("--heartbeat", Some(data.heartbeat_interval_secs.to_string()));
+    }
+    if data.max_in_flight_sends != default.max_in_flight_sends {
+        option("--max-in-flight", Some(data.max_in_flight_sends.to_string()));
+    }
+    if let Some(path) = &data.record_path {
+        option("--record", Some(path.display().to_string()));
+    }
+    if let Some(path) = &data.bad_packet_dir {
+        option("--bad-packet-dir", Some(path.display().to_string()));
+    }
+    if data.recv_bind_retries != default.recv_bind_retries {
+        option("--bind-retries", Some(data.recv_bind_retries.to_string()));
+    }
+    if data.osc_send_bind_port != default.osc_send_bind_port {
+        option("--send-bind-port", Some(data.osc_send_bind_port.to_string()));
+    }
+    if let Some(interface) = &data.osc_bind_interface {
+        option("--bind-interface", Some(interface.clone()));
+    }
+    if let Some(addr) = data.metrics_addr {
+        option("--metrics-addr", Some(addr.to_string()));
+    }
+    if data.dex_reassert_interval_secs != default.dex_reassert_interval_secs {
+        option("--reassert", Some(data.dex_reassert_interval_secs.to_string()));
+    }
+    if data.dex_lenient_avatar_change {
+        option("--lenient-avatar-change", None);
+    }
+    if data.dex_change_addr != default.dex_change_addr {
+        option("--change-addr", Some(data.dex_change_addr.clone()));
+    }
+    if data.dex_param_prefix != default.dex_param_prefix {
+        option("--param-prefix", Some(data.dex_param_prefix.clone()));
+    }
+    if data.dex_high_precision {
+        option("--high-precision", None);
+    }
+    for name in &data.dex_param_filter {
+        option("--param-filter", Some(name.clone()));
+    }
+    if data.dex_param_filter_mode == ParamFilterMode::Allow {
+        option("--param-filter-allow", None);
+    }
+    for rule in &data.osc_multiplexer_rewrite_rules {
+        option("--rewrite", Some(format!("{}:{}={}", rule.port, rule.from, rule.to)));
+    }
+    if !data.path.as_os_str().is_empty() {
+        args.push(data.path.display().to_string());
+    }
+    args.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes `arg` for a POSIX shell, unless it only consists of characters, that no shell treats specially.
+/// Inside single quotes nothing is special, so only single quotes themselves need to be escaped (as `'\''`).
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:@%+,=".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Runs DexProtect without the Ui, until a shutdown signal is received.
+/// On a signal the OSC Tasks are shut down cooperatively, like on a Disconnect in the Ui.
+fn headless(osc_create_data: OscCreateData) -> ExitCode {
+    crate::set_log_level(LevelFilter::INFO);
+    crate::get_runtime().block_on(async move {
+        let (shutdown, shutdown_receiver) = tokio::sync::watch::channel(false);
+        let mut runtime = match crate::osc::create_and_start_osc(
+            &osc_create_data,
+            shutdown_receiver,
+            &crate::osc::OscStatusHandle::default(),
+            &crate::osc::LastUnlockHandle::default(),
+            &crate::osc::LastAvatarHandle::default(),
+            &crate::osc::AppliedParamsHandle::default(),
+            &crate::osc::MultiplexerSwitch::default(),
+            crate::osc::OscNotices::default().sender(),
+            &crate::osc::StatsHandle::default(),
+        ).await {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                log::error!("Failed to start OSC: {e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        log::info!("Running headless with the Keys Folder '{}'. Press Ctrl-C to stop.", osc_create_data.path.display());
+        let signal = shutdown_signal();
+        tokio::pin!(signal);
+        loop {
+            tokio::select! {
+                signal = &mut signal => {
+                    log::info!("Received {signal}. Shutting down.");
+                    break;
+                }
+                task = runtime.join_set.join_next() => match task {
+                    Some(Ok(())) => log::debug!("An OSC Task stopped."),
+                    Some(Err(e)) => {
+                        log::error!("Panic in OSC Thread: {e}");
+                        return ExitCode::FAILURE;
+                    }
+                    None => {
+                        log::error!("All OSC Tasks stopped unexpectedly.");
+                        return ExitCode::FAILURE;
+                    }
+                },
+            }
+        }
+        let _ = shutdown.send(true);
+        let drain = async {
+            while let Some(task) = runtime.join_set.join_next().await {
+                if let Err(e) = task {
+                    log::error!("Panic in OSC Thread while shutting down: {e}");
+                }
+            }
+        };
+        match tokio::time::timeout(HEADLESS_SHUTDOWN_TIMEOUT, drain).await {
+            Ok(()) => log::info!("All OSC Tasks stopped."),
+            Err(_) => {
+                log::warn!("The OSC Tasks did not stop within {}ms. Aborting them.", HEADLESS_SHUTDOWN_TIMEOUT.as_millis());
+                runtime.join_set.shutdown().await;
+            }
+        }
+        ExitCode::SUCCESS
+    })
+}
+
+/// Waits for Ctrl-C, or SIGTERM on Unix. Returns the name of the signal.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            tokio::select! {
+                _ = sigterm.recv() => return "SIGTERM",
+                result = tokio::signal::ctrl_c() => if let Err(e) = result {
+                    log::error!("Failed to listen for Ctrl-C: {e}");
+                },
+            }
+            return "Ctrl-C";
+        }
+        Err(e) => log::warn!("Failed to listen for SIGTERM. Only Ctrl-C stops DexProtect cleanly: {e}"),
+    }
+    //Without a signal there would be no way to stop cleanly, so a failure to listen stops right away.
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Failed to listen for Ctrl-C: {e}");
+    }
+    "Ctrl-C"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv6Addr};
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn bracketed_and_scoped_ipv6() {
+        let data = parse_headless_args(&args(&["--recv-ip", "[::1]", "--send-ip", "fe80::1%3"])).unwrap();
+        assert_eq!(data.recv_ip, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(data.recv_scope_id, 0);
+        assert_eq!(data.send_ip, Some("fe80::1".parse().unwrap()));
+        assert_eq!(data.send_scope_id, 3);
+    }
+
+    /// Splits a command line from [headless_command] into its arguments, like a POSIX shell would.
+    /// Only the quoting [shell_quote] produces is supported.
+    fn split_command(command: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut arg = None::<String>;
+        let mut chars = command.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' => args.extend(arg.take()),
+                '\'' => {
+                    let arg = arg.get_or_insert_with(String::new);
+                    arg.extend(chars.by_ref().take_while(|&c| c != '\''));
+                }
+                '\\' => arg.get_or_insert_with(String::new).extend(chars.next()),
+                c => arg.get_or_insert_with(String::new).push(c),
+            }
+        }
+        args.extend(arg);
+        args
+    }
+
+    #[test]
+    fn special_characters_are_quoted() {
+        for arg in ["", "a b", "\"quoted\"", "$HOME", "trailing\\", "it's", "*", "a\nb", "plain-text_1.0"] {
+            assert_eq!(split_command(&shell_quote(arg)), [arg], "{}", shell_quote(arg));
+        }
+        assert_eq!(shell_quote("/avatar/parameters/"), "/avatar/parameters/");
+    }
+
+    #[test]
+    fn headless_command_round_trips() {
+        let default = OscCreateData::default();
+        let data = OscCreateData {
+            recv_ip: "fe80::1".parse().unwrap(),
+            recv_scope_id: 3,
+            send_ip: Some("fe80::2".parse().unwrap()),
+            send_scope_id: 4,
+            recv_port: 9100,
+            send_port: 9101,
+            max_message_size: 4096,
+            heartbeat_interval_secs: 0,
+            max_in_flight_sends: 16,
+            record_path: Some(PathBuf::from("rec ording.bin")),
+            bad_packet_dir: Some(PathBuf::from("bad $packets")),
+            recv_bind_retries: 7,
+            allow_equal_ports: true,
+            osc_send_bind_port: 9200,
+            osc_bind_interface: Some("eth0".to_string()),
+            metrics_addr: Some("127.0.0.1:9300".parse().unwrap()),
+            dex_protect_enabled: false,
+            dex_use_bundles: true,
+            dex_use_default_key: true,
+            dex_default_key_name: "it's the \"default\"".to_string(),
+            dex_key_mapping_path: Some(PathBuf::from("mapping.txt")),
+            dex_trigger_param: Some("/avatar/parameters/Unlock".to_string()),
+            dex_manifest_path: Some(PathBuf::from("C:\\Keys\\")),
+            dex_bundle_delay_ms: 10,
+            dex_send_spacing_ms: 20,
+            dex_send_target: Some("127.0.0.1:9400".parse().unwrap()),
+            dex_reassert_interval_secs: 30,
+            dex_coalesce_sends: true,
+            dex_only_send_deltas: true,
+            dex_lenient_avatar_change: true,
+            dex_skip_duplicate_changes: false,
+            dex_change_addr: "/avatar/changed".to_string(),
+            dex_param_prefix: "/params/".to_string(),
+            dex_high_precision: true,
+            dex_forward_to_multiplexer: true,
+            dex_param_filter_mode: ParamFilterMode::Allow,
+            dex_param_filter: vec!["a".to_string(), "b c".to_string()],
+            path: PathBuf::from("My Keys"),
+            osc_multiplexer_rev_port: vec![9500, 9501],
+            osc_multiplexer_parse_packets: true,
+            osc_multiplexer_rewrite_rules: vec![RewriteRule { port: 9500, from: "/a/*".to_string(), to: "/b/*".to_string() }],
+        };
+        for data in [default, data] {
+            let command = headless_command(&data);
+            let args = split_command(&command);
+            assert_eq!(args[..2], ["dex_protect_osc_rs", "headless"], "{command}");
+            let parsed = parse_headless_args(&args[2..]).unwrap();
+            assert_eq!(format!("{parsed:?}"), format!("{data:?}"), "{command}");
+        }
+    }
+
+    #[test]
+    fn mixed_ip_versions_are_rejected() {
+        let error = parse_headless_args(&args(&["--recv-ip", "::1", "--send-ip", "127.0.0.1"])).unwrap_err();
+        assert!(error.contains("different IP versions"), "{error}");
+        assert!(parse_headless_args(&args(&["--recv-ip", "127.0.0.1", "--send-ip", "::1"])).is_err());
+    }
+}
@@ -2,13 +2,20 @@
 #![deny(clippy::expect_used)]
 #![windows_subsystem = "windows"]
 
+use std::process::ExitCode;
 use std::sync::OnceLock;
 use tokio::runtime::{Builder, Runtime};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::Layer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
 mod app;
+mod cli;
+mod metrics;
 pub(crate) mod osc;
+#[cfg(all(feature = "tray", not(target_arch = "wasm32")))]
+mod tray;
 
 static RUNTIME: OnceLock<Runtime> = OnceLock::new();
 fn get_runtime() -> &'static Runtime {
@@ -21,10 +28,25 @@ fn get_runtime() -> &'static Runtime {
     })
 }
 
-fn main() {
+static LOG_LEVEL_SETTER: OnceLock<Box<dyn Fn(LevelFilter) + Send + Sync>> = OnceLock::new();
+/// Changes the level of log messages printed to the console at runtime.
+/// The in-app Logs still collect every message, so they can be saved regardless of this setting.
+pub(crate) fn set_log_level(level: LevelFilter) {
+    if let Some(setter) = LOG_LEVEL_SETTER.get() {
+        setter(level);
+    }
+}
+
+fn main() -> ExitCode {
     let collector = egui_tracing::EventCollector::new();
+    let (level_filter, level_filter_handle) = tracing_subscriber::reload::Layer::new(LevelFilter::TRACE);
+    let _ = LOG_LEVEL_SETTER.set(Box::new(move |level|{
+        if let Err(e) = level_filter_handle.reload(level) {
+            eprintln!("Failed to change the log level to {level}: {e}");
+        }
+    }));
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().pretty())
+        .with(tracing_subscriber::fmt::layer().pretty().with_filter(level_filter))
         .with(tracing_subscriber::filter::filter_fn(|event|{
             if let Some(module) = event.module_path(){
                 let mut bool = *event.level() == tracing_core::Level::TRACE && (module.starts_with("egui") || module.starts_with("eframe"));
@@ -36,13 +58,33 @@ fn main() {
         }))
         .with(collector.clone())
         .init();
+    #[cfg(feature = "user_decryption_key")]
+    osc::load_user_decryption_key_from_env();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        //Subcommands print their results to the console, so only problems are logged.
+        set_log_level(LevelFilter::WARN);
+    }
+    if let Some(exit_code) = cli::run(&args) {
+        return exit_code;
+    }
     log::info!("Logger initialized");
     let rt = get_runtime();
     let _a = rt.enter(); // "_" as a variable name immediately drops the value, causing no tokio runtime to be registered. "_a" does not.
     log::info!("Tokio Runtime initialized");
+    let native_options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_min_inner_size([400., 300.]),
+        // eframe stores the window size and position next to the App state (see App::save)
+        // and restores them on startup. Positions on monitors, that are no longer connected, are clamped back onto a visible one.
+        persist_window: true,
+        // The App applies the theme chosen in the Ui itself, including following the system theme.
+        follow_system_theme: false,
+        ..Default::default()
+    };
     if let Some(err) = eframe::run_native(
         "DexProtectOSC-RS",
-        eframe::NativeOptions::default(),
+        native_options,
         Box::new(|cc| Ok(Box::new(app::App::new(collector, cc)))),
     )
         .err()
@@ -53,4 +95,5 @@ fn main() {
         );
     }
     println!("GUI exited. Thank you for using DexProtectOSC-RS!");
+    ExitCode::SUCCESS
 }
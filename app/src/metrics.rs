@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Counters for the metrics endpoint.
+/// They are always counted, since that is cheap. Only the endpoint needs the `metrics` feature.
+pub(crate) struct Metrics {
+    packets_received: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    avatar_changes: AtomicU64,
+    unlocks_applied: AtomicU64,
+    unlocks_failed: AtomicU64,
+    unlocks_timed_out: AtomicU64,
+    /// The amount of packets forwarded by the Multiplexer per port.
+    forwarded: Mutex<BTreeMap<u16, u64>>,
+}
+
+pub(crate) static METRICS: Metrics = Metrics {
+    packets_received: AtomicU64::new(0),
+    bytes_received: AtomicU64::new(0),
+    bytes_sent: AtomicU64::new(0),
+    avatar_changes: AtomicU64::new(0),
+    unlocks_applied: AtomicU64::new(0),
+    unlocks_failed: AtomicU64::new(0),
+    unlocks_timed_out: AtomicU64::new(0),
+    forwarded: Mutex::new(BTreeMap::new()),
+};
+
+impl Metrics {
+    pub(crate) fn packet_received(&self, bytes: usize) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn avatar_change(&self) {
+        self.avatar_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn unlock_applied(&self) {
+        self.unlocks_applied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn unlock_failed(&self) {
+        self.unlocks_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An applied Key was not confirmed by VRChat in time.
+    pub(crate) fn unlock_timed_out(&self) {
+        self.unlocks_timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn forwarded(&self, port: u16) {
+        if let Ok(mut forwarded) = self.forwarded.lock() {
+            *forwarded.entry(port).or_default() += 1;
+        }
+    }
+
+    /// Renders all counters in the Prometheus text format.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn render(&self) -> String {
+        use std::fmt::Write;
+        let mut out = String::new();
+        let counters = [
+            ("dexprotectosc_packets_received_total", "OSC Packets received on the Receive Port.", &self.packets_received),
+            ("dexprotectosc_bytes_received_total", "Bytes received on the Receive Port.", &self.bytes_received),
+            ("dexprotectosc_bytes_sent_total", "Bytes of OSC Messages sent.", &self.bytes_sent),
+            ("dexprotectosc_avatar_changes_total", "Avatar changes seen.", &self.avatar_changes),
+            ("dexprotectosc_unlocks_applied_total", "Keys sent to VRChat.", &self.unlocks_applied),
+            ("dexprotectosc_unlocks_failed_total", "Keys, that could not be read, decoded or were corrupt.", &self.unlocks_failed),
+            ("dexprotectosc_unlocks_timed_out_total", "Keys, that VRChat did not fully confirm in time.", &self.unlocks_timed_out),
+        ];
+        for (name, help, counter) in counters {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}", counter.load(Ordering::Relaxed));
+        }
+        let _ = writeln!(out, "# HELP dexprotectosc_packets_forwarded_total OSC Packets forwarded by the Multiplexer.\n# TYPE dexprotectosc_packets_forwarded_total counter");
+        if let Ok(forwarded) = self.forwarded.lock() {
+            for (port, count) in forwarded.iter() {
+                let _ = writeln!(out, "dexprotectosc_packets_forwarded_total{{port=\"{port}\"}} {count}");
+            }
+        }
+        out
+    }
+}
+
+/// Counts the packets received on the Receive Port.
+pub(crate) struct MetricsHandler;
+
+impl osc_handler::RawPacketHandler for MetricsHandler {
+    type Fut<'a> = core::future::Ready<()>;
+    type Output<'a> = ();
+
+    fn handle<'a>(&mut self, message: &'a [u8]) -> Self::Fut<'a> {
+        METRICS.packet_received(message.len());
+        core::future::ready(())
+    }
+}
+
+/// How long a client of the metrics endpoint may take to send its request or to receive the response.
+#[cfg(feature = "metrics")]
+const CONNECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Serves the metrics on `GET /metrics` until `shutdown` completes.
+///
+/// This is a minimal HTTP/1.1 server, that answers one request per connection.
+/// Connections, that stall for longer than [CONNECTION_TIMEOUT], are closed.
+#[cfg(feature = "metrics")]
+pub(crate) async fn serve(listener: tokio::net::TcpListener, shutdown: impl std::future::Future<Output = ()>) {
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, CONNECTION_TIMEOUT).await {
+                            log::debug!("Failed to answer the metrics request from {peer}: {e}");
+                        }
+                    });
+                }
+                Err(e) => log::warn!("Failed to accept a connection on the metrics endpoint: {e}"),
+            }
+        }
+    }
+    log::info!("Stopped the metrics endpoint.");
+}
+
+/// Answers a single request. Reading the request and writing the response each fail with [std::io::ErrorKind::TimedOut] after `timeout`.
+#[cfg(feature = "metrics")]
+async fn handle_connection(mut stream: tokio::net::TcpStream, timeout: std::time::Duration) -> std::io::Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    async fn with_timeout<T>(timeout: std::time::Duration, io: impl std::future::Future<Output = std::io::Result<T>>) -> std::io::Result<T> {
+        tokio::time::timeout(timeout, io).await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, format!("No progress within {}s", timeout.as_secs_f32())))?
+    }
+    let mut buf = [0u8; 1024];
+    let read = with_timeout(timeout, stream.read(&mut buf)).await?;
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let mut request_line = request.split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", METRICS.render()),
+        _ => ("404 Not Found", "Not Found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    with_timeout(timeout, async {
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }).await
+}
+
+#[cfg(test)]
+#[cfg(feature = "metrics")]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use crate::osc::test_util::block_on;
+    use super::*;
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn endpoint_serves_every_metric() {
+        block_on(async {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let server = tokio::spawn(serve(listener, async move {
+                let _ = stopped.await;
+            }));
+
+            let response = get(addr, "/metrics").await;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+            for name in [
+                "dexprotectosc_packets_received_total",
+                "dexprotectosc_bytes_received_total",
+                "dexprotectosc_bytes_sent_total",
+                "dexprotectosc_avatar_changes_total",
+                "dexprotectosc_unlocks_applied_total",
+                "dexprotectosc_unlocks_failed_total",
+                "dexprotectosc_unlocks_timed_out_total",
+                "dexprotectosc_packets_forwarded_total",
+            ] {
+                assert!(response.contains(&format!("# TYPE {name} counter")), "{name} is missing in {response}");
+            }
+
+            let response = get(addr, "/").await;
+            assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{response}");
+
+            stop.send(()).unwrap();
+            server.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn silent_clients_time_out() {
+        block_on(async {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let _client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            let error = handle_connection(stream, Duration::from_millis(50)).await.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        });
+    }
+}
@@ -1,143 +1,775 @@
-use std::convert::Infallible;
-use std::future::Future;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
 use std::path::PathBuf;
-use std::pin::Pin;
 use std::sync::Arc;
-use futures::future::Either;
 
 use serde_derive::{Deserialize, Serialize};
-use osc_handler::receiver::OscReceiver;
+use osc_handler::dyn_handler::{DynMessageHandler, DynPacketHandler, DynRawPacketHandler};
+use osc_handler::receiver::OscReceiverBuilder;
 
-pub use sender::OscSender;
+pub use sender::{OscSender, SendTimeoutError, DEFAULT_MAX_IN_FLIGHT_SENDS};
+pub use value::{format_osc_value, parse_osc_value, OscValueKind};
+pub use status::{OscNotice, OscNotices, OscStatus, OscStatusHandle};
+pub use stats::StatsHandle;
+pub(crate) use dex::{count_key_files, keys_folder_error, parse_key_file};
+pub use dex::{key_path, reveal_key, decryption_keys_available, AppliedParams, AppliedParamsHandle, KeyMappingError, KeyNumber, KeyParseError, RevealKeyError, LastAvatarHandle, LastUnlockHandle, ManifestError, ParamState, ParamFilterMode, UnlockOutcome};
+pub use multiplexer::{MultiplexerSwitch, RewriteRule};
+#[cfg(feature = "user_decryption_key")]
+pub use dex::{load_user_decryption_key_from_env, parse_user_decryption_key, set_user_decryption_key, USER_IV_ENV, USER_KEY_ENV};
+pub(crate) use coalescing_sender::COALESCE_WINDOW;
+pub use replay::replay_recording;
+pub use connection_test::{test_connection, ConnectionTestError, CONNECTION_TEST_TIMEOUT};
+pub use interface::INTERFACE_BINDING_SUPPORTED;
 use crate::osc::dex::DexOscHandler;
-use crate::osc::multiplexer::MultiplexerOsc;
 
 mod sender;
+mod coalescing_sender;
 mod dex;
 mod multiplexer;
 mod dex_key;
+mod status;
+mod stats;
+mod connection_test;
+mod value;
+mod bad_packets;
+mod replay;
+mod interface;
+#[cfg(test)]
+pub(crate) mod test_util;
 
 pub const OSC_RECV_PORT:u16 = 9001;
 pub const OSC_SEND_PORT:u16 = 9000;
+/// The OSC address, on which VRChat announces Avatar changes.
+pub const DEFAULT_DEX_CHANGE_ADDR: &str = "/avatar/change";
+/// The OSC address prefix of VRChat's Avatar parameters.
+pub const DEFAULT_DEX_PARAM_PREFIX: &str = "/avatar/parameters/";
 
 #[derive(Debug, Clone,Serialize,Deserialize)]
 #[serde(default)]
 pub struct OscCreateData {
-    pub ip: IpAddr,
+    /// The IP the OSC Receive Socket is bound to.
+    #[serde(alias = "ip")]
+    pub recv_ip: IpAddr,
+    /// The IPv6 scope id of `recv_ip`. This is only needed for link-local addresses.
+    pub recv_scope_id: u32,
+    /// The IP OSC Messages are sent to. `None` means the same as `recv_ip`.
+    pub send_ip: Option<IpAddr>,
+    /// The IPv6 scope id of `send_ip`. This is only needed for link-local addresses.
+    pub send_scope_id: u32,
     pub recv_port:u16,
     pub send_port:u16,
     pub max_message_size: usize,
+    /// How often the receive loop logs a heartbeat in seconds. 0 disables the heartbeat.
+    pub heartbeat_interval_secs: u64,
+    /// How many sends of DexProtect and of each Multiplexer port may be in flight at the same time. 0 means no limit.
+    pub max_in_flight_sends: usize,
+    /// If set, every received datagram is recorded into this file, so that it can be replayed later.
+    pub record_path: Option<PathBuf>,
+    /// If set, undecodable data is written to rotating files in this directory.
+    pub bad_packet_dir: Option<PathBuf>,
+    /// How often binding the Receive Port is retried, if it is in use.
+    pub recv_bind_retries: u32,
+    /// Allows the Send Port to be the Receive Port on the same IP, e.g. for a loopback test.
+    /// Otherwise everything sent would be received again.
+    pub allow_equal_ports: bool,
+    /// The local port DexProtect sends from. 0 lets the OS pick a random port.
+    pub osc_send_bind_port: u16,
+    /// The name of the network interface the receive and DexProtect send sockets are bound to.
+    /// This is only supported on Linux. On other platforms only the IP is bound.
+    pub osc_bind_interface: Option<String>,
+    /// The address of the metrics endpoint. It is only started with the `metrics` feature.
+    pub metrics_addr: Option<SocketAddr>,
     pub dex_protect_enabled:bool,
     pub dex_use_bundles: bool,
+    pub dex_use_default_key: bool,
+    /// The name of the Key file (without the `.key` extension) used for Avatars without their own Key, if `dex_use_default_key` is set.
+    pub dex_default_key_name: String,
+    /// If set, Avatar id patterns in this file are mapped to Keys, before looking up the Key for the id itself.
+    pub dex_key_mapping_path: Option<PathBuf>,
+    /// If set, Avatar changes don't unlock the Avatar anymore.
+    /// Instead the last Avatar is unlocked, once a truthy value is received on this OSC address.
+    pub dex_trigger_param: Option<String>,
+    /// If set, all Keys are read from this Key Manifest once on Connect, instead of from one Key file per Avatar in `path`.
+    pub dex_manifest_path: Option<PathBuf>,
+    pub dex_bundle_delay_ms: u64,
+    /// The delay between the single parameter sends, if neither bundles nor coalescing are used.
+    /// 0 sends all parameters at once. A delay makes unlocking slower, but helps setups, that drop parameters sent too quickly.
+    pub dex_send_spacing_ms: u64,
+    /// Where DexProtect sends the Key parameters to. `None` sends to [OscCreateData::send_addr] like everything else.
+    /// This allows e.g. testing DexProtect against a mock, while the Multiplexer keeps forwarding to VRChat.
+    pub dex_send_target: Option<SocketAddr>,
+    /// How often the parameters of the last Key are sent again, as long as the Avatar doesn't change. 0 never sends them again.
+    pub dex_reassert_interval_secs: u64,
+    pub dex_coalesce_sends: bool,
+    /// Only sends the parameters of a Key, whose last value received from VRChat differs from the Key.
+    pub dex_only_send_deltas: bool,
+    /// Uses the first String of an Avatar change as the Avatar id, instead of ignoring Avatar changes with additional arguments.
+    pub dex_lenient_avatar_change: bool,
+    /// Ignores Avatar changes to the Avatar id, that was just unlocked, e.g. the repeated Avatar changes on a world load.
+    pub dex_skip_duplicate_changes: bool,
+    /// The OSC address, on which Avatar changes are received.
+    pub dex_change_addr: String,
+    /// The OSC address prefix of Avatar parameters. Key parameters are sent to this prefix followed by their name.
+    pub dex_param_prefix: String,
+    /// Sends Key values as 64-bit Doubles computed in `f64`, instead of 32-bit Floats.
+    pub dex_high_precision: bool,
+    /// Whether the Messages DexProtect sends to VRChat are also forwarded to the Multiplexer.
+    pub dex_forward_to_multiplexer: bool,
+    pub dex_param_filter_mode: ParamFilterMode,
+    /// The names of the parameters (the part after `/avatar/parameters/`), that `dex_param_filter_mode` applies to.
+    pub dex_param_filter: Vec<String>,
     pub path: PathBuf,
     pub osc_multiplexer_rev_port: Vec<u16>,
     pub osc_multiplexer_parse_packets: bool,
+    /// Address rewrites for the forward ports. If there are any, packets are always parsed.
+    pub osc_multiplexer_rewrite_rules: Vec<RewriteRule>,
 }
 
 impl Default for OscCreateData {
     fn default() -> Self {
         OscCreateData{
-            ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            recv_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            recv_scope_id: 0,
+            send_ip: None,
+            send_scope_id: 0,
             recv_port: OSC_RECV_PORT,
             send_port: OSC_SEND_PORT,
             max_message_size: osc_handler::OSC_RECV_BUFFER_SIZE,
+            heartbeat_interval_secs: osc_handler::receiver::DEFAULT_HEARTBEAT_INTERVAL.map_or(0, |interval| interval.as_secs()),
+            max_in_flight_sends: DEFAULT_MAX_IN_FLIGHT_SENDS,
+            record_path: None,
+            bad_packet_dir: None,
+            recv_bind_retries: 3,
+            allow_equal_ports: false,
+            osc_send_bind_port: 0,
+            osc_bind_interface: None,
+            metrics_addr: None,
             dex_protect_enabled: true,
             dex_use_bundles: false,
+            dex_use_default_key: false,
+            dex_default_key_name: "_default".to_string(),
+            dex_key_mapping_path: None,
+            dex_trigger_param: None,
+            dex_manifest_path: None,
+            dex_bundle_delay_ms: 0,
+            dex_send_spacing_ms: 0,
+            dex_send_target: None,
+            dex_reassert_interval_secs: 0,
+            dex_coalesce_sends: false,
+            dex_only_send_deltas: false,
+            dex_lenient_avatar_change: false,
+            dex_skip_duplicate_changes: true,
+            dex_change_addr: DEFAULT_DEX_CHANGE_ADDR.to_string(),
+            dex_param_prefix: DEFAULT_DEX_PARAM_PREFIX.to_string(),
+            dex_high_precision: false,
+            dex_forward_to_multiplexer: false,
+            dex_param_filter_mode: ParamFilterMode::default(),
+            dex_param_filter: Vec::new(),
             path: PathBuf::new(),
             osc_multiplexer_rev_port: Vec::new(),
             osc_multiplexer_parse_packets: false,
+            osc_multiplexer_rewrite_rules: Vec::new(),
         }
     }
 }
 
-enum MessageHandlers{
-    Dex(DexOscHandler),
-    Stub(osc_handler::multple_handler::StubHandler),
-}
-impl osc_handler::MessageHandler for MessageHandlers {
-    type Fut = Either<core::future::Ready<()>, Pin<Box<dyn Future<Output = Self::Output> + Send>>>;
-    type Output = ();
+impl OscCreateData {
+    /// Returns the names of the settings, that differ between `self` and `other`.
+    pub fn changed_settings(&self, other: &Self) -> Vec<&'static str> {
+        //Destructured, so that new settings can't be forgotten here.
+        let Self {
+            recv_ip,
+            recv_scope_id,
+            send_ip,
+            send_scope_id,
+            recv_port,
+            send_port,
+            max_message_size,
+            heartbeat_interval_secs,
+            max_in_flight_sends,
+            record_path,
+            bad_packet_dir,
+            recv_bind_retries,
+            allow_equal_ports,
+            osc_send_bind_port,
+            osc_bind_interface,
+            metrics_addr,
+            dex_protect_enabled,
+            dex_use_bundles,
+            dex_use_default_key,
+            dex_default_key_name,
+            dex_key_mapping_path,
+            dex_trigger_param,
+            dex_manifest_path,
+            dex_bundle_delay_ms,
+            dex_send_spacing_ms,
+            dex_send_target,
+            dex_reassert_interval_secs,
+            dex_coalesce_sends,
+            dex_only_send_deltas,
+            dex_lenient_avatar_change,
+            dex_skip_duplicate_changes,
+            dex_change_addr,
+            dex_param_prefix,
+            dex_high_precision,
+            dex_forward_to_multiplexer,
+            dex_param_filter_mode,
+            dex_param_filter,
+            path,
+            osc_multiplexer_rev_port,
+            osc_multiplexer_parse_packets,
+            osc_multiplexer_rewrite_rules,
+        } = self;
+        let mut changed = Vec::new();
+        macro_rules! changed {
+            ($field:ident) => {
+                if *$field != other.$field {
+                    changed.push(stringify!($field));
+                }
+            };
+        }
+        changed!(recv_ip);
+        changed!(recv_scope_id);
+        changed!(send_ip);
+        changed!(send_scope_id);
+        changed!(recv_port);
+        changed!(send_port);
+        changed!(max_message_size);
+        changed!(heartbeat_interval_secs);
+        changed!(max_in_flight_sends);
+        changed!(record_path);
+        changed!(bad_packet_dir);
+        changed!(recv_bind_retries);
+        changed!(allow_equal_ports);
+        changed!(osc_send_bind_port);
+        changed!(osc_bind_interface);
+        changed!(metrics_addr);
+        changed!(dex_protect_enabled);
+        changed!(dex_use_bundles);
+        changed!(dex_use_default_key);
+        changed!(dex_default_key_name);
+        changed!(dex_key_mapping_path);
+        changed!(dex_trigger_param);
+        changed!(dex_manifest_path);
+        changed!(dex_bundle_delay_ms);
+        changed!(dex_send_spacing_ms);
+        changed!(dex_send_target);
+        changed!(dex_reassert_interval_secs);
+        changed!(dex_coalesce_sends);
+        changed!(dex_only_send_deltas);
+        changed!(dex_lenient_avatar_change);
+        changed!(dex_skip_duplicate_changes);
+        changed!(dex_change_addr);
+        changed!(dex_param_prefix);
+        changed!(dex_high_precision);
+        changed!(dex_forward_to_multiplexer);
+        changed!(dex_param_filter_mode);
+        changed!(dex_param_filter);
+        changed!(path);
+        changed!(osc_multiplexer_rev_port);
+        changed!(osc_multiplexer_parse_packets);
+        changed!(osc_multiplexer_rewrite_rules);
+        changed
+    }
+
+    /// Returns the IP OSC Messages are sent to.
+    pub fn send_ip(&self) -> IpAddr {
+        self.send_ip.unwrap_or(self.recv_ip)
+    }
 
-    fn handle(&mut self, message: Arc<rosc::OscMessage>) -> Self::Fut {
-        match self {
-            MessageHandlers::Dex(handler) => handler.handle(message),
-            MessageHandlers::Stub(handler) => Either::Left(handler.handle(message)),
+    /// Returns the address the OSC Receive Socket is bound to.
+    pub fn recv_addr(&self) -> SocketAddr {
+        socket_addr(self.recv_ip, self.recv_port, self.recv_scope_id)
+    }
+
+    /// Checks, that neither the Send Port nor DexProtect's Send Target is our own Receive Port, unless `allow_equal_ports` is set.
+    pub fn check_ports(&self) -> Result<(), OscConfigError> {
+        if self.allow_equal_ports {
+            return Ok(());
         }
+        let targets = [("OSC Send Port", Some(self.send_addr())), ("DexProtect Send Target", self.dex_send_target)];
+        for (target, addr) in targets {
+            if addr.is_some_and(|addr| addr.port() == self.recv_port && receives_from(self.recv_ip, addr.ip())) {
+                return Err(OscConfigError::EqualPorts { target, port: self.recv_port });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the address DexProtect sends to, see [OscCreateData::dex_send_target].
+    pub fn dex_send_addr(&self) -> SocketAddr {
+        self.dex_send_target.unwrap_or_else(|| self.send_addr())
+    }
+
+    /// Returns the address OSC Messages are sent to.
+    pub fn send_addr(&self) -> SocketAddr {
+        match self.send_ip {
+            Some(send_ip) => socket_addr(send_ip, self.send_port, self.send_scope_id),
+            None => socket_addr(self.recv_ip, self.send_port, self.recv_scope_id),
+        }
+    }
+
+    /// Returns the IP the Multiplexer forwards to.
+    /// The other OSC Applications run on this machine, so this is localhost, if the Receiver is bound to all interfaces.
+    fn multiplexer_ip(&self) -> IpAddr {
+        match self.recv_ip {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(std::net::Ipv6Addr::LOCALHOST),
+            ip => ip,
+        }
+    }
+}
+
+/// Checks, whether a Receiver bound to `recv_ip` gets what is sent to `send_ip`.
+/// A Receiver on all interfaces only gets it, if `send_ip` is a loopback address or one of this machine's addresses.
+fn receives_from(recv_ip: IpAddr, send_ip: IpAddr) -> bool {
+    if send_ip == recv_ip || (send_ip.is_loopback() && recv_ip.is_loopback()) {
+        return true;
     }
+    //Only addresses of this machine can be bound to.
+    recv_ip.is_unspecified()
+        && (send_ip.is_loopback() || send_ip.is_unspecified() || std::net::UdpSocket::bind((send_ip, 0)).is_ok())
 }
 
+fn socket_addr(ip: IpAddr, port: u16, scope_id: u32) -> SocketAddr {
+    match ip {
+        IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)),
+        IpAddr::V4(_) => SocketAddr::new(ip, port),
+    }
+}
 
-enum PacketHandlers{
-    Multiplexer(MultiplexerOsc),
-    Stub(osc_handler::multple_handler::StubHandler),
+/// The errors, that can occur when turning the Settings from the Ui into [OscCreateData].
+#[derive(Debug, thiserror::Error)]
+pub enum OscConfigError {
+    #[error("The {field} \"{text}\" is not a valid OSC Address. It has to start with a '/'.")]
+    InvalidOscAddress{
+        field: &'static str,
+        text: String,
+    },
+    #[error("The {field} \"{text}\" is not a valid IP-Address: {error}")]
+    InvalidIp{
+        field: &'static str,
+        text: String,
+        #[source]
+        error: std::net::AddrParseError,
+    },
+    #[error("The {field} has an invalid scope id \"{scope}\". Only numeric interface indices are supported (e.g. fe80::1%3).")]
+    InvalidScopeId{
+        field: &'static str,
+        scope: String,
+    },
+    #[error("The {field} \"{text}\" is an IPv4 Address, but only IPv6 Addresses can have a scope id.")]
+    ScopeIdOnIpv4{
+        field: &'static str,
+        text: String,
+    },
+    #[error("The Receive IP {recv_ip} and the Send IP {send_ip} are of different IP versions. Please use either IPv4 or IPv6 for both.")]
+    MixedFamilies{
+        recv_ip: IpAddr,
+        send_ip: IpAddr,
+    },
+    #[error("The {field} \"{text}\" is not a valid IP-Address and Port (e.g. 127.0.0.1:9464): {error}")]
+    InvalidSocketAddr{
+        field: &'static str,
+        text: String,
+        #[source]
+        error: std::net::AddrParseError,
+    },
+    #[error("The {target} and the OSC Receive Port are both {port} on the same IP. Everything sent would be received again. VRChat uses different ports (by default {} and {}).", OSC_SEND_PORT, OSC_RECV_PORT)]
+    EqualPorts{
+        target: &'static str,
+        port: u16,
+    },
 }
 
-impl osc_handler::PacketHandler for PacketHandlers {
-    type Fut = Either<core::future::Ready<()>, Pin<Box<dyn Future<Output = Self::Output> + Send>>>;
-    type Output = ();
+/// Turns the trigger parameter from the Ui into an OSC address.
+/// A plain parameter name is turned into `<param_prefix><name>`. Empty means no trigger parameter.
+pub fn trigger_address(text: &str, param_prefix: &str) -> Option<String> {
+    match text.trim() {
+        "" => None,
+        address if address.starts_with('/') => Some(address.to_string()),
+        name => Some(format!("{param_prefix}{name}")),
+    }
+}
 
-    fn handle(&mut self, message: Arc<osc_handler::osc_types_arc::OscPacket>) -> Self::Fut {
-        match self {
-            PacketHandlers::Multiplexer(handler) => {
-                let mut handler = handler.clone();
-                Either::Right(Box::pin(async move {handler.handle(message).await;}))
-            },
-            PacketHandlers::Stub(handler) => Either::Left(handler.handle(message)),
-        }
+/// Parses an OSC address from the Ui. Empty means `default`.
+pub fn parse_osc_address(field: &'static str, text: &str, default: &str) -> Result<String, OscConfigError> {
+    match text.trim() {
+        "" => Ok(default.to_string()),
+        address if address.starts_with('/') => Ok(address.to_string()),
+        address => Err(OscConfigError::InvalidOscAddress { field, text: address.to_string() }),
     }
 }
-enum RawPacketHandlers{
-    Multiplexer(MultiplexerOsc),
-    Stub(osc_handler::multple_handler::StubHandler),
+
+/// Parses an IP-Address from the Ui.
+/// IPv6 Addresses may be wrapped in brackets (`[::1]`) and may have a numeric scope id (`fe80::1%3`).
+/// Returns the IP and its scope id (0 if none was given).
+pub fn parse_ip(field: &'static str, text: &str) -> Result<(IpAddr, u32), OscConfigError> {
+    let trimmed = text.trim();
+    let unbracketed = trimmed.strip_prefix('[')
+        .and_then(|trimmed| trimmed.strip_suffix(']'))
+        .unwrap_or(trimmed);
+    let (ip, scope) = match unbracketed.split_once('%') {
+        Some((ip, scope)) => (ip, Some(scope)),
+        None => (unbracketed, None),
+    };
+    let ip = IpAddr::from_str(ip).map_err(|error| OscConfigError::InvalidIp { field, text: text.to_string(), error })?;
+    let scope_id = match (ip, scope) {
+        (_, None) => 0,
+        (IpAddr::V6(_), Some(scope)) => u32::from_str(scope).map_err(|_| OscConfigError::InvalidScopeId { field, scope: scope.to_string() })?,
+        (IpAddr::V4(_), Some(_)) => return Err(OscConfigError::ScopeIdOnIpv4 { field, text: text.to_string() }),
+    };
+    Ok((ip, scope_id))
 }
 
-impl osc_handler::RawPacketHandler for RawPacketHandlers {
-    type Fut<'a> = Either<core::future::Ready<()>, Pin<Box<dyn Future<Output = Self::Output<'a>> + Send + 'a>>>;
-    type Output<'a> = ();
+/// The errors, that can occur when starting the OSC Handlers.
+#[derive(Debug, thiserror::Error)]
+pub enum OscStartError {
+    #[error("The OSC Receive Port {port} on {ip} is already in use by another Application: {error}")]
+    RecvPortInUse{
+        ip: IpAddr,
+        port: u16,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("The local OSC Send Port {port} is already in use by another Application: {error}")]
+    SendPortInUse{
+        port: u16,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+    #[error(transparent)]
+    KeyMapping(#[from] KeyMappingError),
+    #[error(transparent)]
+    Config(#[from] OscConfigError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
 
-    fn handle<'a>(&mut self, message: &'a[u8]) -> Self::Fut<'a> {
-        match self {
-            RawPacketHandlers::Multiplexer(handler) => {
-                let mut handler = handler.clone();
-                Either::Right(Box::pin(async move {handler.handle(message).await;}))
-            },
-            RawPacketHandlers::Stub(handler) => Either::Left(handler.handle(message)),
+/// How many ports after the configured one are tried by [find_free_recv_port].
+const FREE_PORT_SEARCH_LIMIT: u16 = 100;
+
+/// Finds the next port after `port`, that can be bound on `ip`.
+/// Returns None, if none of the next [FREE_PORT_SEARCH_LIMIT] ports are free.
+pub fn find_free_recv_port(ip: IpAddr, port: u16) -> Option<u16> {
+    (1..=FREE_PORT_SEARCH_LIMIT)
+        .filter_map(|offset| port.checked_add(offset))
+        .find(|port| std::net::UdpSocket::bind((ip, *port)).is_ok())
+}
+
+/// The delay before the first retry of binding the Receive Port. It doubles on every further retry.
+const BIND_RETRY_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Binds the Receive Port.
+/// If the port is in use, binding is retried up to `retries` times with an exponential backoff,
+/// since an instance, that was just closed, might still hold the port for a moment.
+/// If all retries fail, the error of the last attempt is returned.
+async fn bind_recv_socket(addr: SocketAddr, interface: Option<&str>, retries: u32) -> std::io::Result<tokio::net::UdpSocket> {
+    let mut delay = BIND_RETRY_INITIAL_DELAY;
+    let mut attempt = 0;
+    loop {
+        log::info!("About to Bind OSC UDP receive Socket on {}", addr);
+        match interface::bind_udp(addr, interface) {
+            Ok(socket) => return Ok(socket),
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && attempt < retries => {
+                attempt += 1;
+                log::warn!("The OSC Receive Port {} is in use. Retrying in {}ms (retry {attempt}/{retries}).", addr.port(), delay.as_millis());
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e),
         }
     }
 }
 
-pub async fn create_and_start_osc(osc_create_data: &OscCreateData) -> std::io::Result<tokio::task::JoinSet<Infallible>> {
-    let mut message_handlers = MessageHandlers::Stub(osc_handler::multple_handler::StubHandler);
-    let mut packet_handlers = PacketHandlers::Stub(osc_handler::multple_handler::StubHandler);
-    let mut raw_packet_handlers = RawPacketHandlers::Stub(osc_handler::multple_handler::StubHandler);
+/// A started OSC Thread, see [create_and_start_osc].
+pub struct OscRuntime {
+    /// The tasks of the OSC Thread. They stop, once the `shutdown` passed to [create_and_start_osc] changes.
+    pub join_set: tokio::task::JoinSet<()>,
+    pub handles: OscHandles,
+}
+
+/// Shared handles to the parts of a running OSC Thread, so that they can be used without reconnecting.
+#[derive(Clone, Default)]
+pub struct OscHandles {
+    /// The sender DexProtect sends to VRChat with. `None`, if DexProtect is disabled.
+    pub sender: Option<Arc<OscSender>>,
+    /// `None`, if the Multiplexer is disabled.
+    pub(crate) multiplexer: Option<multiplexer::MultiplexerOsc>,
+    /// `None`, if DexProtect is disabled.
+    pub(crate) dex: Option<DexOscHandler>,
+    /// Controls the receive loop, e.g. to clear the buffered bundles.
+    pub receiver: Option<osc_handler::receiver::ReceiverControl>,
+}
+
+/// The [OscHandles] of the running OSC Thread. `None`, while no OSC Thread is running.
+pub type OscHandlesHandle = Arc<egui::mutex::Mutex<Option<OscHandles>>>;
 
+/// Creates all OSC Handlers and starts listening.
+/// All started tasks stop, once `shutdown` changes or its sender is dropped.
+/// Once everything is started, `status` is set to [OscStatus::Connected].
+/// If the Receive Port is still in use after `recv_bind_retries` retries, [OscStartError::RecvPortInUse] is returned.
+/// The outcome of every unlock attempt is stored in `last_unlock`, the parameters sent for it in `applied_params`.
+/// The id of every Avatar change is stored in `last_avatar`.
+/// The Multiplexer can be paused and resumed with `multiplexer_switch`.
+/// Problems, that the user should know about, are sent to `notices`.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_and_start_osc(osc_create_data: &OscCreateData, mut shutdown: tokio::sync::watch::Receiver<bool>, status: &OscStatusHandle, last_unlock: &LastUnlockHandle, last_avatar: &LastAvatarHandle, applied_params: &AppliedParamsHandle, multiplexer_switch: &MultiplexerSwitch, notices: std::sync::mpsc::Sender<OscNotice>, stats: &StatsHandle) -> Result<OscRuntime, OscStartError> {
+    osc_create_data.check_ports()?;
+    let mut message_handlers: Vec<Box<dyn DynMessageHandler>> = Vec::new();
+    let mut packet_handlers: Vec<Box<dyn DynPacketHandler>> = Vec::new();
+    let mut raw_packet_handlers: Vec<Box<dyn DynRawPacketHandler>> = vec![Box::new(crate::metrics::MetricsHandler)];
+    if let Some(path) = &osc_create_data.record_path {
+        raw_packet_handlers.push(Box::new(osc_handler::recording::Recorder::create(path)?));
+    }
+
+    let multiplexer = if !osc_create_data.osc_multiplexer_rev_port.is_empty() {
+        let multiplexer = multiplexer::MultiplexerOsc::new(osc_create_data.multiplexer_ip(), osc_create_data.osc_multiplexer_rev_port.clone(), osc_create_data.osc_multiplexer_rewrite_rules.clone(), osc_create_data.recv_port, osc_create_data.max_in_flight_sends, multiplexer_switch.clone(), stats.clone()).await?;
+        log::info!("Created OSC Multiplexer");
+        Some(multiplexer)
+    } else {
+        None
+    };
+
+    let mut send_addr = None;
+    let mut handles = OscHandles {
+        multiplexer: multiplexer.clone(),
+        ..OscHandles::default()
+    };
     if osc_create_data.dex_protect_enabled {
-        match OscSender::new(osc_create_data.ip, osc_create_data.send_port).await {
+        let bind_port = (osc_create_data.osc_send_bind_port != 0).then_some(osc_create_data.osc_send_bind_port);
+        match OscSender::with_bind_port(osc_create_data.dex_send_addr(), bind_port, osc_create_data.osc_bind_interface.as_deref()).await {
             Ok(v) => {
                 log::info!("Created OSC Sender.");
-                let osc = Arc::new(v);
-                message_handlers = MessageHandlers::Dex(dex::DexOscHandler::new(osc_create_data, osc));
+                send_addr = v.local_addr().ok().zip(v.peer_addr().ok());
+                let osc = Arc::new(v.with_max_in_flight(osc_create_data.max_in_flight_sends));
+                handles.sender = Some(osc.clone());
+                let dex_multiplexer = multiplexer.as_ref()
+                    .filter(|_| osc_create_data.dex_forward_to_multiplexer)
+                    .map(|multiplexer| multiplexer.without_port(osc_create_data.recv_port));
+                let dex = DexOscHandler::new(osc_create_data, osc, last_unlock.clone(), last_avatar.clone(), applied_params.clone(), notices, dex_multiplexer).await?;
+                handles.dex = Some(dex.clone());
+                message_handlers.push(Box::new(dex));
                 log::info!("Created DexProtectOsc Handler.");
             },
             Err(e) => {
                 log::error!("Failed to create OSC Sender: {}. Can't create DexProtectOsc Handler as a Result.", e);
-                return Err(e)
+                return Err(match (e.kind(), bind_port) {
+                    (std::io::ErrorKind::AddrInUse, Some(port)) => OscStartError::SendPortInUse { port, error: e },
+                    _ => e.into(),
+                })
             }
         };
     }
 
-    if !osc_create_data.osc_multiplexer_rev_port.is_empty() {
-        let multiplexer = multiplexer::MultiplexerOsc::new(osc_create_data.ip, osc_create_data.osc_multiplexer_rev_port.clone()).await?;
-        log::info!("Created OSC Multiplexer");
-        if osc_create_data.osc_multiplexer_parse_packets {
-            packet_handlers = PacketHandlers::Multiplexer(multiplexer);
+    if let Some(multiplexer) = multiplexer {
+        //Rewriting addresses needs the parsed packets.
+        if osc_create_data.osc_multiplexer_parse_packets || !osc_create_data.osc_multiplexer_rewrite_rules.is_empty() {
+            packet_handlers.push(Box::new(multiplexer));
         } else {
-            raw_packet_handlers = RawPacketHandlers::Multiplexer(multiplexer);
+            raw_packet_handlers.push(Box::new(multiplexer));
         }
     }
     let mut js = tokio::task::JoinSet::new();
-    OscReceiver::new(osc_create_data.ip, osc_create_data.recv_port, osc_create_data.max_message_size, core::iter::once(message_handlers), core::iter::once(packet_handlers), core::iter::once(raw_packet_handlers)).await?.listen(&mut js);
+    let socket = bind_recv_socket(osc_create_data.recv_addr(), osc_create_data.osc_bind_interface.as_deref(), osc_create_data.recv_bind_retries)
+        .await
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::AddrInUse => OscStartError::RecvPortInUse { ip: osc_create_data.recv_ip, port: osc_create_data.recv_port, error },
+            _ => OscStartError::Io(error),
+        })?;
+    let receiver = OscReceiverBuilder::new()
+        .socket(socket)
+        .max_message_size(osc_create_data.max_message_size)
+        .stats(stats.receiver.clone())
+        .bad_packet_sink(osc_create_data.bad_packet_dir.clone().map(|dir| {
+            Arc::new(bad_packets::BadPacketCapture::new(dir)) as Arc<dyn osc_handler::receiver::BadPacketSink>
+        }))
+        .heartbeat(match osc_create_data.heartbeat_interval_secs {
+            0 => None,
+            secs => Some(std::time::Duration::from_secs(secs)),
+        })
+        .message_handlers(message_handlers)
+        .packet_handlers(packet_handlers)
+        .raw_packet_handlers(raw_packet_handlers)
+        .build()
+        .await?;
+    let recv_addr = receiver.local_addr()?;
+    #[cfg(feature = "metrics")]
+    if let Some(metrics_addr) = osc_create_data.metrics_addr {
+        let listener = tokio::net::TcpListener::bind(metrics_addr).await?;
+        log::info!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+        let mut shutdown = shutdown.clone();
+        js.spawn(crate::metrics::serve(listener, async move {
+            let _ = shutdown.changed().await;
+        }));
+    }
+    handles.receiver = Some(receiver.control());
+    receiver.listen(&mut js, async move {
+        let _ = shutdown.changed().await;
+    });
     log::info!("Started OSC Listener.");
-    Ok(js)
-}
\ No newline at end of file
+    *status.lock() = OscStatus::Connected { recv_addr, send_addr };
+    Ok(OscRuntime {
+        join_set: js,
+        handles,
+    })
+}
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use rosc::{OscMessage, OscPacket, OscType};
+    use crate::osc::test_util::{block_on, fake_app, float_message, recv_messages};
+    use super::*;
+
+    /// Starts the OSC Threads, and returns the address they receive on.
+    async fn start(data: &OscCreateData) -> (OscRuntime, tokio::sync::watch::Sender<bool>, SocketAddr) {
+        let (shutdown, shutdown_rx) = tokio::sync::watch::channel(false);
+        let status = OscStatusHandle::default();
+        let (notices, _notices) = std::sync::mpsc::channel();
+        let runtime = create_and_start_osc(
+            data,
+            shutdown_rx,
+            &status,
+            &LastUnlockHandle::default(),
+            &LastAvatarHandle::default(),
+            &AppliedParamsHandle::default(),
+            &MultiplexerSwitch::default(),
+            notices,
+            &StatsHandle::default(),
+        ).await.unwrap();
+        let recv_addr = match &*status.lock() {
+            OscStatus::Connected { recv_addr, .. } => *recv_addr,
+            other => panic!("Expected to be connected, but the status is {other:?}"),
+        };
+        (runtime, shutdown, recv_addr)
+    }
+
+    async fn stop(mut runtime: OscRuntime, shutdown: tokio::sync::watch::Sender<bool>) {
+        shutdown.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while runtime.join_set.join_next().await.is_some() {}
+        }).await.unwrap();
+    }
+
+    /// Sends an Avatar change from `vrchat` to `recv_addr`.
+    async fn change_avatar(vrchat: &tokio::net::UdpSocket, recv_addr: SocketAddr, id: &str) {
+        let change = OscPacket::Message(OscMessage {
+            addr: DEFAULT_DEX_CHANGE_ADDR.to_string(),
+            args: vec![OscType::String(id.to_string())],
+        });
+        vrchat.send_to(&rosc::encoder::encode(&change).unwrap(), recv_addr).await.unwrap();
+    }
+
+    #[test]
+    fn avatar_change_unlocks_over_loopback() {
+        block_on(async {
+            let vrchat = fake_app().await;
+            let keys = tempfile::tempdir().unwrap();
+            std::fs::write(keys.path().join("avtr_e2e.key"), "1|a|0,5|b").unwrap();
+            let data = OscCreateData {
+                path: keys.path().to_path_buf(),
+                recv_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                recv_port: 0,
+                send_port: vrchat.local_addr().unwrap().port(),
+                ..OscCreateData::default()
+            };
+            let (runtime, shutdown, recv_addr) = start(&data).await;
+
+            change_avatar(&vrchat, recv_addr, "avtr_e2e").await;
+            assert_eq!(recv_messages(&vrchat, Duration::from_millis(200)).await, vec![
+                float_message("/avatar/parameters/a", 1.0),
+                float_message("/avatar/parameters/b", 0.5),
+            ]);
+
+            stop(runtime, shutdown).await;
+        });
+    }
+
+    #[test]
+    fn dex_send_target_overrides_the_send_port() {
+        block_on(async {
+            let vrchat = fake_app().await;
+            let mock = fake_app().await;
+            let keys = tempfile::tempdir().unwrap();
+            std::fs::write(keys.path().join("avtr_mock.key"), "1|a").unwrap();
+            let data = OscCreateData {
+                path: keys.path().to_path_buf(),
+                recv_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                recv_port: 0,
+                send_port: vrchat.local_addr().unwrap().port(),
+                dex_send_target: Some(mock.local_addr().unwrap()),
+                ..OscCreateData::default()
+            };
+            assert_eq!(data.dex_send_addr(), mock.local_addr().unwrap());
+            let (runtime, shutdown, recv_addr) = start(&data).await;
+
+            change_avatar(&vrchat, recv_addr, "avtr_mock").await;
+            assert_eq!(recv_messages(&mock, Duration::from_millis(200)).await, vec![float_message("/avatar/parameters/a", 1.0)]);
+            assert_eq!(recv_messages(&vrchat, Duration::from_millis(100)).await, vec![]);
+
+            stop(runtime, shutdown).await;
+        });
+    }
+
+    fn ports(recv_ip: Ipv4Addr, send_ip: Option<Ipv4Addr>, send_port: u16) -> OscCreateData {
+        OscCreateData {
+            recv_ip: IpAddr::V4(recv_ip),
+            recv_port: 9001,
+            send_ip: send_ip.map(IpAddr::V4),
+            send_port,
+            ..OscCreateData::default()
+        }
+    }
+
+    #[test]
+    fn equal_ports_on_the_same_ip_are_rejected() {
+        let data = ports(Ipv4Addr::LOCALHOST, None, 9001);
+        assert!(matches!(data.check_ports(), Err(OscConfigError::EqualPorts { target: "OSC Send Port", port: 9001 })));
+        assert!(ports(Ipv4Addr::LOCALHOST, None, 9000).check_ports().is_ok());
+        assert!(OscCreateData { allow_equal_ports: true, ..data }.check_ports().is_ok());
+    }
+
+    #[test]
+    fn all_interfaces_only_match_local_send_ips() {
+        assert!(ports(Ipv4Addr::UNSPECIFIED, None, 9001).check_ports().is_err());
+        assert!(ports(Ipv4Addr::UNSPECIFIED, Some(Ipv4Addr::LOCALHOST), 9001).check_ports().is_err());
+        //TEST-NET-1 is never assigned to this machine, so the Send Port can be anything.
+        assert!(ports(Ipv4Addr::UNSPECIFIED, Some(Ipv4Addr::new(192, 0, 2, 1)), 9001).check_ports().is_ok());
+    }
+
+    #[test]
+    fn dex_send_target_is_checked() {
+        let data = OscCreateData {
+            dex_send_target: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9001)),
+            ..ports(Ipv4Addr::UNSPECIFIED, Some(Ipv4Addr::new(192, 0, 2, 1)), 9001)
+        };
+        assert!(matches!(data.check_ports(), Err(OscConfigError::EqualPorts { target: "DexProtect Send Target", port: 9001 })));
+    }
+
+    #[test]
+    fn recv_bind_is_retried_until_the_port_is_freed() {
+        block_on(async {
+            let busy = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let addr = busy.local_addr().unwrap();
+            //Freed during the first retry delay.
+            let free = tokio::spawn(async move {
+                tokio::time::sleep(BIND_RETRY_INITIAL_DELAY / 2).await;
+                drop(busy);
+            });
+            let socket = bind_recv_socket(addr, None, 3).await.unwrap();
+            assert_eq!(socket.local_addr().unwrap(), addr);
+            free.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn recv_bind_gives_up_after_the_retries() {
+        block_on(async {
+            let busy = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let error = bind_recv_socket(busy.local_addr().unwrap(), None, 1).await.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+        });
+    }
+}
@@ -0,0 +1,95 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use egui::mutex::Mutex;
+
+/// The size at which the capture file is rotated.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// How many rotated files are kept besides the current one.
+/// This caps the total size of the captures at `(MAX_ROTATED_FILES + 1) * MAX_FILE_SIZE`.
+const MAX_ROTATED_FILES: u32 = 4;
+const FILE_STEM: &str = "bad_packets";
+/// How many bytes are written per line of the hex dump.
+const BYTES_PER_LINE: usize = 32;
+
+/// Writes the data, that could not be decoded, as hex together with the decode error into rotating files.
+///
+/// The current file is `bad_packets.log` in the configured directory.
+/// Once it reaches [MAX_FILE_SIZE], it is renamed to `bad_packets.1.log`, the older ones are shifted by one
+/// and the oldest one is deleted.
+pub(super) struct BadPacketCapture {
+    dir: PathBuf,
+    /// Opened on the first bad packet, so that nothing is created if everything decodes fine.
+    file: Mutex<Option<File>>,
+}
+
+impl BadPacketCapture {
+    pub(super) fn new(dir: PathBuf) -> Self {
+        log::info!("Capturing undecodable OSC Data to '{}'.", dir.display());
+        Self {
+            dir,
+            file: Mutex::new(None),
+        }
+    }
+
+    fn path(&self, index: u32) -> PathBuf {
+        match index {
+            0 => self.dir.join(format!("{FILE_STEM}.log")),
+            i => self.dir.join(format!("{FILE_STEM}.{i}.log")),
+        }
+    }
+
+    fn rotate(&self) -> std::io::Result<()> {
+        remove_if_exists(&self.path(MAX_ROTATED_FILES))?;
+        for i in (0..MAX_ROTATED_FILES).rev() {
+            let from = self.path(i);
+            if from.exists() {
+                std::fs::rename(&from, self.path(i + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8], reason: &dyn std::fmt::Display) -> std::io::Result<()> {
+        let mut file = self.file.lock();
+        if let Some(f) = file.as_ref() {
+            if f.metadata()?.len() >= MAX_FILE_SIZE {
+                *file = None;
+                self.rotate()?;
+            }
+        }
+        let f = match file.as_mut() {
+            Some(f) => f,
+            None => {
+                std::fs::create_dir_all(&self.dir)?;
+                file.insert(File::options().create(true).append(true).open(self.path(0))?)
+            }
+        };
+        let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let mut record = format!("[{}.{:03}] {} bytes: {reason}\n", time.as_secs(), time.subsec_millis(), data.len());
+        for line in data.chunks(BYTES_PER_LINE) {
+            for byte in line {
+                record.push_str(&format!("{byte:02X} "));
+            }
+            record.pop();
+            record.push('\n');
+        }
+        f.write_all(record.as_bytes())
+    }
+}
+
+impl osc_handler::receiver::BadPacketSink for BadPacketCapture {
+    fn bad_packet(&self, data: &[u8], reason: &dyn std::fmt::Display) {
+        if let Err(e) = self.write(data, reason) {
+            log::warn!("Failed to capture undecodable OSC Data to '{}': {e}", self.dir.display());
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use egui::mutex::Mutex;
+use rosc::{OscMessage, OscPacket};
+use super::OscSender;
+
+/// How long messages are collected, before they are sent.
+pub(crate) const COALESCE_WINDOW: Duration = Duration::from_millis(16);
+
+/// Wraps an [OscSender] and collects messages for a short window before sending them.
+/// If multiple messages to the same address are queued within that window, only the latest one is sent.
+///
+/// This reduces the amount of OSC traffic, since VRChat is known to drop messages under load.
+#[derive(Clone)]
+pub(super) struct CoalescingSender {
+    osc: Arc<OscSender>,
+    window: Duration,
+    pending: Arc<Mutex<Pending>>,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// The queued messages, in the order their address was first queued.
+    messages: Vec<OscMessage>,
+    /// Maps an address to its message's index in `messages`.
+    index: HashMap<String, usize>,
+    /// Whether a task to flush `messages` has already been spawned.
+    flush_scheduled: bool,
+}
+
+impl CoalescingSender {
+    pub fn new(osc: Arc<OscSender>, window: Duration) -> Self {
+        Self {
+            osc,
+            window,
+            pending: Arc::new(Mutex::new(Pending::default())),
+        }
+    }
+
+    /// Queues a message to be sent at the end of the current window.
+    /// Replaces a message to the same address, that was queued within the current window.
+    pub fn queue(&self, message: OscMessage) {
+        let mut pending = self.pending.lock();
+        match pending.index.get(&message.addr).copied() {
+            Some(i) => {
+                #[cfg(all(debug_assertions, feature="debug_log"))]
+                log::trace!("Coalescing OSC Message to {}", message.addr);
+                pending.messages[i] = message;
+            },
+            None => {
+                let i = pending.messages.len();
+                pending.index.insert(message.addr.clone(), i);
+                pending.messages.push(message);
+            }
+        }
+        if !pending.flush_scheduled {
+            pending.flush_scheduled = true;
+            let slf = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(slf.window).await;
+                slf.flush().await;
+            });
+        }
+    }
+
+    /// Sends all queued messages.
+    async fn flush(&self) {
+        let messages = {
+            let mut pending = self.pending.lock();
+            pending.flush_scheduled = false;
+            pending.index.clear();
+            core::mem::take(&mut pending.messages)
+        };
+        for message in messages {
+            if let Ok(v) = self.osc.send_message_with_logs(&OscPacket::Message(message)) {
+                let _ = v.await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::osc::test_util::{block_on, fake_app, float_message, recv_messages, sender_to};
+    use super::*;
+
+    #[test]
+    fn only_latest_value_is_sent() {
+        block_on(async {
+            let app = fake_app().await;
+            let sender = CoalescingSender::new(Arc::new(sender_to(&app).await), COALESCE_WINDOW);
+            sender.queue(float_message("/avatar/parameters/X", 1.0));
+            sender.queue(float_message("/avatar/parameters/Y", 3.0));
+            sender.queue(float_message("/avatar/parameters/X", 2.0));
+
+            assert_eq!(recv_messages(&app, Duration::from_millis(200)).await, vec![
+                float_message("/avatar/parameters/X", 2.0),
+                float_message("/avatar/parameters/Y", 3.0),
+            ]);
+        });
+    }
+}
@@ -0,0 +1,60 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use super::{OscSender, SendTimeoutError};
+
+/// How long the Connection Test waits for any OSC Data to arrive.
+pub const CONNECTION_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long sending the test Message may take.
+const CONNECTION_TEST_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+/// The address of the Message sent by the Connection Test.
+/// VRChat ignores Messages to addresses it doesn't know, so this has no effect on the Avatar.
+const CONNECTION_TEST_ADDRESS: &str = "/dexprotectosc/ping";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectionTestError{
+    #[error("Failed to bind the OSC Receive Port {port}: {error}")]
+    Bind{
+        port: u16,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("Failed to create the OSC Sender: {0}")]
+    Sender(#[source] std::io::Error),
+    #[error("Failed to send the test Message: {0}")]
+    Send(#[from] SendTimeoutError),
+    #[error("Failed to receive on the OSC Receive Port: {0}")]
+    Receive(#[source] std::io::Error),
+    #[error("No OSC Data was received within {}s.", .0.as_secs())]
+    NothingReceived(Duration),
+}
+
+/// Checks, whether OSC Data can be exchanged with the configured ips and ports.
+///
+/// This binds the Receive Port, sends a harmless Message to the Send Port
+/// and then waits up to [CONNECTION_TEST_TIMEOUT] for any datagram on the Receive Port.
+/// Returns the address the first datagram was received from.
+///
+/// This needs the Receive Port to be free, so it can't run while the OSC Thread is running.
+pub async fn test_connection(recv_addr: SocketAddr, send_addr: SocketAddr) -> Result<SocketAddr, ConnectionTestError> {
+    log::info!("Starting OSC Connection Test. Receiving on {}, sending to {}", recv_addr, send_addr);
+    let recv = UdpSocket::bind(recv_addr).await
+        .map_err(|error| ConnectionTestError::Bind { port: recv_addr.port(), error })?;
+    let sender = OscSender::from_addr(send_addr).await
+        .map_err(ConnectionTestError::Sender)?;
+    let message = rosc::OscPacket::Message(rosc::OscMessage{
+        addr: CONNECTION_TEST_ADDRESS.to_string(),
+        args: Vec::new(),
+    });
+    sender.send_with_timeout(&message, CONNECTION_TEST_SEND_TIMEOUT).await?;
+
+    //The content doesn't matter. Any datagram means, that something is sending OSC Data to us.
+    match tokio::time::timeout(CONNECTION_TEST_TIMEOUT, recv.peek_sender()).await {
+        Ok(Ok(addr)) => {
+            log::info!("OSC Connection Test succeeded. Received data from {}", addr);
+            Ok(addr)
+        },
+        Ok(Err(e)) => Err(ConnectionTestError::Receive(e)),
+        Err(_) => Err(ConnectionTestError::NothingReceived(CONNECTION_TEST_TIMEOUT)),
+    }
+}
@@ -1,37 +1,252 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::future::Future;
-use std::ops::{Index, Shr};
+use std::ops::{Index, Shl};
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use aes::cipher::KeyIvInit;
 use cbc::cipher::BlockDecryptMut;
 use egui::mutex::Mutex;
 use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+use serde_derive::{Deserialize, Serialize};
 use unicode_bom::Bom;
 use super::OscSender;
-use super::OscCreateData;
+use super::{OscCreateData, OscNotice, OscStartError};
+use super::multiplexer::MultiplexerOsc;
+use super::coalescing_sender::{CoalescingSender, COALESCE_WINDOW};
 
+/// How long VRChat gets to confirm all parameters of a Key, unless an [AvatarOverrides] says otherwise.
 const DEX_KEY_WAIT_MS:u64 = 1_500;
-const DEX_KEY_WAIT_DESC:&'static str = "1.5 seconds";
+/// The maximum difference between an echoed parameter and the Key value, for them to still be considered equal.
+const PARAM_FLOAT_TOLERANCE:f32 = 1e-5;
+/// The timetag, that tells the receiver to apply a bundle immediately.
+const IMMEDIATE: rosc::OscTime = rosc::OscTime{
+    seconds: 0,
+    fractional: 1
+};
+
+/// The result of trying to unlock an Avatar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnlockOutcome {
+    /// There is no Key file for the Avatar.
+    NoKey,
+    /// The Key file (or the Keys Folder) could not be read.
+    ReadFailed,
+    /// The Key file could not be decrypted or decoded into text.
+    DecodeFailed,
+    /// The Key file was decoded, but its contents are malformed.
+    Corrupt,
+    /// The Key was sent to VRChat.
+    Applied {
+        /// The amount of parameters sent.
+        count: usize,
+        /// How many of the parameters failed to send.
+        failed: usize,
+    },
+}
+
+impl std::fmt::Display for UnlockOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnlockOutcome::NoKey => write!(f, "No Key found"),
+            UnlockOutcome::ReadFailed => write!(f, "Failed to read the Key"),
+            UnlockOutcome::DecodeFailed => write!(f, "Failed to decode the Key"),
+            UnlockOutcome::Corrupt => write!(f, "The Key is corrupt"),
+            UnlockOutcome::Applied { count, failed: 0 } => write!(f, "Applied {count} parameters"),
+            UnlockOutcome::Applied { count, failed } => write!(f, "Applied {count} parameters, {failed} failed to send"),
+        }
+    }
+}
+
+/// The last Avatar id, that was attempted to be unlocked, and the outcome of that.
+pub type LastUnlockHandle = Arc<Mutex<Option<(Arc<str>, UnlockOutcome)>>>;
+
+/// The last Avatar id, that was seen in an Avatar change, whether it was unlocked or not.
+pub type LastAvatarHandle = Arc<Mutex<Option<Arc<str>>>>;
+
+/// Whether VRChat has confirmed a parameter, that was sent for a Key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamState {
+    /// VRChat has not echoed the parameter yet.
+    Pending,
+    /// VRChat echoed the parameter with the value from the Key.
+    Confirmed,
+    /// VRChat echoed the parameter with a different value.
+    Mismatched,
+    /// The parameter could not be sent to VRChat.
+    SendFailed,
+}
+
+/// The parameters sent for the last applied Key.
+#[derive(Debug, Clone)]
+pub struct AppliedParams {
+    pub id: Arc<str>,
+    /// When the parameters were sent.
+    pub sent: Instant,
+    /// How long VRChat gets to confirm the parameters.
+    pub wait: Duration,
+    /// The parameter names (without `/avatar/parameters/`) with the value from the Key and their state.
+    pub params: BTreeMap<String, (f32, ParamState)>,
+}
+
+impl AppliedParams {
+    /// Whether VRChat is still expected to confirm some of the parameters.
+    pub fn in_progress(&self) -> bool {
+        self.sent.elapsed() < self.wait
+            && self.params.values().any(|(_, state)| *state == ParamState::Pending)
+    }
+}
+
+/// The parameters of the last applied Key. This is cleared on every Avatar change.
+pub type AppliedParamsHandle = Arc<Mutex<Option<AppliedParams>>>;
+
+/// How the parameter filter of DexProtect is applied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ParamFilterMode {
+    /// Parameters in the filter are never set by a Key.
+    #[default]
+    Deny,
+    /// Only parameters in the filter are set by a Key.
+    Allow,
+}
+
+impl std::fmt::Display for ParamFilterMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamFilterMode::Deny => write!(f, "Never set these Parameters"),
+            ParamFilterMode::Allow => write!(f, "Only set these Parameters"),
+        }
+    }
+}
+
+/// Decides, which parameters of a Key are sent.
+struct ParamFilter {
+    mode: ParamFilterMode,
+    names: HashSet<String>,
+}
+
+impl ParamFilter {
+    fn allows(&self, name: &str) -> bool {
+        match self.mode {
+            ParamFilterMode::Deny => !self.names.contains(name),
+            ParamFilterMode::Allow => self.names.contains(name),
+        }
+    }
+}
 
 #[derive(Clone)]
-pub(super) struct DexOscHandler {
+pub(crate) struct DexOscHandler {
+    /// Whether Avatars are unlocked. While this is false, Avatar changes are still tracked, but no Keys are read or sent.
+    enabled: Arc<AtomicBool>,
     path: Arc<std::path::Path>,
     dex_use_bundles: bool,
+    /// Whether Key values are sent as Doubles instead of Floats.
+    dex_high_precision: bool,
+    /// The name of the Key file, that is used for Avatars without their own Key file.
+    default_key: Option<Arc<str>>,
+    /// How far in the future bundles should be applied.
+    dex_bundle_delay: Duration,
+    /// The delay between single parameter sends. Zero sends them concurrently.
+    dex_send_spacing: Duration,
+    /// If set, the parameters of the last Key are sent again this often, until the Avatar changes.
+    dex_reassert_interval: Option<Duration>,
+    /// The task, that sends the parameters of the last Key again.
+    reassert: Arc<Mutex<Option<tokio::task::AbortHandle>>>,
+    /// Whether parameters, that already have the value from the Key, are skipped.
+    only_send_deltas: bool,
+    /// The last value received from VRChat per parameter address since the last Avatar change.
+    /// Only tracked if `only_send_deltas` is set.
+    observed: ObservedParams,
     osc: Arc<OscSender>,
+    /// If set, single parameter sends are coalesced through this instead of being sent directly.
+    coalescer: Option<CoalescingSender>,
+    param_filter: Arc<ParamFilter>,
+    last_unlock: LastUnlockHandle,
+    applied_params: AppliedParamsHandle,
+    /// The address, on which Avatar changes are received.
+    change_addr: Arc<str>,
+    /// Whether Avatar changes with more than one argument use the first String as the Avatar id, instead of being ignored.
+    lenient_avatar_change: bool,
+    /// Whether Avatar changes to `last_unlocked` are ignored.
+    skip_duplicate_changes: bool,
+    /// The Avatar id, that was last unlocked (or is being unlocked right now).
+    /// This is cleared, if the unlock didn't apply a Key, so that the next Avatar change tries again.
+    last_unlocked: Arc<Mutex<Option<Arc<str>>>>,
+    /// The address prefix of Avatar parameters.
+    param_prefix: Arc<str>,
+    /// If set, Avatars are only unlocked, when a truthy value is received on this address.
+    trigger: Option<Arc<str>>,
+    /// The last Avatar id, that was seen. This is unlocked, when the trigger fires.
+    last_avatar: LastAvatarHandle,
+    /// If set, Keys are looked up in this instead of the Keys Folder.
+    manifest: Option<Arc<KeyManifest>>,
+    /// If set, everything sent to VRChat is also forwarded to the Multiplexer.
+    multiplexer: Option<MultiplexerOsc>,
+    /// If set, Avatar ids matching one of its patterns use the mapped Key instead of their own.
+    key_mapping: Option<Arc<KeyMapping>>,
+    notices: std::sync::mpsc::Sender<OscNotice>,
+    /// Whether the Keys Folder is known to be inaccessible, so that this is only reported once.
+    keys_folder_unavailable: Arc<AtomicBool>,
     params: Arc<Mutex<Option<(tokio::task::AbortHandle, HashMap<String, f32>)>>>,
 }
 
 impl DexOscHandler {
-    pub fn new(osc_create_data: &OscCreateData, osc: Arc<OscSender>) -> Self {
-        Self {
+    /// Creates the DexProtect Handler.
+    /// If a Key Manifest or a Key Mapping is configured, it is loaded here, so that it is only read once per Connect.
+    pub async fn new(osc_create_data: &OscCreateData, osc: Arc<OscSender>, last_unlock: LastUnlockHandle, last_avatar: LastAvatarHandle, applied_params: AppliedParamsHandle, notices: std::sync::mpsc::Sender<OscNotice>, multiplexer: Option<MultiplexerOsc>) -> Result<Self, OscStartError> {
+        let manifest = match &osc_create_data.dex_manifest_path {
+            Some(path) => Some(Arc::new(load_manifest(path).await?)),
+            None => None,
+        };
+        let key_mapping = match &osc_create_data.dex_key_mapping_path {
+            Some(path) => Some(Arc::new(load_key_mapping(path).await?)),
+            None => None,
+        };
+        let keys_folder_error = if manifest.is_none() {
+            keys_folder_error(&osc_create_data.path, std::fs::metadata(&osc_create_data.path))
+        } else {
+            None
+        };
+        if let Some(err) = &keys_folder_error {
+            log::error!("{err}\n No Avatar will be unlocked until the Keys Folder is fixed.");
+        }
+        Ok(Self {
+            enabled: Arc::new(AtomicBool::new(true)),
             path: Arc::from(osc_create_data.path.clone()),
             dex_use_bundles: osc_create_data.dex_use_bundles,
+            dex_high_precision: osc_create_data.dex_high_precision,
+            default_key: osc_create_data.dex_use_default_key.then(|| Arc::from(osc_create_data.dex_default_key_name.as_str())),
+            dex_bundle_delay: Duration::from_millis(osc_create_data.dex_bundle_delay_ms),
+            dex_send_spacing: Duration::from_millis(osc_create_data.dex_send_spacing_ms),
+            dex_reassert_interval: (osc_create_data.dex_reassert_interval_secs > 0).then(|| Duration::from_secs(osc_create_data.dex_reassert_interval_secs)),
+            reassert: Arc::new(Mutex::new(None)),
+            only_send_deltas: osc_create_data.dex_only_send_deltas,
+            observed: ObservedParams::default(),
+            coalescer: osc_create_data.dex_coalesce_sends.then(|| CoalescingSender::new(osc.clone(), COALESCE_WINDOW)),
+            param_filter: Arc::new(ParamFilter {
+                mode: osc_create_data.dex_param_filter_mode,
+                names: osc_create_data.dex_param_filter.iter().cloned().collect(),
+            }),
+            last_unlock,
+            applied_params,
+            change_addr: Arc::from(osc_create_data.dex_change_addr.as_str()),
+            lenient_avatar_change: osc_create_data.dex_lenient_avatar_change,
+            skip_duplicate_changes: osc_create_data.dex_skip_duplicate_changes,
+            last_unlocked: Arc::new(Mutex::new(None)),
+            param_prefix: Arc::from(osc_create_data.dex_param_prefix.as_str()),
+            trigger: osc_create_data.dex_trigger_param.as_deref().map(Arc::from),
+            last_avatar,
+            manifest,
+            key_mapping,
+            multiplexer,
+            notices,
+            //The Ui already tells the user about a broken Keys Folder on Connect.
+            keys_folder_unavailable: Arc::new(AtomicBool::new(keys_folder_error.is_some())),
             osc,
             params: Arc::new(Mutex::new(None)),
-        }
+        })
     }
 }
 
@@ -41,38 +256,93 @@ impl osc_handler::MessageHandler for DexOscHandler
     type Output = ();
 
     fn handle(&mut self, message: Arc<OscMessage>) -> Self::Fut {
-        if message.addr.eq_ignore_ascii_case("/avatar/change") {
-            let mut id = None;
-            for i in &message.args{
-                match i {
-                    OscType::String(s) => {
-                        if id.is_none(){
-                            id = Some(s);
-                        }else{
-                            unrecognized_avatar_change(&message.args);
+        if message.addr.eq_ignore_ascii_case(&self.change_addr) {
+            let id = if self.lenient_avatar_change {
+                let id = message.args.iter().find_map(|arg| match arg {
+                    OscType::String(s) => Some(s),
+                    _ => None,
+                });
+                if message.args.len() > 1 {
+                    log::debug!("Using the first String of the Avatar change as the Avatar id. Ignoring the other arguments: {:?}", message.args);
+                }
+                id
+            } else {
+                let mut id = None;
+                for i in &message.args{
+                    match i {
+                        OscType::String(s) => {
+                            if id.is_none(){
+                                id = Some(s);
+                            }else{
+                                unrecognized_avatar_change(&self.change_addr, &message.args);
+                                return futures::future::Either::Left(core::future::ready(()));
+                            }
+                        }
+                        _ => {
+                            unrecognized_avatar_change(&self.change_addr, &message.args);
                             return futures::future::Either::Left(core::future::ready(()));
                         }
                     }
-                    _ => {
-                        unrecognized_avatar_change(&message.args);
-                        return futures::future::Either::Left(core::future::ready(()));
-                    }
                 }
-            }
+                id
+            };
             if let Some(id) = id {
                 log::info!("Got Avatar Change to {id}");
-                let clone = self.clone();
-                return futures::future::Either::Right(Box::pin(clone.handle_avatar_change(Arc::from(id.as_str()))))
+                crate::metrics::METRICS.avatar_change();
+                if self.skip_duplicate_changes && self.last_unlocked.lock().as_deref() == Some(id.as_str()) {
+                    log::info!("The Avatar id '{id}' was just unlocked. Ignoring the repeated Avatar change.");
+                    return futures::future::Either::Left(core::future::ready(()));
+                }
+                self.stop_reassert();
+                //VRChat resets the parameters on an Avatar change, so the old values mean nothing anymore.
+                self.observed.lock().clear();
+                *self.applied_params.lock() = None;
+                let id = Arc::<str>::from(id.as_str());
+                *self.last_avatar.lock() = Some(id.clone());
+                if !self.is_enabled() {
+                    log::info!("DexProtect is paused. Not unlocking the Avatar id '{id}'.");
+                    return futures::future::Either::Left(core::future::ready(()));
+                }
+                if let Some(trigger) = &self.trigger {
+                    log::info!("Not unlocking the Avatar id '{id}' until '{trigger}' is set.");
+                    return futures::future::Either::Left(core::future::ready(()));
+                }
+                return futures::future::Either::Right(self.unlock(id));
             }else{
-                log::error!("No avatar id was found for the '/avatar/change' message. This is unexpected and might be a change to VRChat's OSC messages.")
+                log::error!("No avatar id was found for the '{}' message. This is unexpected and might be a change to VRChat's OSC messages.", self.change_addr)
+            }
+        } else if self.trigger.as_deref() == Some(message.addr.as_str()) {
+            if !message.args.first().and_then(param_is_truthy).unwrap_or(false) {
+                #[cfg(all(debug_assertions, feature="debug_log"))]
+                log::trace!("The trigger parameter was reset: {:?}", message.args);
+                return futures::future::Either::Left(core::future::ready(()));
+            }
+            if !self.is_enabled() {
+                log::info!("The trigger parameter '{}' was set, but DexProtect is paused. Not unlocking.", message.addr);
+                return futures::future::Either::Left(core::future::ready(()));
+            }
+            let id = self.last_avatar.lock().clone();
+            match id {
+                Some(id) => {
+                    log::info!("The trigger parameter '{}' was set. Unlocking the Avatar id '{id}'.", message.addr);
+                    self.stop_reassert();
+                    *self.applied_params.lock() = None;
+                    return futures::future::Either::Right(self.unlock(id));
+                }
+                None => log::warn!("The trigger parameter '{}' was set, but no Avatar change was seen yet. Change into the Avatar again, so that its id is known.", message.addr),
+            }
+        } else if message.addr.starts_with(&*self.param_prefix) {
+            if self.only_send_deltas {
+                if let Some(value) = message.args.first().and_then(param_as_f32) {
+                    self.observed.lock().insert(message.addr.clone(), value);
+                }
             }
-        } else if message.addr.starts_with("/avatar/parameters/") {
             let mut replace = false;
 
             {
                 let mut params = self.params.lock();
                 match params.as_mut() {
-                    Some((abort, params)) => {
+                    Some((_, params)) => {
                         match params.remove(&message.addr) {
                             None => {
                                 #[cfg(all(debug_assertions, feature="debug_log"))]
@@ -90,15 +360,17 @@ impl osc_handler::MessageHandler for DexOscHandler
                                         log::error!("An Avatar Key parameter at the path '{}' was set to no values. Currently this is unexpected.", message.addr);
                                         replace = true;
                                     }
-                                    Some(OscType::Float(f)) => {
-                                        if *f != val {
-                                            log::error!("An Avatar Key parameter at the path '{}' was set to a different value than the key. ", message.addr);
+                                    Some(v) => match param_value_matches(v, val) {
+                                        Some(true) => self.set_param_state(&message.addr, ParamState::Confirmed),
+                                        Some(false) => {
+                                            log::error!("An Avatar Key parameter at the path '{}' was set to a different value than the key. Expected: {val}, Value: {v:?}", message.addr);
+                                            self.set_param_state(&message.addr, ParamState::Mismatched);
+                                            replace = true;
+                                        }
+                                        None => {
+                                            log::error!("An Avatar Key parameter at the path '{}' was set to a non-numeric value. Currently this is unexpected. Value: {v:?}", message.addr);
                                             replace = true;
                                         }
-                                    }
-                                    Some(v) => {
-                                        log::error!("An Avatar Key parameter at the path '{}' was set to a non-float value. Currently this is unexpected. Value: {v:?}", message.addr);
-                                        replace = true;
                                     }
                                 }
                             }
@@ -106,17 +378,18 @@ impl osc_handler::MessageHandler for DexOscHandler
 
                         if params.is_empty() {
                             log::info!("Key has been applied successfully.");
-                            abort.abort();
                             replace = true;
                         }
                     }
                     None => {}
                 }
-            }
-
-            //create a different arc here, so that any cloned arcs are still valid.
-            if replace {
-                self.params = Arc::new(Mutex::new(None));
+                //Reset in place, since clones of this handler (e.g. the one for "Unlock Again") share the same state.
+                //The timeout check isn't needed anymore either, since the problems have already been logged.
+                if replace {
+                    if let Some((abort, _)) = params.take() {
+                        abort.abort();
+                    }
+                }
             }
         }else{
             #[cfg(all(debug_assertions, feature="debug_log"))]
@@ -127,139 +400,629 @@ impl osc_handler::MessageHandler for DexOscHandler
 }
 
 impl DexOscHandler {
-    async fn handle_avatar_change(self, id: Arc<str>) {
-        let mut path = self.path.to_path_buf();
-        if path.file_name().is_some() {
-            path.push(id.as_ref());
-        }
-        path.set_file_name(id.as_ref());
-        path.set_extension("key");
-        match tokio::fs::read(path.as_path()).await{
-            Ok(potentially_decrypted) => {
-                let (v, err) = decrpyt(potentially_decrypted);
-                if let Some(err) = err {
-                    log::error!("Failed to decrypt the Key for the Avatar id '{id}'. Trying to treat the key as an unencrypted legacy Key.\n Error: {err}");
+    /// Returns the path of the Key file with the given name in the Keys Folder, see [key_path].
+    fn key_path(&self, name: &str) -> Option<std::path::PathBuf> {
+        key_path(&self.path, name)
+    }
+
+    /// Pauses or resumes unlocking, without reconnecting. Clones share this.
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        log::info!("{} DexProtect.", if enabled {"Resuming"} else {"Pausing"});
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.stop_reassert();
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Unlocks the Avatar again, even if it was just unlocked. This is what the "Unlock Again" button does.
+    pub(crate) fn force_unlock(&self, id: Arc<str>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        log::info!("Unlocking the Avatar id '{id}' again, as requested.");
+        self.stop_reassert();
+        *self.applied_params.lock() = None;
+        self.unlock(id)
+    }
+
+    /// Unlocks the Avatar, stores the outcome in the [LastUnlockHandle] and reports it to the Ui for the Unlock History.
+    fn unlock(&self, id: Arc<str>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        *self.last_unlocked.lock() = Some(id.clone());
+        let clone = self.clone();
+        Box::pin(async move {
+            let last_unlock = clone.last_unlock.clone();
+            let last_unlocked = clone.last_unlocked.clone();
+            let notices = clone.notices.clone();
+            let outcome = clone.handle_avatar_change(id.clone()).await;
+            if !matches!(outcome, UnlockOutcome::Applied { .. }) {
+                let mut last_unlocked = last_unlocked.lock();
+                if last_unlocked.as_ref() == Some(&id) {
+                    *last_unlocked = None;
                 }
-                let mut decoded = match vecu8_to_str(v){
-                    Some(v) => v,
-                    None => {
-                        log::error!("Failed to decode the Avatar id '{}' Key file. Refusing to unlock.", id);
-                        return;
-                    }
-                };
+            }
+            match outcome {
+                UnlockOutcome::Applied { .. } => crate::metrics::METRICS.unlock_applied(),
+                UnlockOutcome::NoKey => {},
+                UnlockOutcome::ReadFailed | UnlockOutcome::DecodeFailed | UnlockOutcome::Corrupt => crate::metrics::METRICS.unlock_failed(),
+            }
+            log::debug!("Unlock outcome for the Avatar id '{id}': {outcome}");
+            let _ = notices.send(OscNotice::Unlocked { id: id.clone(), outcome });
+            *last_unlock.lock() = Some((id, outcome));
+        })
+    }
+
+    /// Forwards a packet, that was sent to VRChat, to the Multiplexer (if configured).
+    async fn forward_to_multiplexer(&self, packet: &OscPacket) {
+        if let Some(multiplexer) = &self.multiplexer {
+            multiplexer.forward(packet).await;
+        }
+    }
+
+    /// Updates the state of a parameter in the [AppliedParamsHandle].
+    fn set_param_state(&self, addr: &str, state: ParamState) {
+        let name = addr.strip_prefix(&*self.param_prefix).unwrap_or(addr);
+        if let Some(applied) = self.applied_params.lock().as_mut() {
+            if let Some((_, param_state)) = applied.params.get_mut(name) {
+                *param_state = state;
+            }
+        }
+    }
+
+    /// Reports, that the Keys Folder can't be accessed (anymore).
+    /// The user is only notified once, until the Keys Folder is accessible again.
+    fn report_keys_folder_unavailable(&self, id: &str, err: String) {
+        if self.keys_folder_unavailable.swap(true, Ordering::Relaxed) {
+            log::debug!("Can't look up the key for avatar ID {id}, because the Keys Folder is still not accessible: {err}");
+            return;
+        }
+        log::error!("The Keys Folder is no longer accessible. No Avatar will be unlocked until it is accessible again: {err}");
+        let _ = self.notices.send(OscNotice::KeysFolderUnavailable {
+            path: self.path.to_path_buf(),
+            error: err,
+        });
+    }
+
+    /// Marks the Keys Folder as accessible again, after [DexOscHandler::report_keys_folder_unavailable].
+    fn keys_folder_available(&self) {
+        if self.keys_folder_unavailable.swap(false, Ordering::Relaxed) {
+            log::info!("The Keys Folder is accessible again.");
+        }
+    }
+
+    async fn handle_avatar_change(self, id: Arc<str>) -> UnlockOutcome {
+        let settings = self.unlock_settings(load_avatar_overrides(&self.path, &id).await.as_ref());
+        let key_name = self.key_name(&id);
+        let parsed = match &self.manifest {
+            Some(manifest) => match self.manifest_key(manifest, key_name) {
+                Ok(key) => parse_key_text(&key),
+                Err(outcome) => return outcome,
+            },
+            None => match self.read_key_file(key_name).await {
+                Ok(bytes) => parse_key_file(bytes),
+                Err(outcome) => return outcome,
+            },
+        };
+        match parsed {
+            Ok(params) => self.apply_key(&id, params, &settings).await,
+            Err(KeyParseError::UnevenEntries(params)) => {
+                log::error!("Found an uneven amount of keys in the Avatar id '{id}' key file.\n This is highly unusual and suggests corruption in the key file. \n You should suggest reporting this in the Discord for DexProtect.\n All bets are off from here on out, if unlocking will actually work.");
+                self.apply_key(&id, params, &settings).await;
+                UnlockOutcome::Corrupt
+            }
+            Err(e) => {
+                log::error!("Failed to parse the Key for the Avatar id '{id}'. Refusing to unlock: {e}");
+                e.outcome()
+            }
+        }
+    }
+
+    /// Returns the settings for unlocking an Avatar, with its overrides applied over the global settings.
+    fn unlock_settings(&self, overrides: Option<&AvatarOverrides>) -> UnlockSettings {
+        let overrides = overrides.cloned().unwrap_or_default();
+        UnlockSettings {
+            use_bundles: overrides.use_bundles.unwrap_or(self.dex_use_bundles),
+            bundle_delay: overrides.bundle_delay_ms.map_or(self.dex_bundle_delay, Duration::from_millis),
+            send_spacing: overrides.send_spacing_ms.map_or(self.dex_send_spacing, Duration::from_millis),
+            wait: Duration::from_millis(overrides.wait_ms.unwrap_or(DEX_KEY_WAIT_MS)),
+        }
+    }
+
+    /// Returns the name of the Key for an Avatar.
+    /// This is the id itself, unless a pattern in the Key Mapping matches it.
+    fn key_name<'b>(&'b self, id: &'b str) -> &'b str {
+        match self.key_mapping.as_ref().and_then(|mapping| mapping.key_for(id)) {
+            Some(key) => {
+                log::info!("The Avatar id '{id}' is mapped to the Key '{key}'.");
+                key
+            }
+            None => id,
+        }
+    }
+
+    /// Looks up the Key for an Avatar in the Key Manifest, falling back to the default Key.
+    fn manifest_key(&self, manifest: &KeyManifest, id: &str) -> Result<String, UnlockOutcome> {
+        if let Some(key) = manifest.get(id) {
+            log::debug!("Found the key for avatar ID {id} in the Key Manifest.");
+            return Ok(key.clone());
+        }
+        if let Some(default_key) = &self.default_key {
+            if let Some(key) = manifest.get(default_key.as_ref()) {
+                log::info!("No key for avatar ID {id} in the Key Manifest. Using the default Key '{default_key}'.");
+                return Ok(key.clone());
+            }
+        }
+        log::info!("No key for avatar ID {id} in the Key Manifest, not unlocking.");
+        Err(UnlockOutcome::NoKey)
+    }
+
+    /// Reads the Key file for an Avatar, falling back to the default Key.
+    async fn read_key_file(&self, id: &str) -> Result<Vec<u8>, UnlockOutcome> {
+        let Some(path) = self.key_path(id) else {
+            log::warn!("The Key name '{id}' is not a plain file name, so it could point outside the Keys Folder. Not unlocking.");
+            return Err(UnlockOutcome::NoKey);
+        };
+        let default_path = self.default_key.as_deref().and_then(|default_key| {
+            let default_path = self.key_path(default_key);
+            if default_path.is_none() {
+                log::error!("The default Key name '{default_key}' is not a plain file name. Not using it.");
+            }
+            default_path
+        });
+        let (path, read) = match (tokio::fs::read(path.as_path()).await, default_path) {
+            (Err(e), Some(default_path)) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("No key detected for avatar ID {id} at {}. Trying the default Key at {}.", path.display(), default_path.display());
+                let read = tokio::fs::read(default_path.as_path()).await;
+                (default_path, read)
+            }
+            (read, _) => (path, read),
+        };
+        match read{
+            Ok(potentially_decrypted) => {
+                self.keys_folder_available();
+                let hash = key_file_hash(&potentially_decrypted);
+                log::debug!("Read {} bytes from the Key file '{}' with the SHA-256 hash {}...", potentially_decrypted.len(), path.display(), hash.get(..8).unwrap_or(&hash));
                 #[cfg(all(debug_assertions, feature="debug_log"))]
-                log::debug!("Decoded Avatar id '{}' Key file: '{}'", id, decoded);
-                let mut key:Vec<rosc::OscPacket> = Vec::new();
-                decoded = decoded.replace(",", ".");
+                log::debug!("Full SHA-256 hash of the Key file '{}': {}", path.display(), hash);
+                Ok(potentially_decrypted)
+            }
+            Err(e) => {
+                //Not just NotFound, but any error might be caused by the Keys Folder going away (e.g. a disconnected network drive).
+                if let Some(err) = keys_folder_error(&self.path, tokio::fs::metadata(&self.path).await) {
+                    self.report_keys_folder_unavailable(id, err);
+                    return Err(UnlockOutcome::ReadFailed);
+                }
+                self.keys_folder_available();
+                if e.kind() == std::io::ErrorKind::NotFound{
+                    log::info!("No key detected for avatar ID {id} at {}, not unlocking.\nAssuming that the following error actually means the file doesn't exist and not just a directory along the way:\n {e}", path.display());
+                    return Err(UnlockOutcome::NoKey);
+                }
+                log::error!("Failed to read the Avatar id '{}' from the Avatar Folder: {}.", id, e);
+                Err(UnlockOutcome::ReadFailed)
+            }
+        }
+    }
+
+    /// Sends the parameters of a parsed Key.
+    async fn apply_key(&self, id: &str, parsed: Vec<(String, KeyNumber)>, settings: &UnlockSettings) -> UnlockOutcome {
+        let mut key:Vec<rosc::OscPacket> = Vec::new();
+        let mut params = HashMap::with_capacity(parsed.len());
+        //The addresses of the parameters, that could not be sent.
+        //Queued parameters are not included, since the CoalescingSender sends them later.
+        let mut failed = Vec::new();
+        //The single parameters, that are sent together once all of them are collected.
+        let mut sends = Vec::new();
+        let mut first_spaced_send = true;
+        //The parameters, that are sent again every `dex_reassert_interval`.
+        let mut baseline = Vec::new();
+        //The amount of parameters, that weren't sent, because VRChat already has their value.
+        let mut skipped = 0usize;
+        for (name, amount) in parsed {
+            if !self.param_filter.allows(&name) {
+                log::info!("Not setting the parameter '{name}' of the Avatar id '{id}', because of the parameter filter.");
+                continue;
+            }
+            let addr = format!("{}{name}", self.param_prefix);
+            if self.dex_reassert_interval.is_some() {
+                baseline.push(OscPacket::Message(OscMessage{
+                    addr: addr.clone(),
+                    args: vec![amount.to_osc(self.dex_high_precision)],
+                }));
+            }
+            if self.only_send_deltas && observed_matches(&self.observed, &addr, &amount.to_osc(self.dex_high_precision)) {
                 #[cfg(all(debug_assertions, feature="debug_log"))]
-                log::debug!("Decoded Avatar id '{}' post processed Key file: '{}'", id, decoded);
-                let split:Vec<&str> = decoded.split("|").collect();
-                let len = if split.len()%2 == 0 {
-                    split.len()
-                }else{
-                    log::error!("Found an uneven amount of keys in the Avatar id '{id}' key file.\n This is highly unusual and suggests corruption in the key file. \n You should suggest reporting this in the Discord for DexProtect.\n All bets are off from here on out, if unlocking will actually work.");
-                    split.len()-1
+                log::trace!("Not sending '{addr}', since it already has the value from the Key.");
+                skipped += 1;
+                continue;
+            }
+            //Skipped parameters are neither sent nor echoed by VRChat, so only track the sent ones.
+            params.insert(addr.clone(), amount.as_f32());
+            if settings.use_bundles {
+                key.push(OscPacket::Message(OscMessage{
+                    addr,
+                    args: vec![amount.to_osc(self.dex_high_precision)],
+                }));
+            }else if let Some(coalescer) = &self.coalescer {
+                let message = OscMessage{
+                    addr,
+                    args: vec![amount.to_osc(self.dex_high_precision)],
                 };
-                let mut i = 0;
-                let mut params = HashMap::with_capacity(len);
-                while i < len {
-                    let float = split[i];
-                    #[cfg(all(debug_assertions, feature="debug_log"))]
-                    log::trace!("Decoding float: {}", float);
-                    let whole:u32;
-                    let part:u32;
-                    let part_digits:u32;
-                    if let Some(index) = float.find("."){
-                        let (whole_str, part_str) = float.split_at(index);
-                        let mut part_string = part_str.to_string();
-                        part_string.remove(0);
-                        #[cfg(all(debug_assertions, feature="debug_log"))]
-                        log::trace!("Decoding float: {}, whole: {}, part:{}", float,whole_str, part_string);
-                        whole = match decode_number(whole_str, &id){
-                            Some(v) => v,
-                            None => return
-                        };
-                        part = match decode_number(part_string.as_str(), &id){
-                            Some(v) => v,
-                            None => return
-                        };
-                        part_digits = part_string.len() as u32;
-                    }else {
-                        whole = match decode_number(float, &id){
-                            Some(v) => v,
-                            None => return
-                        };
-                        part = 0;
-                        part_digits = 0;
+                //The CoalescingSender only sends the latest value anyway, so the Multiplexer can get every value right away.
+                self.forward_to_multiplexer(&OscPacket::Message(message.clone())).await;
+                coalescer.queue(message);
+            }else {
+                let packet = OscPacket::Message(OscMessage{
+                    addr: addr.clone(),
+                    args: vec![amount.to_osc(self.dex_high_precision)],
+                });
+                if settings.send_spacing.is_zero() {
+                    sends.push((addr, packet));
+                    continue;
+                }
+                match self.osc.send_message_with_logs(&packet) {
+                    Ok(v) => {
+                        if !first_spaced_send {
+                            tokio::time::sleep(settings.send_spacing).await;
+                        }
+                        first_spaced_send = false;
+                        if v.await.0.is_ok() {
+                            self.forward_to_multiplexer(&packet).await;
+                        } else {
+                            failed.push(addr);
+                        }
                     }
-                    let amount = whole as f32 + part as f32/(10.0f32.powf(part_digits as f32));
-                    params.insert(format!("/avatar/parameters/{}", split[i+1]), amount);
-                    if self.dex_use_bundles {
-                        key.push(OscPacket::Message(OscMessage{
-                            addr: format!("/avatar/parameters/{}", split[i+1]),
-                            args: vec![OscType::Float(amount)],
-                        }));
-                    }else {
-                        if let Ok(v) = self.osc.send_message_with_logs(&OscPacket::Message(OscMessage{
-                            addr: format!("/avatar/parameters/{}", split[i+1]),
-                            args: vec![OscType::Float(amount)],
-                        })) {
-                            let _ = v.await;
-                        };
+                    Err(_) => failed.push(addr),
+                }
+            }
+        }
+        if !sends.is_empty() {
+            let start = Instant::now();
+            let count = sends.len();
+            let results = self.osc.send_all(sends.iter().map(|(_, packet)| packet.clone())).await;
+            log::debug!("Sending {count} parameters for the Avatar id '{id}' took {}ms.", start.elapsed().as_millis());
+            for ((addr, packet), result) in sends.into_iter().zip(results) {
+                match result {
+                    Ok(_) => self.forward_to_multiplexer(&packet).await,
+                    Err(e) => {
+                        log::error!("Failed to send the parameter '{addr}': {e}");
+                        failed.push(addr);
                     }
-                    i+=2;
                 }
-                if self.dex_use_bundles {
-                    log::warn!("You are using Osc Bundles. This can cause issues with newer style keys and VRChat.\nSee https://feedback.vrchat.com/bug-reports/p/inconsistent-handling-of-osc-packets-inside-osc-bundles-and-osc-packages .");
-                    if let Ok(v) = self.osc.send_message_with_logs(&OscPacket::Bundle(OscBundle{
-                        timetag: rosc::OscTime{
-                            seconds: 0,
-                            fractional: 1
-                        },
-                        content: key
-                    })){
-                        let _ = v.await;
-                    };
+            }
+        }
+        if settings.use_bundles && !key.is_empty() {
+            log::warn!("You are using Osc Bundles. This can cause issues with newer style keys and VRChat.\nSee https://feedback.vrchat.com/bug-reports/p/inconsistent-handling-of-osc-packets-inside-osc-bundles-and-osc-packages .");
+            let packet = OscPacket::Bundle(OscBundle{
+                timetag: bundle_timetag(settings.bundle_delay),
+                content: key
+            });
+            let sent = match self.osc.send_message_with_logs(&packet){
+                Ok(v) => v.await.0.is_ok(),
+                Err(_) => false,
+            };
+            if sent {
+                self.forward_to_multiplexer(&packet).await;
+            } else {
+                failed.extend(params.keys().cloned());
+            }
+        }
+        if skipped > 0 {
+            log::info!("Skipped {skipped} parameters of the Avatar id '{id}', since they already have the value from the Key.");
+        }
+        log::info!("A Key for the Avatar id '{}' was detected and decoded. The Avatar has been attempted to be Unlocked.", id);
+        params.shrink_to_fit();
+        let params_clone = self.params.clone();
+        let wait = settings.wait;
+        let jh = tokio::task::spawn(async move {
+            tokio::time::sleep(wait).await;
+            let params = params_clone.lock();
+            let params = &*params;
+            match params {
+                None => {
+                    log::warn!("Unexpected None variant in the Avatar Key application. This is unexpected and might be a bug.");
+                    log::trace!("All Avatar Keys have been supplied after {}ms.", wait.as_millis())
                 }
-                log::info!("A Key for the Avatar id '{}' was detected and decoded. The Avatar has been attempted to be Unlocked.", id);
-                params.shrink_to_fit();
-                let params_clone = self.params.clone();
-                let jh = tokio::task::spawn(async move {
-                    tokio::time::sleep(Duration::from_millis(DEX_KEY_WAIT_MS)).await;
-                    let params = params_clone.lock();
-                    let params = &*params;
-                    match params {
-                        None => {
-                            log::warn!("Unexpected None variant in the Avatar Key application. This is unexpected and might be a bug.");
-                            log::trace!("All Avatar Keys have been supplied after {DEX_KEY_WAIT_DESC}.")
+                Some((_, params)) => {
+                    if params.is_empty() {
+                        log::trace!("All Avatar Keys have been supplied after {}ms.", wait.as_millis())
+                    } else {
+                        crate::metrics::METRICS.unlock_timed_out();
+                        #[cfg(all(debug_assertions, feature="debug_log"))]
+                        {
+                            log::error!("The Avatar Key has not been fully applied after {}ms. There are {} avatar keys, that were not applied. {params:?}", wait.as_millis(), params.len());
                         }
-                        Some((_, params)) => {
-                            if params.is_empty() {
-                                log::trace!("All Avatar Keys have been supplied after {DEX_KEY_WAIT_DESC}.")
-                            } else {
-                                #[cfg(all(debug_assertions, feature="debug_log"))]
-                                {
-                                    log::error!("The Avatar Key has not been fully applied after {DEX_KEY_WAIT_DESC}. There are {} avatar keys, that were not applied. {params:?}", params.len());
-                                }
-                                #[cfg(not(all(debug_assertions, feature="debug_log")))]
-                                {
-                                    log::error!("The Avatar Key has not been fully applied after {DEX_KEY_WAIT_DESC}. There are {} avatar keys, that were not applied.", params.len());
-                                }
-                            }
+                        #[cfg(not(all(debug_assertions, feature="debug_log")))]
+                        {
+                            log::error!("The Avatar Key has not been fully applied after {}ms. There are {} avatar keys, that were not applied.", wait.as_millis(), params.len());
                         }
                     }
-                });
-                *self.params.lock() = Some((jh.abort_handle(), params));
+                }
             }
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::NotFound{
-                    log::info!("No key detected for avatar ID {id} at {}, not unlocking.\nAssuming that the following error actually means the file doesn't exist and not just a directory along the way:\n {e}", path.display());
+        });
+        let count = params.len();
+        *self.applied_params.lock() = Some(AppliedParams {
+            id: Arc::from(id),
+            sent: Instant::now(),
+            wait,
+            params: params.iter()
+                .map(|(addr, value)| {
+                    let state = if failed.contains(addr) {ParamState::SendFailed} else {ParamState::Pending};
+                    (addr.strip_prefix(&*self.param_prefix).unwrap_or(addr).to_string(), (*value, state))
+                })
+                .collect(),
+        });
+        if !failed.is_empty() {
+            log::error!("{} of {count} parameters of the Avatar id '{id}' failed to send and will not be applied: {failed:?}", failed.len());
+            //VRChat can't confirm parameters it never received, so don't wait for them.
+            for addr in &failed {
+                params.remove(addr);
+            }
+        }
+        //The timeout check of the previous Key would look at these parameters otherwise.
+        if let Some((previous, _)) = self.params.lock().replace((jh.abort_handle(), params)) {
+            previous.abort();
+        }
+        self.start_reassert(Arc::from(id), baseline);
+        UnlockOutcome::Applied { count, failed: failed.len() }
+    }
+
+    /// Sends `baseline` again every `dex_reassert_interval`, in case VRChat reset some of the parameters.
+    /// This stops, once the Avatar changes or the OSC Thread stops.
+    fn start_reassert(&self, id: Arc<str>, baseline: Vec<OscPacket>) {
+        let Some(interval) = self.dex_reassert_interval else {
+            return;
+        };
+        //Only a weak reference, so that this doesn't keep the send socket alive after a disconnect.
+        let osc = Arc::downgrade(&self.osc);
+        let last_avatar = self.last_avatar.clone();
+        let observed = self.only_send_deltas.then(|| self.observed.clone());
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                ticker.tick().await;
+                if last_avatar.lock().as_deref() != Some(&*id) {
+                    log::debug!("Not sending the parameters of the Avatar id '{id}' again, since the Avatar changed.");
                     return;
                 }
-                log::error!("Failed to read the Avatar id '{}' from the Avatar Folder: {}.", id, e);
+                let Some(osc) = osc.upgrade() else {
+                    return;
+                };
+                log::debug!("Sending the {} parameters of the Avatar id '{id}' again.", baseline.len());
+                let sends = baseline.iter()
+                    .filter(|packet| match (&observed, packet) {
+                        (Some(observed), OscPacket::Message(message)) => !message.args.first().is_some_and(|value| observed_matches(observed, &message.addr, value)),
+                        _ => true,
+                    })
+                    .filter_map(|packet| osc.send_message_with_logs(packet).ok())
+                    .collect::<Vec<_>>();
+                futures::future::join_all(sends).await;
             }
+        });
+        if let Some(previous) = self.reassert.lock().replace(task.abort_handle()) {
+            previous.abort();
         }
+    }
+
+    /// Stops sending the parameters of the last Key again.
+    fn stop_reassert(&self) {
+        if let Some(reassert) = self.reassert.lock().take() {
+            reassert.abort();
+        }
+    }
+}
+
+/// Settings for a single Avatar, that override the global settings.
+///
+/// These are read from `<avatar id>.toml` in the Keys Folder, if that exists. Every setting is optional, e.g.:
+/// ```toml
+/// use_bundles = true
+/// bundle_delay_ms = 100
+/// send_spacing_ms = 20
+/// wait_ms = 3000
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AvatarOverrides {
+    use_bundles: Option<bool>,
+    bundle_delay_ms: Option<u64>,
+    send_spacing_ms: Option<u64>,
+    /// How long VRChat gets to confirm all parameters, in milliseconds.
+    wait_ms: Option<u64>,
+}
+
+/// The settings used for a single unlock.
+struct UnlockSettings {
+    use_bundles: bool,
+    bundle_delay: Duration,
+    send_spacing: Duration,
+    wait: Duration,
+}
+
+/// Loads the [AvatarOverrides] for an Avatar from the Keys Folder.
+/// Returns `None`, if there are none or they are invalid, in which case the global settings are used.
+async fn load_avatar_overrides(folder: &std::path::Path, id: &str) -> Option<AvatarOverrides> {
+    let mut path = key_path(folder, id)?;
+    path.set_extension("toml");
+    let text = match tokio::fs::read_to_string(&path).await {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::error!("Failed to read the overrides for the Avatar id '{id}' at '{}'. Using the global settings.\n Error: {e}", path.display());
+            return None;
+        }
+    };
+    match toml::from_str(&text) {
+        Ok(overrides) => {
+            log::info!("Using the overrides for the Avatar id '{id}' from '{}': {overrides:?}", path.display());
+            Some(overrides)
+        }
+        Err(e) => {
+            log::error!("The overrides for the Avatar id '{id}' at '{}' are invalid. Using the global settings.\n Error: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Maps Avatar ids to their decrypted Keys.
+type KeyManifest = HashMap<Box<str>, String>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestError {
+    #[error("Failed to read the Key Manifest '{}': {error}", path.display())]
+    Read{
+        path: std::path::PathBuf,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("The Key Manifest '{}' is not valid text.", .0.display())]
+    Decode(std::path::PathBuf),
+    #[error("Line {line} of the Key Manifest is not of the form 'avatar id=key'.")]
+    InvalidLine{
+        line: usize,
+    },
+}
+
+/// Loads a Key Manifest, which contains the Keys of many Avatars in a single file.
+///
+/// The whole file is decrypted like a Key file (see [decrpyt]).
+/// The decrypted text has one `avatar id=key` pair per line. Empty lines are ignored.
+async fn load_manifest(path: &std::path::Path) -> Result<KeyManifest, ManifestError> {
+    let file = tokio::fs::read(path).await
+        .map_err(|error| ManifestError::Read { path: path.to_path_buf(), error })?;
+    log::info!("Read {} bytes from the Key Manifest '{}'.", file.len(), path.display());
+    let (v, err) = decrpyt(maybe_gunzip(file));
+    match err {
+        None => {},
+        Some(DecryptError::NoKeys) => log::warn!("{}", DecryptError::NoKeys),
+        Some(err) => log::error!("Failed to decrypt the Key Manifest. Trying to treat it as unencrypted.\n Error: {err}"),
+    }
+    let v = maybe_gunzip(v);
+    let decoded = vecu8_to_str(v).ok_or_else(|| ManifestError::Decode(path.to_path_buf()))?;
+    let mut manifest = HashMap::new();
+    for (i, line) in decoded.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (id, key) = line.split_once('=').ok_or(ManifestError::InvalidLine { line: i + 1 })?;
+        if manifest.insert(Box::from(id.trim()), key.trim().to_string()).is_some() {
+            log::warn!("The Avatar id '{}' is in the Key Manifest more than once. Using the last Key.", id.trim());
+        }
+    }
+    log::info!("Loaded {} Keys from the Key Manifest.", manifest.len());
+    Ok(manifest)
+}
+
+/// Maps Avatar id patterns to Key names.
+struct KeyMapping {
+    patterns: globset::GlobSet,
+    /// The Key name for every pattern in `patterns`, in the same order.
+    keys: Vec<Box<str>>,
+}
 
+impl KeyMapping {
+    /// Returns the Key of the first pattern (in the order of the file), that matches the id.
+    fn key_for(&self, id: &str) -> Option<&str> {
+        self.patterns.matches(id).into_iter().min().map(|i| self.keys[i].as_ref())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyMappingError {
+    #[error("Failed to read the Key Mapping '{}': {error}", path.display())]
+    Read{
+        path: std::path::PathBuf,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("Line {line} of the Key Mapping is not of the form 'avatar id pattern=key'.")]
+    InvalidLine{
+        line: usize,
+    },
+    #[error("Line {line} of the Key Mapping has an invalid pattern: {error}")]
+    InvalidPattern{
+        line: usize,
+        #[source]
+        error: globset::Error,
+    },
+    #[error("Failed to build the patterns of the Key Mapping: {0}")]
+    Build(#[source] globset::Error),
+}
+
+/// Loads a Key Mapping, which maps Avatar id patterns (e.g. `avtr_abc*`) to Key names.
+///
+/// The file has one `pattern=key` pair per line. The key may have the `.key` extension.
+/// Empty lines and lines starting with `#` are ignored.
+async fn load_key_mapping(path: &std::path::Path) -> Result<KeyMapping, KeyMappingError> {
+    let text = tokio::fs::read_to_string(path).await
+        .map_err(|error| KeyMappingError::Read { path: path.to_path_buf(), error })?;
+    let mut builder = globset::GlobSetBuilder::new();
+    let mut keys = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (pattern, key) = line.split_once('=').ok_or(KeyMappingError::InvalidLine { line: i + 1 })?;
+        let glob = globset::Glob::new(pattern.trim())
+            .map_err(|error| KeyMappingError::InvalidPattern { line: i + 1, error })?;
+        builder.add(glob);
+        let key = key.trim();
+        keys.push(Box::from(key.strip_suffix(".key").unwrap_or(key)));
+    }
+    let patterns = builder.build().map_err(KeyMappingError::Build)?;
+    log::info!("Loaded {} patterns from the Key Mapping.", keys.len());
+    Ok(KeyMapping { patterns, keys })
+}
+
+/// Returns the hex encoded SHA-256 hash of a Key file.
+/// This identifies the file in the logs, without exposing the Key.
+fn key_file_hash(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    use std::fmt::Write;
+    sha2::Sha256::digest(bytes).iter().fold(String::with_capacity(64), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+/// Checks, whether a parameter value echoed by VRChat matches the value from the Key.
+/// VRChat echoes Bool and Int parameters with their own types, so those are compared by their numeric value.
+/// Returns None, if the value isn't numeric.
+fn param_value_matches(value: &OscType, expected: f32) -> Option<bool> {
+    let value = param_as_f32(value)?;
+    Some((value - expected).abs() <= PARAM_FLOAT_TOLERANCE)
+}
+
+/// Returns the numeric value of a parameter. Bools are 0 or 1.
+/// Returns None, if the value isn't numeric.
+fn param_as_f32(value: &OscType) -> Option<f32> {
+    match value {
+        OscType::Float(f) => Some(*f),
+        OscType::Double(d) => Some(*d as f32),
+        OscType::Int(i) => Some(*i as f32),
+        OscType::Bool(b) => Some(if *b {1.0} else {0.0}),
+        _ => None,
+    }
+}
+
+/// The last value received from VRChat per parameter address.
+type ObservedParams = Arc<Mutex<HashMap<String, f32>>>;
+
+/// Checks, whether VRChat's last value of the parameter at `addr` is `value`, so that sending it would change nothing.
+/// Parameters, that were never received, never match.
+fn observed_matches(observed: &ObservedParams, addr: &str, value: &OscType) -> bool {
+    observed.lock().get(addr).is_some_and(|observed| param_value_matches(value, *observed) == Some(true))
+}
+
+/// Checks, whether a trigger parameter was set (`true`, or a non zero number).
+/// Returns None, if the value isn't a Bool or a number.
+fn param_is_truthy(value: &OscType) -> Option<bool> {
+    match value {
+        OscType::Bool(b) => Some(*b),
+        OscType::Int(i) => Some(*i != 0),
+        OscType::Float(f) => Some(f.abs() > PARAM_FLOAT_TOLERANCE),
+        _ => None,
     }
 }
 
@@ -269,43 +1032,463 @@ enum DecryptError{
     InvalidLength(#[from] aes::cipher::InvalidLength),
     #[error("DecryptError:UnpadError({0})")]
     UnpadError(#[from] aes::cipher::block_padding::UnpadError),
+    #[error("This build has no decryption Keys. Only unencrypted legacy Keys will work.")]
+    NoKeys,
 }
 
 //Sorry for those people wanting to build this themselves.
-//If I were to commit the Key and IV, it would defeat the entire purpose.
+//If I were to commit the Keys and IVs, it would defeat the entire purpose.
 //Consider this a crackme challenge, under the terms that you do not redistribute those keys.
+//dex_key.rs provides `KEY` and `IV`.
+//With the `extra_decryption_keys` feature it also provides `EXTRA_KEYS`, the (Key, IV) pairs DexProtect used before.
 #[cfg(not(feature = "no_decryption_keys"))]
 include!("dex_key.rs");
+/// The (Key, IV) pairs, that are tried in order.
+#[cfg(not(feature = "no_decryption_keys"))]
+const KEYS: &[([u8; 32], [u8; 16])] = &[(KEY, IV)];
+//Without the keys nothing is decrypted, instead of "decrypting" with all zeros to garbage.
 #[cfg(feature = "no_decryption_keys")]
-const KEY: [u8; 32] = [0; 32];
-#[cfg(feature = "no_decryption_keys")]
-const IV: [u8;16] = [0; 16];
+const KEYS: &[([u8; 32], [u8; 16])] = &[];
+#[cfg(not(all(feature = "extra_decryption_keys", not(feature = "no_decryption_keys"))))]
+const EXTRA_KEYS: &[([u8; 32], [u8; 16])] = &[];
+
+/// Whether this build can decrypt Keys. Without decryption keys only unencrypted legacy Keys work.
+pub const DECRYPTION_KEYS_AVAILABLE: bool = !KEYS.is_empty() || !EXTRA_KEYS.is_empty();
+
+/// Whether Keys can be decrypted right now, either with the compiled in keys or a user supplied one.
+pub fn decryption_keys_available() -> bool {
+    DECRYPTION_KEYS_AVAILABLE || user_decryption_key().is_some()
+}
+
+/// The environment variable, that [load_user_decryption_key_from_env] reads the hex encoded Key from.
+#[cfg(feature = "user_decryption_key")]
+pub const USER_KEY_ENV: &str = "DEX_PROTECT_KEY";
+/// The environment variable, that [load_user_decryption_key_from_env] reads the hex encoded IV from.
+#[cfg(feature = "user_decryption_key")]
+pub const USER_IV_ENV: &str = "DEX_PROTECT_IV";
+
+/// A Key and IV supplied at runtime. This is tried before [KEYS].
+#[cfg(feature = "user_decryption_key")]
+static USER_KEY: std::sync::RwLock<Option<([u8; 32], [u8; 16])>> = std::sync::RwLock::new(None);
+
+#[cfg(feature = "user_decryption_key")]
+fn user_decryption_key() -> Option<([u8; 32], [u8; 16])> {
+    USER_KEY.read().ok().and_then(|key| *key)
+}
+
+#[cfg(not(feature = "user_decryption_key"))]
+fn user_decryption_key() -> Option<([u8; 32], [u8; 16])> {
+    None
+}
 
+/// Sets (or with `None` removes) the Key and IV, that is tried before the compiled in keys.
+/// This is meant for testing builds without the bundled keys with your own Key.
+#[cfg(feature = "user_decryption_key")]
+pub fn set_user_decryption_key(key: Option<([u8; 32], [u8; 16])>) {
+    log::info!("{} the user supplied decryption Key.", if key.is_some() {"Setting"} else {"Removing"});
+    match USER_KEY.write() {
+        Ok(mut user_key) => *user_key = key,
+        Err(e) => log::error!("Failed to set the user supplied decryption Key: {e}"),
+    }
+}
+
+#[cfg(feature = "user_decryption_key")]
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum UserKeyError {
+    #[error("The {what} is not valid hex.")]
+    InvalidHex{
+        what: &'static str,
+    },
+    #[error("The {what} must be {expected} bytes ({} hex digits), but is {actual} bytes.", expected * 2)]
+    WrongLength{
+        what: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// Parses a hex encoded Key (32 bytes) and IV (16 bytes). Whitespace is ignored.
+#[cfg(feature = "user_decryption_key")]
+pub fn parse_user_decryption_key(key: &str, iv: &str) -> Result<([u8; 32], [u8; 16]), UserKeyError> {
+    fn parse_hex<const N: usize>(hex: &str, what: &'static str) -> Result<[u8; N], UserKeyError> {
+        let hex = hex.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(UserKeyError::InvalidHex { what });
+        }
+        let bytes = (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| UserKeyError::InvalidHex { what })?;
+        let actual = bytes.len();
+        bytes.try_into().map_err(|_| UserKeyError::WrongLength { what, expected: N, actual })
+    }
+    Ok((parse_hex(key, "Key")?, parse_hex(iv, "IV")?))
+}
 
+/// Sets the user supplied decryption Key from [USER_KEY_ENV] and [USER_IV_ENV], if both are set.
+#[cfg(feature = "user_decryption_key")]
+pub fn load_user_decryption_key_from_env() {
+    let (Ok(key), Ok(iv)) = (std::env::var(USER_KEY_ENV), std::env::var(USER_IV_ENV)) else {
+        return;
+    };
+    match parse_user_decryption_key(&key, &iv) {
+        Ok(key) => set_user_decryption_key(Some(key)),
+        Err(e) => log::error!("Ignoring the decryption Key from {USER_KEY_ENV} and {USER_IV_ENV}: {e}"),
+    }
+}
+
+/// Decrypts a Key file with the first of [KEYS] and [EXTRA_KEYS], that results in valid padding and text.
+/// A user supplied decryption Key (see `set_user_decryption_key`) is tried first.
+/// If none of them results in text, the first result with valid padding is returned.
+/// If there are no keys, the file is returned unchanged with [DecryptError::NoKeys].
 fn decrpyt(file: Vec<u8>) -> (Vec<u8>, Option<DecryptError>) {
-    match cbc::Decryptor::<aes::Aes256>::new_from_slices(
-            &KEY,
-            &IV
-        ).map_err(DecryptError::from)
-        .and_then(|aes|aes.decrypt_padded_vec_mut::<cbc::cipher::block_padding::Pkcs7>(file.as_slice()).map_err(DecryptError::from)) {
-        Ok(v) => (v, None),
-        Err(err) => (file, Some(err)),
+    decrypt_with_keys(file, user_decryption_key(), KEYS.iter().chain(EXTRA_KEYS))
+}
+
+/// Like [decrpyt], but with the given user supplied Key and compiled in Keys.
+fn decrypt_with_keys<'a>(file: Vec<u8>, user_key: Option<([u8; 32], [u8; 16])>, keys: impl IntoIterator<Item = &'a ([u8; 32], [u8; 16])>) -> (Vec<u8>, Option<DecryptError>) {
+    let mut padded = None;
+    let mut error = None;
+    let keys = user_key.iter().map(|key| (None, key))
+        .chain(keys.into_iter().enumerate().map(|(i, key)| (Some(i), key)));
+    for (i, (key, iv)) in keys {
+        match decrypt_with(&file, key, iv) {
+            Ok(v) if vecu8_to_str(v.clone()).is_some() => {
+                match i {
+                    Some(i) => log::debug!("Decrypted with the decryption Key number {i}."),
+                    None => log::debug!("Decrypted with the user supplied decryption Key."),
+                }
+                return (v, None);
+            }
+            Ok(v) => {
+                padded.get_or_insert(v);
+            }
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        }
+    }
+    match (padded, error) {
+        (Some(v), _) => (v, None),
+        (None, Some(err)) => (file, Some(err)),
+        (None, None) => (file, Some(DecryptError::NoKeys)),
     }
 }
 
-fn unrecognized_avatar_change(arg:&Vec<OscType>){
-    log::error!("Received a OSC Message with the address /avatar/change but the first argument was not a string.\n This is unexpected and there might have been a change to VRChat's OSC messages.\n Extraneous Argument: {:#?}", arg);
+fn decrypt_with(file: &[u8], key: &[u8; 32], iv: &[u8; 16]) -> Result<Vec<u8>, DecryptError> {
+    let aes = cbc::Decryptor::<aes::Aes256>::new_from_slices(key, iv)?;
+    Ok(aes.decrypt_padded_vec_mut::<cbc::cipher::block_padding::Pkcs7>(file)?)
 }
 
-fn decode_number(number:&str, id:&str) -> Option<u32> {
-    match u32::from_str(number){
-        Ok(v) => Some(v),
+/// Returns the timetag for a bundle, that should be applied `delay` from now.
+/// A zero delay results in the timetag for applying the bundle immediately.
+fn bundle_timetag(delay: Duration) -> rosc::OscTime {
+    if delay.is_zero() {
+        return IMMEDIATE;
+    }
+    match rosc::OscTime::try_from(SystemTime::now() + delay) {
+        Ok(v) => v,
         Err(e) => {
-            log::error!("Error whilst decoding part of the Key for the Avatar id '{}': {}.\n Refusing to unlock.", id, e);
-            None
+            log::error!("Failed to compute the timetag for an Osc Bundle. The Bundle will be applied immediately instead: {e:?}");
+            IMMEDIATE
+        }
+    }
+}
+
+/// Counts the Key files (files with the `.key` extension) in the Keys Folder.
+/// An empty path counts the Key files in the working directory, since keys are then looked up there.
+pub(crate) async fn count_key_files(path: std::path::PathBuf) -> std::io::Result<usize> {
+    let path = if path.as_os_str().is_empty() { std::path::PathBuf::from(".") } else { path };
+    let mut dir = tokio::fs::read_dir(path).await?;
+    let mut count = 0;
+    while let Some(entry) = dir.next_entry().await? {
+        let is_key = entry.path().extension().is_some_and(|ext| ext.eq_ignore_ascii_case("key"));
+        if is_key && entry.file_type().await.is_ok_and(|file_type| file_type.is_file()) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Checks the configured keys folder, given the result of fetching its metadata.
+/// Returns a description of the problem, if the folder is missing or not a folder.
+///
+/// An empty path is not checked, since keys are then looked up relative to the working directory.
+pub(crate) fn keys_folder_error(path: &std::path::Path, metadata: std::io::Result<std::fs::Metadata>) -> Option<String> {
+    if path.as_os_str().is_empty() {
+        return None;
+    }
+    match metadata {
+        Ok(metadata) if metadata.is_dir() => None,
+        Ok(_) => Some(format!("The configured Keys Folder '{}' is not a folder.", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Some(format!("The configured Keys Folder '{}' does not exist.", path.display())),
+        Err(e) => Some(format!("The configured Keys Folder '{}' could not be accessed: {e}", path.display())),
+    }
+}
+
+fn unrecognized_avatar_change(addr: &str, arg:&Vec<OscType>){
+    log::error!("Received a OSC Message with the address {addr} but the first argument was not a string.\n This is unexpected and there might have been a change to VRChat's OSC messages.\n Extraneous Argument: {:#?}", arg);
+}
+
+/// Converts Keys, that put each `value,name` pair on its own line, into the usual `value|name|value|name` format.
+///
+/// `|` stays the primary format: if the Key contains any `|`, it is returned unchanged.
+/// Within a line, the name is separated from the value by the last `,`, `;`, `=`, tab or space,
+/// since the value itself might use a `,` as the decimal separator.
+fn normalize_key_delimiters(decoded: String) -> String {
+    if decoded.contains('|') || !decoded.contains('\n') {
+        return decoded;
+    }
+    log::debug!("The Key has no '|', but multiple lines. Treating every line as a value and name pair.");
+    let mut pairs = Vec::new();
+    for line in decoded.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match line.rfind([',', ';', '=', '\t', ' ']) {
+            Some(index) => {
+                pairs.push(line[..index].trim());
+                pairs.push(line[index+1..].trim());
+            }
+            //Keep the line, so that the uneven amount of entries gets reported as corruption.
+            None => pairs.push(line),
+        }
+    }
+    pairs.join("|")
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyParseError {
+    #[error("The Key could not be decoded into text.")]
+    Decode,
+    /// The Key has an uneven amount of entries. The complete pairs are still parsed.
+    #[error("The Key has an uneven amount of entries, which suggests corruption.")]
+    UnevenEntries(Vec<(String, KeyNumber)>),
+    #[error("'{text}' in the Key is not a valid number: {error}")]
+    InvalidNumber{
+        text: String,
+        #[source]
+        error: std::num::ParseIntError,
+    },
+}
+
+impl KeyParseError {
+    fn outcome(&self) -> UnlockOutcome {
+        match self {
+            KeyParseError::Decode => UnlockOutcome::DecodeFailed,
+            KeyParseError::UnevenEntries(_) | KeyParseError::InvalidNumber { .. } => UnlockOutcome::Corrupt,
+        }
+    }
+}
+
+/// Returns the path of the Key file with the given name in the Keys Folder.
+///
+/// Returns None, if the name isn't a plain file name (e.g. it contains a path separator, is `..` or is a root),
+/// since the name comes from VRChat or a Key Mapping and must not point outside the Keys Folder.
+pub fn key_path(folder: &std::path::Path, name: &str) -> Option<std::path::PathBuf> {
+    let mut components = std::path::Path::new(name).components();
+    let plain = matches!((components.next(), components.next()), (Some(std::path::Component::Normal(_)), None));
+    //Trailing separators are dropped by `components`, and '\\' is only a separator on Windows.
+    if !plain || name.contains(['/', '\\']) {
+        return None;
+    }
+    let mut path = folder.to_path_buf();
+    if path.file_name().is_some() {
+        path.push(name);
+    }
+    path.set_file_name(name);
+    path.set_extension("key");
+    Some(path)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RevealKeyError {
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+    #[error(transparent)]
+    KeyMapping(#[from] KeyMappingError),
+    #[error("There is no Key for the Avatar id '{0}'.")]
+    NoKey(Arc<str>),
+    #[error("Failed to read the Key file '{}': {error}", path.display())]
+    Read{
+        path: std::path::PathBuf,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error(transparent)]
+    Key(#[from] KeyParseError),
+}
+
+/// Looks up and parses the Key for an Avatar like an unlock would, but without sending anything.
+/// This honours the Key Manifest, the Key Mapping and the default Key, but not the parameter filter.
+pub async fn reveal_key(data: OscCreateData, id: Arc<str>) -> Result<Vec<(String, KeyNumber)>, RevealKeyError> {
+    let key_mapping = match &data.dex_key_mapping_path {
+        Some(path) => Some(load_key_mapping(path).await?),
+        None => None,
+    };
+    let name = key_mapping.as_ref().and_then(|mapping| mapping.key_for(&id)).unwrap_or(&id);
+    let default_key = data.dex_use_default_key.then_some(data.dex_default_key_name.as_str());
+    let parsed = match &data.dex_manifest_path {
+        Some(path) => {
+            let manifest = load_manifest(path).await?;
+            let key = manifest.get(name)
+                .or_else(|| default_key.and_then(|default_key| manifest.get(default_key)))
+                .ok_or_else(|| RevealKeyError::NoKey(id.clone()))?;
+            parse_key_text(key)
+        }
+        None => {
+            let mut path = key_path(&data.path, name).ok_or_else(|| RevealKeyError::NoKey(id.clone()))?;
+            let mut read = tokio::fs::read(&path).await;
+            if let (Err(e), Some(default_path)) = (&read, default_key.and_then(|default_key| key_path(&data.path, default_key))) {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    path = default_path;
+                    read = tokio::fs::read(&path).await;
+                }
+            }
+            match read {
+                Ok(bytes) => parse_key_file(bytes),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Err(RevealKeyError::NoKey(id)),
+                Err(error) => return Err(RevealKeyError::Read { path, error }),
+            }
+        }
+    };
+    match parsed {
+        //Show what could be parsed, since that is the interesting part when checking a corrupt Key.
+        Err(KeyParseError::UnevenEntries(params)) => {
+            log::warn!("The Key for the Avatar id '{id}' has an uneven amount of entries. The last value has no name.");
+            Ok(params)
+        }
+        parsed => Ok(parsed?),
+    }
+}
+
+/// Decrypts and parses the contents of a Key file into the parameter names and their values, in the order of the Key.
+///
+/// Keys, that can't be decrypted, are treated as unencrypted legacy Keys.
+/// With the `gzip` feature, gzip compressed Keys are decompressed (see [maybe_gunzip]).
+/// This neither reads nor sends anything, so everything that can go wrong is returned as a [KeyParseError].
+pub(crate) fn parse_key_file(bytes: Vec<u8>) -> Result<Vec<(String, KeyNumber)>, KeyParseError> {
+    let (v, err) = decrpyt(maybe_gunzip(bytes));
+    match err {
+        None => {},
+        Some(DecryptError::NoKeys) => log::warn!("{}", DecryptError::NoKeys),
+        Some(err) => log::error!("Failed to decrypt the Key. Trying to treat the key as an unencrypted legacy Key.\n Error: {err}"),
+    }
+    let v = maybe_gunzip(v);
+    let decoded = vecu8_to_str(v).ok_or(KeyParseError::Decode)?;
+    #[cfg(all(debug_assertions, feature="debug_log"))]
+    log::debug!("Decoded Key file: '{}'", decoded);
+    parse_key_text(&decoded)
+}
+
+/// Parses a decrypted Key (`value|name|value|name...`) into the parameter names and their values.
+fn parse_key_text(decoded: &str) -> Result<Vec<(String, KeyNumber)>, KeyParseError> {
+    let decoded = normalize_key_delimiters(decoded.to_string()).replace(",", ".");
+    #[cfg(all(debug_assertions, feature="debug_log"))]
+    log::debug!("Post processed Key: '{}'", decoded);
+    let split:Vec<&str> = decoded.split("|").collect();
+    let params = split.chunks_exact(2)
+        .map(|pair| Ok((pair[1].to_string(), parse_key_value(pair[0])?)))
+        .collect::<Result<Vec<_>, KeyParseError>>()?;
+    if split.len()%2 != 0 {
+        return Err(KeyParseError::UnevenEntries(params));
+    }
+    Ok(params)
+}
+
+/// Parses a value of a Key.
+/// Only the digits are parsed by hand, to avoid differences between float parsers.
+fn parse_key_value(float: &str) -> Result<KeyNumber, KeyParseError> {
+    #[cfg(all(debug_assertions, feature="debug_log"))]
+    log::trace!("Decoding float: {}", float);
+    let (whole, part, part_digits) = match float.split_once(".") {
+        Some((whole_str, part_str)) => {
+            #[cfg(all(debug_assertions, feature="debug_log"))]
+            log::trace!("Decoding float: {}, whole: {}, part:{}", float, whole_str, part_str);
+            (decode_number(whole_str)?, decode_number(part_str)?, part_str.len() as u32)
+        }
+        None => (decode_number(float)?, 0, 0),
+    };
+    Ok(KeyNumber { whole, part, part_digits })
+}
+
+/// A value of a Key, as its digits.
+/// This allows computing the value in either precision, without rounding twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyNumber {
+    whole: u32,
+    part: u32,
+    part_digits: u32,
+}
+
+impl KeyNumber {
+    pub fn as_f32(&self) -> f32 {
+        self.whole as f32 + self.part as f32/(10.0f32.powf(self.part_digits as f32))
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.whole as f64 + self.part as f64/(10.0f64.powf(self.part_digits as f64))
+    }
+
+    /// Returns the value as an OSC argument. `high_precision` selects a Double instead of a Float.
+    fn to_osc(self, high_precision: bool) -> OscType {
+        if high_precision {
+            OscType::Double(self.as_f64())
+        } else {
+            OscType::Float(self.as_f32())
+        }
+    }
+}
+
+impl std::fmt::Display for KeyNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_f64())
+    }
+}
+
+fn decode_number(number:&str) -> Result<u32, KeyParseError> {
+    u32::from_str(number).map_err(|error| KeyParseError::InvalidNumber { text: number.to_string(), error })
+}
+/// The magic bytes at the start of every gzip stream.
+#[cfg(feature = "gzip")]
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Decompressed Keys larger than this are rejected, so that a malicious file can't use up all the memory.
+#[cfg(feature = "gzip")]
+const MAX_DECOMPRESSED_LEN: u64 = 16 * 1024 * 1024;
+
+/// Decompresses gzip compressed data.
+///
+/// This is called before and after decrypting, so a Key can either be compressed and then encrypted, or encrypted and then compressed.
+/// Data is only treated as gzip, if it starts with the gzip magic bytes.
+/// Encrypted data can start with those bytes by chance, so if decompressing fails, the data is returned unchanged.
+#[cfg(feature = "gzip")]
+fn maybe_gunzip(data: Vec<u8>) -> Vec<u8> {
+    use std::io::Read;
+    if !data.starts_with(&GZIP_MAGIC) {
+        return data;
+    }
+    let mut decompressed = Vec::new();
+    let result = flate2::read::GzDecoder::new(data.as_slice())
+        .take(MAX_DECOMPRESSED_LEN + 1)
+        .read_to_end(&mut decompressed);
+    match result {
+        Ok(len) if len as u64 > MAX_DECOMPRESSED_LEN => {
+            log::error!("The gzip compressed Key is larger than {MAX_DECOMPRESSED_LEN} bytes, when decompressed. Not decompressing it.");
+            data
+        }
+        Ok(len) => {
+            log::debug!("Decompressed {} gzip compressed bytes to {len} bytes.", data.len());
+            decompressed
+        }
+        Err(e) => {
+            log::debug!("The Key starts with the gzip magic bytes, but is not valid gzip. Using it as is.\n Error: {e}");
+            data
         }
     }
 }
+
+#[cfg(not(feature = "gzip"))]
+fn maybe_gunzip(data: Vec<u8>) -> Vec<u8> {
+    data
+}
+
 fn vecu8_to_str(v:Vec<u8>) -> Option<String> {
     let bom = unicode_bom::Bom::from(v.as_slice());
     match bom {
@@ -350,14 +1533,14 @@ fn vecu8_to_str(v:Vec<u8>) -> Option<String> {
         }
         Bom::Utf16Be => {
             log::debug!("Detected UTF-16Be file.");
-            let mut utf16_buf = VecDeque::from(vecu8_to_vecu16(v, true));
+            let mut utf16_buf = VecDeque::from(vecu8_to_vecu16(v, true)?);
             utf16_buf.pop_front();
             log::debug!("Decoded {} u16 values.", utf16_buf.len());
             utf16_buf_to_str(utf16_buf.into())
         }
         Bom::Utf16Le => {
             log::debug!("Detected UTF-16Le file.");
-            let mut utf16_buf = VecDeque::from(vecu8_to_vecu16(v,false));
+            let mut utf16_buf = VecDeque::from(vecu8_to_vecu16(v,false)?);
             utf16_buf.pop_front();
             log::debug!("Decoded {} u16 values.", utf16_buf.len());
             utf16_buf_to_str(utf16_buf.into())
@@ -366,26 +1549,22 @@ fn vecu8_to_str(v:Vec<u8>) -> Option<String> {
         Bom::Utf32Le => None,
     }
 }
-fn vecu8_to_vecu16(v:Vec<u8>, be:bool) -> Vec<u16>{
+/// Combines pairs of bytes into UTF-16 code units.
+/// Returns None, if there is an uneven amount of bytes, since that means the file is truncated or corrupt.
+fn vecu8_to_vecu16(v:Vec<u8>, be:bool) -> Option<Vec<u16>>{
     log::debug!("Got {} bytes.", v.len());
+    if v.len()%2 != 0 {
+        log::error!("Read an uneven amount of bytes ({}) from a UTF-16 Key file. The Key file is likely truncated or corrupt. Refusing to unlock.", v.len());
+        return None;
+    }
     let mut utf16buf:Vec<u16> = Vec::new();
     let mut i = 0;
-    let len = if v.len()%2 == 0 {
-        v.len()
-    } else {
-        log::debug!("Uneven amount of bytes read.");
-        v.len()-1
-    };
-    while i < len{
-        utf16buf.push(if be {(*v.index(i) as u16).shr(8) | (*v.index(i+1) as u16)} else {(*v.index(i+1) as u16).shr(8) | (*v.index(i) as u16)});
+    while i < v.len(){
+        utf16buf.push(if be {(*v.index(i) as u16).shl(8) | (*v.index(i+1) as u16)} else {(*v.index(i+1) as u16).shl(8) | (*v.index(i) as u16)});
         i+=2;
     }
-    if len != v.len() {
-        log::info!("Reappending last byte.");
-        utf16buf.push(*v.index(len) as u16);
-    }
     log::debug!("Converted to {} u16 values.", utf16buf.len());
-    utf16buf
+    Some(utf16buf)
 }
 fn utf16_buf_to_str(v:Vec<u16>) -> Option<String>{
     let mut string = String::new();
@@ -397,3 +1576,826 @@ fn utf16_buf_to_str(v:Vec<u16>) -> Option<String>{
     }
     return Some(string);
 }
+
+#[cfg(test)]
+mod tests {
+    use osc_handler::MessageHandler;
+    use tokio::net::UdpSocket;
+    use crate::osc::test_util::{block_on, fake_app, recv_messages, recv_packet};
+    use super::*;
+
+    /// How long nothing has to arrive, until no more sends are expected.
+    const QUIET: Duration = Duration::from_millis(100);
+
+    /// A [DexOscHandler], that reads Keys from a temporary Keys Folder and sends to a fake VRChat.
+    struct Harness {
+        dex: DexOscHandler,
+        vrchat: UdpSocket,
+        keys: tempfile::TempDir,
+        last_unlock: LastUnlockHandle,
+        applied_params: AppliedParamsHandle,
+        _notices: std::sync::mpsc::Receiver<OscNotice>,
+    }
+
+    impl Harness {
+        /// `configure` can change the settings. The Keys Folder and the send target are already set.
+        async fn new(configure: impl FnOnce(&mut OscCreateData)) -> Self {
+            Self::with_multiplexer(configure, None).await
+        }
+
+        /// Like [Harness::new], but also forwards what DexProtect sends through `multiplexer`.
+        async fn with_multiplexer(configure: impl FnOnce(&mut OscCreateData), multiplexer: Option<MultiplexerOsc>) -> Self {
+            let vrchat = fake_app().await;
+            let keys = tempfile::tempdir().unwrap();
+            let mut data = OscCreateData {
+                path: keys.path().to_path_buf(),
+                dex_send_target: Some(vrchat.local_addr().unwrap()),
+                ..OscCreateData::default()
+            };
+            configure(&mut data);
+            let osc = Arc::new(OscSender::from_addr(data.dex_send_addr()).await.unwrap());
+            let (notices, notices_rx) = std::sync::mpsc::channel();
+            let last_unlock = LastUnlockHandle::default();
+            let applied_params = AppliedParamsHandle::default();
+            let dex = DexOscHandler::new(&data, osc, last_unlock.clone(), LastAvatarHandle::default(), applied_params.clone(), notices, multiplexer).await.unwrap();
+            Self { dex, vrchat, keys, last_unlock, applied_params, _notices: notices_rx }
+        }
+
+        /// Writes an unencrypted legacy Key into the Keys Folder.
+        fn write_key(&self, name: &str, key: &str) {
+            //Only data, that isn't a multiple of the AES block size, is sure to fail decrypting and be treated as unencrypted.
+            assert_ne!(key.len() % 16, 0, "The test Key '{key}' could be mistaken for an encrypted Key");
+            std::fs::write(self.keys.path().join(format!("{name}.key")), key).unwrap();
+        }
+
+        /// Handles a Message, as if it was received from VRChat, and waits for any unlock it causes.
+        async fn receive(&mut self, addr: &str, args: Vec<OscType>) {
+            self.dex.handle(Arc::new(OscMessage { addr: addr.to_string(), args })).await;
+        }
+
+        async fn change_avatar(&mut self, id: &str) {
+            self.receive(super::super::DEFAULT_DEX_CHANGE_ADDR, vec![OscType::String(id.to_string())]).await;
+        }
+
+        /// Returns the Messages sent to VRChat, with bundles flattened.
+        async fn sent(&self) -> Vec<OscMessage> {
+            recv_messages(&self.vrchat, QUIET).await
+        }
+
+        fn last_outcome(&self) -> Option<UnlockOutcome> {
+            self.last_unlock.lock().as_ref().map(|(_, outcome)| *outcome)
+        }
+
+        /// Returns the addresses, that VRChat is still expected to confirm, sorted.
+        fn awaiting_confirmation(&self) -> Vec<String> {
+            let mut addrs = self.dex.params.lock().as_ref()
+                .map(|(_, params)| params.keys().cloned().collect::<Vec<_>>())
+                .unwrap_or_default();
+            addrs.sort();
+            addrs
+        }
+
+        /// Returns the state of a parameter of the last applied Key.
+        fn param_state(&self, name: &str) -> Option<ParamState> {
+            self.applied_params.lock().as_ref()?.params.get(name).map(|(_, state)| *state)
+        }
+    }
+
+    fn float(name: &str, value: f32) -> OscMessage {
+        crate::osc::test_util::float_message(&format!("/avatar/parameters/{name}"), value)
+    }
+
+    #[test]
+    fn bundles_are_applied_immediately_by_default() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_use_bundles = true).await;
+            harness.write_key("avtr_bundle", "1|a|0.5|b");
+            harness.change_avatar("avtr_bundle").await;
+            match recv_packet(&harness.vrchat, QUIET).await {
+                Some(OscPacket::Bundle(bundle)) => {
+                    assert_eq!(bundle.timetag, IMMEDIATE);
+                    assert_eq!(bundle.content, vec![OscPacket::Message(float("a", 1.0)), OscPacket::Message(float("b", 0.5))]);
+                }
+                other => panic!("Expected a bundle, got {other:?}"),
+            }
+        });
+    }
+
+    #[test]
+    fn bundles_carry_the_delayed_timetag() {
+        block_on(async {
+            const DELAY: Duration = Duration::from_millis(500);
+            //OscTime has a resolution of about 233 picoseconds, so this only covers rounding.
+            const TOLERANCE: Duration = Duration::from_millis(1);
+            let mut harness = Harness::new(|data| {
+                data.dex_use_bundles = true;
+                data.dex_bundle_delay_ms = DELAY.as_millis() as u64;
+            }).await;
+            harness.write_key("avtr_bundle", "1|a|0.5|b");
+            let before = SystemTime::now();
+            harness.change_avatar("avtr_bundle").await;
+            let after = SystemTime::now();
+            match recv_packet(&harness.vrchat, QUIET).await {
+                Some(OscPacket::Bundle(bundle)) => {
+                    let time = SystemTime::from(bundle.timetag);
+                    assert!(time + TOLERANCE >= before + DELAY, "{time:?} is earlier than {DELAY:?} after {before:?}");
+                    assert!(time <= after + DELAY + TOLERANCE, "{time:?} is later than {DELAY:?} after {after:?}");
+                }
+                other => panic!("Expected a bundle, got {other:?}"),
+            }
+        });
+    }
+
+    /// Encodes `text` as UTF-16 with a BOM.
+    fn utf16(text: &str, be: bool) -> Vec<u8> {
+        "\u{feff}".encode_utf16()
+            .chain(text.encode_utf16())
+            .flat_map(|unit| if be { unit.to_be_bytes() } else { unit.to_le_bytes() })
+            .collect()
+    }
+
+    #[test]
+    fn utf16_keys_decode_characters_above_u00ff() {
+        let key = "1|猫|0.5|é";
+        assert_eq!(vecu8_to_str(utf16(key, false)).as_deref(), Some(key));
+        assert_eq!(vecu8_to_str(utf16(key, true)).as_deref(), Some(key));
+    }
+
+    #[test]
+    fn utf16_code_units_combine_both_bytes() {
+        assert_eq!(vecu8_to_vecu16(vec![0x2b, 0x73, 0xe9, 0x00], false), Some(vec![0x732b, 0x00e9]));
+        assert_eq!(vecu8_to_vecu16(vec![0x73, 0x2b, 0x00, 0xe9], true), Some(vec![0x732b, 0x00e9]));
+    }
+
+    #[test]
+    fn odd_length_utf16_is_rejected() {
+        for be in [false, true] {
+            let mut key = utf16("1|a", be);
+            key.push(b'b');
+            assert_eq!(vecu8_to_vecu16(key.clone(), be), None);
+            assert_eq!(vecu8_to_str(key), None);
+        }
+    }
+
+    #[test]
+    fn bool_and_int_echoes_confirm_numeric_values() {
+        assert_eq!(param_value_matches(&OscType::Bool(true), 1.0), Some(true));
+        assert_eq!(param_value_matches(&OscType::Bool(false), 0.0), Some(true));
+        assert_eq!(param_value_matches(&OscType::Bool(false), 1.0), Some(false));
+        assert_eq!(param_value_matches(&OscType::Int(3), 3.0), Some(true));
+        assert_eq!(param_value_matches(&OscType::Int(3), 0.5), Some(false));
+        assert_eq!(param_value_matches(&OscType::Float(0.5 + PARAM_FLOAT_TOLERANCE / 2.0), 0.5), Some(true));
+        assert_eq!(param_value_matches(&OscType::String("1".to_string()), 1.0), None);
+    }
+
+    #[test]
+    fn bool_echo_confirms_a_key_value_of_one() {
+        block_on(async {
+            let mut harness = Harness::new(|_| {}).await;
+            harness.write_key("avtr_bool", "1|IsUnlocked");
+            harness.change_avatar("avtr_bool").await;
+            assert_eq!(harness.sent().await, vec![float("IsUnlocked", 1.0)]);
+            assert_eq!(harness.param_state("IsUnlocked"), Some(ParamState::Pending));
+
+            harness.receive("/avatar/parameters/IsUnlocked", vec![OscType::Bool(true)]).await;
+            assert_eq!(harness.param_state("IsUnlocked"), Some(ParamState::Confirmed));
+        });
+    }
+
+    #[test]
+    fn specific_key_is_preferred_over_the_default_key() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_use_default_key = true).await;
+            harness.write_key("avtr_specific", "1|specific");
+            harness.write_key("_default", "1|default");
+            harness.change_avatar("avtr_specific").await;
+            assert_eq!(harness.sent().await, vec![float("specific", 1.0)]);
+        });
+    }
+
+    #[test]
+    fn default_key_is_used_without_a_specific_key() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_use_default_key = true).await;
+            harness.write_key("_default", "1|default");
+            harness.change_avatar("avtr_other").await;
+            assert_eq!(harness.sent().await, vec![float("default", 1.0)]);
+            assert_eq!(harness.last_outcome(), Some(UnlockOutcome::Applied { count: 1, failed: 0 }));
+        });
+    }
+
+    #[test]
+    fn nothing_is_sent_without_any_key() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_use_default_key = true).await;
+            harness.change_avatar("avtr_other").await;
+            assert_eq!(harness.sent().await, vec![]);
+            assert_eq!(harness.last_outcome(), Some(UnlockOutcome::NoKey));
+        });
+    }
+
+    #[test]
+    fn denied_parameters_are_neither_sent_nor_tracked() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_param_filter = vec!["Gesture".to_string()]).await;
+            harness.write_key("avtr_filter", "1|a|0.5|Gesture");
+            harness.change_avatar("avtr_filter").await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+            assert_eq!(harness.awaiting_confirmation(), vec!["/avatar/parameters/a".to_string()]);
+            assert_eq!(harness.param_state("Gesture"), None);
+        });
+    }
+
+    #[test]
+    fn allow_list_only_sends_listed_parameters() {
+        block_on(async {
+            let mut harness = Harness::new(|data| {
+                data.dex_param_filter_mode = ParamFilterMode::Allow;
+                data.dex_param_filter = vec!["Gesture".to_string()];
+            }).await;
+            harness.write_key("avtr_filter", "1|a|0.5|Gesture");
+            harness.change_avatar("avtr_filter").await;
+            assert_eq!(harness.sent().await, vec![float("Gesture", 0.5)]);
+            assert_eq!(harness.awaiting_confirmation(), vec!["/avatar/parameters/Gesture".to_string()]);
+        });
+    }
+
+    #[test]
+    fn key_file_hash_is_stable() {
+        const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(key_file_hash(b"abc"), ABC);
+        assert_eq!(key_file_hash(b"abc"), key_file_hash(b"abc"));
+        assert_ne!(key_file_hash(b"abd"), ABC);
+    }
+
+    #[test]
+    fn manifest_keys_are_looked_up_by_avatar_id() {
+        block_on(async {
+            //26 bytes, so it isn't mistaken for an encrypted Manifest.
+            const MANIFEST: &str = "avtr_one=1|a\navtr_two=0.5|b\n";
+            let mut harness = Harness::new(|data| {
+                let path = data.path.join("keys.dexpack");
+                std::fs::write(&path, MANIFEST).unwrap();
+                data.dex_manifest_path = Some(path);
+            }).await;
+            let manifest = load_manifest(&harness.keys.path().join("keys.dexpack")).await.unwrap();
+            assert_eq!(manifest.len(), 2);
+            assert_eq!(manifest.get("avtr_one").map(String::as_str), Some("1|a"));
+
+            harness.change_avatar("avtr_two").await;
+            assert_eq!(harness.sent().await, vec![float("b", 0.5)]);
+            harness.change_avatar("avtr_missing").await;
+            assert_eq!(harness.sent().await, vec![]);
+            assert_eq!(harness.last_outcome(), Some(UnlockOutcome::NoKey));
+        });
+    }
+
+    #[test]
+    fn trigger_unlocks_the_last_avatar() {
+        block_on(async {
+            const TRIGGER: &str = "/avatar/parameters/DexUnlock";
+            let mut harness = Harness::new(|data| data.dex_trigger_param = Some(TRIGGER.to_string())).await;
+            harness.write_key("avtr_trigger", "1|a");
+            harness.change_avatar("avtr_trigger").await;
+            assert_eq!(harness.sent().await, vec![]);
+            assert_eq!(harness.last_outcome(), None);
+
+            harness.receive(TRIGGER, vec![OscType::Bool(false)]).await;
+            assert_eq!(harness.sent().await, vec![]);
+            harness.receive(TRIGGER, vec![OscType::Bool(true)]).await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+        });
+    }
+
+    #[test]
+    fn key_mapping_globs_fall_back_to_the_exact_id() {
+        block_on(async {
+            let mut harness = Harness::new(|data| {
+                let path = data.path.join("mapping.txt");
+                std::fs::write(&path, "# Every version of the family\navtr_abc*=family.key\n").unwrap();
+                data.dex_key_mapping_path = Some(path);
+            }).await;
+            harness.write_key("family", "1|a");
+            harness.write_key("avtr_exact", "0.5|b");
+
+            harness.change_avatar("avtr_abc_v2").await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+            harness.change_avatar("avtr_exact").await;
+            assert_eq!(harness.sent().await, vec![float("b", 0.5)]);
+            harness.change_avatar("avtr_none").await;
+            assert_eq!(harness.sent().await, vec![]);
+            assert_eq!(harness.last_outcome(), Some(UnlockOutcome::NoKey));
+        });
+    }
+
+    #[test]
+    fn sent_parameters_are_forwarded_to_the_multiplexer() {
+        block_on(async {
+            let overlay = fake_app().await;
+            let multiplexer = MultiplexerOsc::new(
+                overlay.local_addr().unwrap().ip(),
+                vec![overlay.local_addr().unwrap().port()],
+                Vec::new(),
+                0,
+                0,
+                crate::osc::MultiplexerSwitch::default(),
+                crate::osc::StatsHandle::default(),
+            ).await.unwrap();
+            let mut harness = Harness::with_multiplexer(|_| {}, Some(multiplexer)).await;
+            harness.write_key("avtr_forward", "1|a");
+            harness.change_avatar("avtr_forward").await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+            assert_eq!(recv_messages(&overlay, QUIET).await, vec![float("a", 1.0)]);
+        });
+    }
+
+    /// Returns the names and values of a parsed Key.
+    fn values(parsed: Vec<(String, KeyNumber)>) -> Vec<(String, f32)> {
+        parsed.into_iter().map(|(name, value)| (name, value.as_f32())).collect()
+    }
+
+    #[test]
+    fn pipe_separated_keys() {
+        let parsed = parse_key_text("1|a|0,5|b").unwrap();
+        assert_eq!(values(parsed), vec![("a".to_string(), 1.0), ("b".to_string(), 0.5)]);
+    }
+
+    #[test]
+    fn newline_separated_keys() {
+        let parsed = parse_key_text("1,a\n0,5;b\r\n\n0.25 c\n").unwrap();
+        assert_eq!(values(parsed), vec![("a".to_string(), 1.0), ("b".to_string(), 0.5), ("c".to_string(), 0.25)]);
+    }
+
+    #[test]
+    fn pipes_are_preferred_over_newlines() {
+        let parsed = parse_key_text("1|a\nb").unwrap();
+        assert_eq!(values(parsed), vec![("a\nb".to_string(), 1.0)]);
+    }
+
+    /// Encrypts `plain` like DexProtect encrypts its Keys.
+    fn encrypt(plain: &[u8], key: &[u8; 32], iv: &[u8; 16]) -> Vec<u8> {
+        use cbc::cipher::BlockEncryptMut;
+        cbc::Encryptor::<aes::Aes256>::new_from_slices(key, iv).unwrap()
+            .encrypt_padded_vec_mut::<cbc::cipher::block_padding::Pkcs7>(plain)
+    }
+
+    #[test]
+    fn parse_key_file_decrypts_keys() {
+        let Some((key, iv)) = KEYS.first() else {
+            eprintln!("Skipping, this build has no decryption Keys.");
+            return;
+        };
+        let parsed = parse_key_file(encrypt(b"1|a|0,5|b", key, iv)).unwrap();
+        assert_eq!(values(parsed), vec![("a".to_string(), 1.0), ("b".to_string(), 0.5)]);
+    }
+
+    #[test]
+    fn parse_key_file_reads_unencrypted_legacy_keys() {
+        let parsed = parse_key_file(b"1|a|0,5|b".to_vec()).unwrap();
+        assert_eq!(values(parsed), vec![("a".to_string(), 1.0), ("b".to_string(), 0.5)]);
+    }
+
+    #[cfg(feature = "gzip")]
+    fn gzip(plain: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(plain).unwrap();
+        let compressed = encoder.finish().unwrap();
+        //Like in Harness::write_key, this must not be mistaken for an encrypted Key.
+        assert_ne!(compressed.len() % 16, 0, "The compressed test Key could be mistaken for an encrypted Key");
+        compressed
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn parse_key_file_decompresses_gzip() {
+        let parsed = parse_key_file(gzip(b"1|a|0,5|b")).unwrap();
+        assert_eq!(values(parsed), vec![("a".to_string(), 1.0), ("b".to_string(), 0.5)]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn parse_key_file_decompresses_encrypted_gzip() {
+        let Some((key, iv)) = KEYS.first() else {
+            eprintln!("Skipping, this build has no decryption Keys.");
+            return;
+        };
+        let parsed = parse_key_file(encrypt(&gzip(b"1|a|0,5|b"), key, iv)).unwrap();
+        assert_eq!(values(parsed), vec![("a".to_string(), 1.0), ("b".to_string(), 0.5)]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn data_without_the_gzip_magic_is_unchanged() {
+        assert_eq!(maybe_gunzip(b"1|a|0,5|b".to_vec()), b"1|a|0,5|b".to_vec());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn invalid_gzip_with_the_magic_is_unchanged() {
+        let mut fake = GZIP_MAGIC.to_vec();
+        fake.extend_from_slice(b"1|a|0,5|b");
+        assert_eq!(maybe_gunzip(fake.clone()), fake);
+        //The magic bytes aren't valid UTF-8 text, so this is reported instead of panicking.
+        assert!(parse_key_file(fake).is_err());
+    }
+
+    #[test]
+    fn parse_key_file_reports_uneven_entries() {
+        match parse_key_file(b"1|a|0,5".to_vec()) {
+            Err(KeyParseError::UnevenEntries(parsed)) => assert_eq!(values(parsed), vec![("a".to_string(), 1.0)]),
+            other => panic!("Expected uneven entries, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_key_file_rejects_invalid_numbers() {
+        assert!(matches!(parse_key_file(b"1|a|x|b".to_vec()), Err(KeyParseError::InvalidNumber { text, .. }) if text == "x"));
+        assert!(matches!(parse_key_file(b"-1|a".to_vec()), Err(KeyParseError::InvalidNumber { .. })));
+    }
+
+    #[test]
+    fn parse_key_file_rejects_bad_encodings() {
+        //Not UTF-8.
+        assert!(matches!(parse_key_file(vec![0x31, 0x7c, 0x61, 0xc3]), Err(KeyParseError::Decode)));
+        //UTF-7 is deliberately not supported.
+        assert!(matches!(parse_key_file(b"+/v8 1|a".to_vec()), Err(KeyParseError::Decode)));
+    }
+
+    #[test]
+    fn double_precision_keeps_more_digits() {
+        const EXACT: f64 = 0.123456789;
+        let value = parse_key_value("0.123456789").unwrap();
+        let float_error = (value.as_f32() as f64 - EXACT).abs();
+        let double_error = (value.as_f64() - EXACT).abs();
+        assert!(double_error < float_error, "The Double is off by {double_error}, the Float by {float_error}");
+        assert!(double_error < 1e-15);
+    }
+
+    #[test]
+    fn high_precision_sends_doubles() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_high_precision = true).await;
+            harness.write_key("avtr_double", "0,123456789|a");
+            harness.change_avatar("avtr_double").await;
+            assert_eq!(harness.sent().await, vec![OscMessage {
+                addr: "/avatar/parameters/a".to_string(),
+                args: vec![OscType::Double(0.123456789)],
+            }]);
+        });
+    }
+
+    #[test]
+    fn custom_prefixes_are_used() {
+        block_on(async {
+            let mut harness = Harness::new(|data| {
+                data.dex_change_addr = "/custom/change".to_string();
+                data.dex_param_prefix = "/custom/params/".to_string();
+            }).await;
+            harness.write_key("avtr_custom", "1|a");
+            //The default address is ignored.
+            harness.change_avatar("avtr_custom").await;
+            assert_eq!(harness.sent().await, vec![]);
+
+            harness.receive("/custom/change", vec![OscType::String("avtr_custom".to_string())]).await;
+            assert_eq!(harness.sent().await, vec![crate::osc::test_util::float_message("/custom/params/a", 1.0)]);
+            assert_eq!(harness.awaiting_confirmation(), vec!["/custom/params/a".to_string()]);
+        });
+    }
+
+    #[test]
+    fn send_spacing_is_honored() {
+        block_on(async {
+            const SPACING: Duration = Duration::from_millis(500);
+            //Timers may fire late on a busy machine, but never early.
+            const TOLERANCE: Duration = Duration::from_secs(1);
+            //The receive side may notice a packet a bit late, which shortens the next gap.
+            const RECEIVE_DELAY: Duration = Duration::from_millis(100);
+            let mut harness = Harness::new(|data| data.dex_send_spacing_ms = SPACING.as_millis() as u64).await;
+            harness.write_key("avtr_spaced", "1|a|0,5|b|0|c");
+            let change = harness.dex.handle(Arc::new(OscMessage {
+                addr: super::super::DEFAULT_DEX_CHANGE_ADDR.to_string(),
+                args: vec![OscType::String("avtr_spaced".to_string())],
+            }));
+            let arrivals = async {
+                let mut arrivals = Vec::new();
+                while let Some(packet) = recv_packet(&harness.vrchat, SPACING + TOLERANCE).await {
+                    arrivals.push((Instant::now(), packet));
+                }
+                arrivals
+            };
+            let (_, arrivals) = tokio::join!(change, arrivals);
+            let packets: Vec<_> = arrivals.iter().map(|(_, packet)| packet.clone()).collect();
+            assert_eq!(packets, [float("a", 1.0), float("b", 0.5), float("c", 0.0)].map(OscPacket::Message));
+            for pair in arrivals.windows(2) {
+                let gap = pair[1].0 - pair[0].0;
+                assert!(gap + RECEIVE_DELAY >= SPACING, "The sends were only {gap:?} apart");
+                assert!(gap <= SPACING + TOLERANCE, "The sends were {gap:?} apart");
+            }
+        });
+    }
+
+    #[test]
+    fn baseline_is_reasserted_until_the_avatar_changes() {
+        block_on(async {
+            const INTERVAL: Duration = Duration::from_millis(500);
+            //Timers may fire late on a busy machine.
+            const TOLERANCE: Duration = Duration::from_secs(1);
+            let mut harness = Harness::new(|data| data.dex_reassert_interval_secs = 1).await;
+            //Whole seconds would make the test needlessly slow.
+            harness.dex.dex_reassert_interval = Some(INTERVAL);
+            harness.write_key("avtr_reassert", "1|a");
+            harness.change_avatar("avtr_reassert").await;
+            for _ in 0..3 {
+                assert_eq!(recv_packet(&harness.vrchat, INTERVAL + TOLERANCE).await, Some(OscPacket::Message(float("a", 1.0))));
+            }
+
+            harness.change_avatar("avtr_without_key").await;
+            //Resends from before the Avatar change may still be unread.
+            recv_messages(&harness.vrchat, INTERVAL / 3).await;
+            assert_eq!(recv_packet(&harness.vrchat, INTERVAL * 3).await, None);
+        });
+    }
+
+    /// The arguments of an Avatar change with the Avatar id and some other metadata.
+    fn change_with_extras(id: &str) -> Vec<OscType> {
+        vec![OscType::String(id.to_string()), OscType::String("wrld_metadata".to_string())]
+    }
+
+    #[test]
+    fn single_string_avatar_change_unlocks() {
+        block_on(async {
+            let mut harness = Harness::new(|_| {}).await;
+            harness.write_key("avtr_single", "1|a");
+            harness.receive(super::super::DEFAULT_DEX_CHANGE_ADDR, vec![OscType::String("avtr_single".to_string())]).await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+        });
+    }
+
+    #[test]
+    fn strict_avatar_change_rejects_extra_strings() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_lenient_avatar_change = false).await;
+            harness.write_key("avtr_extras", "1|a");
+            harness.receive(super::super::DEFAULT_DEX_CHANGE_ADDR, change_with_extras("avtr_extras")).await;
+            assert_eq!(harness.sent().await, vec![]);
+            assert_eq!(harness.last_outcome(), None);
+        });
+    }
+
+    #[test]
+    fn lenient_avatar_change_uses_the_first_string() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_lenient_avatar_change = true).await;
+            harness.write_key("avtr_extras", "1|a");
+            harness.receive(super::super::DEFAULT_DEX_CHANGE_ADDR, change_with_extras("avtr_extras")).await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+        });
+    }
+
+    #[test]
+    fn every_decryption_key_is_tried() {
+        let keys = [([1; 32], [2; 16]), ([3; 32], [4; 16])];
+        let (decrypted, error) = decrypt_with_keys(encrypt(b"1|a|0,5|b", &keys[1].0, &keys[1].1), None, &keys);
+        assert!(error.is_none(), "{error:?}");
+        assert_eq!(decrypted, b"1|a|0,5|b");
+    }
+
+    #[cfg(feature = "extra_decryption_keys")]
+    #[test]
+    fn extra_keys_are_tried_after_the_key() {
+        let Some((key, iv)) = EXTRA_KEYS.last() else {
+            eprintln!("Skipping, this build has no extra decryption Keys.");
+            return;
+        };
+        let (decrypted, error) = decrpyt(encrypt(b"1|a|0,5|b", key, iv));
+        assert!(error.is_none(), "{error:?}");
+        assert_eq!(decrypted, b"1|a|0,5|b");
+    }
+
+    //A user supplied Key, set by another test, would be tried otherwise.
+    #[cfg(all(feature = "no_decryption_keys", not(feature = "user_decryption_key")))]
+    #[test]
+    fn without_keys_nothing_is_decrypted() {
+        const { assert!(!DECRYPTION_KEYS_AVAILABLE) };
+        let encrypted = encrypt(b"1|a|0,5|b", &[3; 32], &[4; 16]);
+        assert_eq!(encrypted.len() % 16, 0);
+        let (decrypted, error) = decrpyt(encrypted.clone());
+        assert!(matches!(error, Some(DecryptError::NoKeys)), "{error:?}");
+        assert_eq!(decrypted, encrypted);
+        assert!(matches!(parse_key_file(encrypted), Err(KeyParseError::Decode)));
+        //Unencrypted legacy Keys still work.
+        assert_eq!(values(parse_key_file(b"1|a".to_vec()).unwrap()), vec![("a".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn user_key_is_tried_first() {
+        let user_key = ([5; 32], [6; 16]);
+        let keys = [([1; 32], [2; 16])];
+        let (decrypted, error) = decrypt_with_keys(encrypt(b"1|a", &user_key.0, &user_key.1), Some(user_key), &keys);
+        assert!(error.is_none(), "{error:?}");
+        assert_eq!(decrypted, b"1|a");
+        let (decrypted, _) = decrypt_with_keys(encrypt(b"1|a", &keys[0].0, &keys[0].1), Some(user_key), &keys);
+        assert_eq!(decrypted, b"1|a");
+    }
+
+    #[cfg(feature = "user_decryption_key")]
+    #[test]
+    fn user_key_decrypts_a_known_vector() {
+        //CBC-AES256 from NIST SP 800-38A, F.2.5.
+        let (key, iv) = parse_user_decryption_key(
+            "603deb1015ca71be2b73aef0857d7781 1f352c073b6108d72d9810a30914dff4",
+            "000102030405060708090a0b0c0d0e0f",
+        ).unwrap();
+        let plain = [0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a];
+        let encrypted = encrypt(&plain, &key, &iv);
+        assert_eq!(encrypted[..16], [0xf5, 0x8c, 0x4c, 0x04, 0xd6, 0xe5, 0xf1, 0xba, 0x77, 0x9e, 0xab, 0xfb, 0x5f, 0x7b, 0xfb, 0xd6]);
+
+        let (decrypted, error) = decrypt_with_keys(encrypted, Some((key, iv)), &[]);
+        assert!(error.is_none(), "{error:?}");
+        assert_eq!(decrypted, plain);
+    }
+
+    #[cfg(feature = "user_decryption_key")]
+    #[test]
+    fn invalid_user_keys_are_rejected() {
+        let iv = "000102030405060708090a0b0c0d0e0f";
+        assert!(matches!(parse_user_decryption_key("zz", iv), Err(UserKeyError::InvalidHex { what: "Key" })));
+        assert!(matches!(parse_user_decryption_key("abc", iv), Err(UserKeyError::InvalidHex { what: "Key" })));
+        assert!(matches!(parse_user_decryption_key("00ff", iv), Err(UserKeyError::WrongLength { what: "Key", expected: 32, actual: 2 })));
+        assert!(matches!(parse_user_decryption_key(&"00".repeat(32), "00"), Err(UserKeyError::WrongLength { what: "IV", expected: 16, actual: 1 })));
+    }
+
+    /// Configures DexProtect to only send deltas, and to only unlock on the returned trigger.
+    fn deltas_on_trigger(data: &mut OscCreateData) -> &'static str {
+        const TRIGGER: &str = "/avatar/parameters/DexUnlock";
+        data.dex_only_send_deltas = true;
+        data.dex_trigger_param = Some(TRIGGER.to_string());
+        TRIGGER
+    }
+
+    #[test]
+    fn correct_parameters_are_not_sent_again() {
+        block_on(async {
+            for use_bundles in [false, true] {
+                let mut harness = Harness::new(|data| {
+                    deltas_on_trigger(data);
+                    data.dex_use_bundles = use_bundles;
+                }).await;
+                harness.write_key("avtr_deltas", "1|a|0.5|b");
+                harness.change_avatar("avtr_deltas").await;
+                harness.receive("/avatar/parameters/a", vec![OscType::Float(1.0)]).await;
+                harness.receive("/avatar/parameters/b", vec![OscType::Float(0.5)]).await;
+                harness.receive("/avatar/parameters/DexUnlock", vec![OscType::Bool(true)]).await;
+
+                assert_eq!(recv_packet(&harness.vrchat, QUIET).await, None);
+                assert_eq!(harness.last_outcome(), Some(UnlockOutcome::Applied { count: 0, failed: 0 }));
+                assert_eq!(harness.awaiting_confirmation(), Vec::<String>::new());
+                assert_eq!(harness.param_state("a"), None);
+                assert_eq!(harness.param_state("b"), None);
+            }
+        });
+    }
+
+    #[test]
+    fn only_differing_parameters_are_sent() {
+        block_on(async {
+            let mut harness = Harness::new(|data| { deltas_on_trigger(data); }).await;
+            harness.write_key("avtr_deltas", "1|a|0.5|b|0.125|c");
+            harness.change_avatar("avtr_deltas").await;
+            harness.receive("/avatar/parameters/a", vec![OscType::Float(1.0)]).await;
+            harness.receive("/avatar/parameters/b", vec![OscType::Float(0.25)]).await;
+            //c was never observed, so it has to be sent.
+            harness.receive("/avatar/parameters/DexUnlock", vec![OscType::Bool(true)]).await;
+
+            assert_eq!(harness.sent().await, vec![float("b", 0.5), float("c", 0.125)]);
+            assert_eq!(harness.last_outcome(), Some(UnlockOutcome::Applied { count: 2, failed: 0 }));
+            assert_eq!(harness.awaiting_confirmation(), vec!["/avatar/parameters/b", "/avatar/parameters/c"]);
+            assert_eq!(harness.param_state("a"), None);
+            assert_eq!(harness.param_state("b"), Some(ParamState::Pending));
+        });
+    }
+
+    #[test]
+    fn overrides_enable_bundles_for_one_avatar() {
+        block_on(async {
+            let mut harness = Harness::new(|_| {}).await;
+            harness.write_key("avtr_bundled", "1|a|0.5|b");
+            harness.write_key("avtr_plain", "1|a|0.5|b");
+            std::fs::write(harness.keys.path().join("avtr_bundled.toml"), "use_bundles = true\n").unwrap();
+
+            harness.change_avatar("avtr_bundled").await;
+            assert!(matches!(recv_packet(&harness.vrchat, QUIET).await, Some(OscPacket::Bundle(_))));
+            assert_eq!(recv_packet(&harness.vrchat, QUIET).await, None);
+            harness.change_avatar("avtr_plain").await;
+            assert_eq!(recv_packet(&harness.vrchat, QUIET).await, Some(OscPacket::Message(float("a", 1.0))));
+            assert_eq!(recv_packet(&harness.vrchat, QUIET).await, Some(OscPacket::Message(float("b", 0.5))));
+        });
+    }
+
+    #[test]
+    fn key_names_must_be_plain_file_names() {
+        let folder = std::path::Path::new("keys");
+        assert_eq!(key_path(folder, "avtr_abc"), Some(folder.join("avtr_abc.key")));
+        for name in ["", "..", ".", "../avtr_abc", "sub/avtr_abc", "avtr_abc/", "/avtr_abc", "sub\\avtr_abc"] {
+            assert_eq!(key_path(folder, name), None, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn avatar_ids_cant_escape_the_keys_folder() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.path = data.path.join("nested")).await;
+            std::fs::create_dir(harness.keys.path().join("nested")).unwrap();
+            //Right next to the Keys Folder, so "../avtr_escape" would find it.
+            harness.write_key("avtr_escape", "1|a");
+            std::fs::write(harness.keys.path().join("avtr_escape.toml"), "use_bundles = true\n").unwrap();
+
+            harness.change_avatar("../avtr_escape").await;
+            assert_eq!(harness.sent().await, vec![]);
+            assert_eq!(harness.last_outcome(), Some(UnlockOutcome::NoKey));
+            assert!(load_avatar_overrides(&harness.keys.path().join("nested"), "../avtr_escape").await.is_none());
+        });
+    }
+
+    #[test]
+    fn paused_dex_protect_sends_nothing() {
+        block_on(async {
+            let mut harness = Harness::new(|_| {}).await;
+            harness.write_key("avtr_paused", "1|a");
+            harness.dex.set_enabled(false);
+            harness.change_avatar("avtr_paused").await;
+            assert_eq!(harness.sent().await, vec![]);
+            assert_eq!(harness.last_outcome(), None);
+            //The Avatar change is still seen, e.g. for the "Unlock Again" button.
+            assert_eq!(harness.dex.last_avatar.lock().as_deref(), Some("avtr_paused"));
+
+            harness.dex.set_enabled(true);
+            harness.change_avatar("avtr_paused").await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+        });
+    }
+
+    #[test]
+    fn repeated_avatar_changes_unlock_once() {
+        block_on(async {
+            let mut harness = Harness::new(|_| {}).await;
+            harness.write_key("avtr_one", "1|a");
+            harness.write_key("avtr_two", "0.5|b");
+            harness.change_avatar("avtr_one").await;
+            harness.change_avatar("avtr_one").await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+
+            harness.change_avatar("avtr_two").await;
+            harness.change_avatar("avtr_one").await;
+            assert_eq!(harness.sent().await, vec![float("b", 0.5), float("a", 1.0)]);
+
+            //The "Unlock Again" button unlocks anyway.
+            harness.dex.force_unlock(Arc::from("avtr_one")).await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+        });
+    }
+
+    #[test]
+    fn unlock_again_is_confirmed_by_the_receive_loop() {
+        block_on(async {
+            let mut harness = Harness::new(|_| {}).await;
+            //Like the handle the Ui keeps for the "Unlock Again" button.
+            let ui_handle = harness.dex.clone();
+            harness.write_key("avtr_again", "1|a");
+            for _ in 0..2 {
+                harness.change_avatar("avtr_again").await;
+                assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+                assert_eq!(harness.param_state("a"), Some(ParamState::Pending));
+                harness.receive("/avatar/parameters/a", vec![OscType::Float(1.0)]).await;
+                assert_eq!(harness.param_state("a"), Some(ParamState::Confirmed));
+                assert_eq!(harness.awaiting_confirmation(), Vec::<String>::new());
+
+                ui_handle.force_unlock(Arc::from("avtr_again")).await;
+                assert_eq!(harness.sent().await, vec![float("a", 1.0)]);
+                assert_eq!(harness.awaiting_confirmation(), vec!["/avatar/parameters/a"]);
+                harness.receive("/avatar/parameters/a", vec![OscType::Float(1.0)]).await;
+                assert_eq!(harness.param_state("a"), Some(ParamState::Confirmed));
+                assert_eq!(harness.awaiting_confirmation(), Vec::<String>::new());
+                harness.change_avatar("avtr_other").await;
+            }
+        });
+    }
+
+    #[test]
+    fn repeated_avatar_changes_unlock_again_without_skipping() {
+        block_on(async {
+            let mut harness = Harness::new(|data| data.dex_skip_duplicate_changes = false).await;
+            harness.write_key("avtr_one", "1|a");
+            harness.change_avatar("avtr_one").await;
+            harness.change_avatar("avtr_one").await;
+            assert_eq!(harness.sent().await, vec![float("a", 1.0), float("a", 1.0)]);
+        });
+    }
+
+    #[test]
+    fn zero_delay_is_immediate() {
+        assert_eq!(bundle_timetag(Duration::ZERO), IMMEDIATE);
+        assert_ne!(bundle_timetag(Duration::from_millis(10)), IMMEDIATE);
+    }
+}
@@ -0,0 +1,35 @@
+//! Binding UDP sockets to a specific network interface.
+//!
+//! Binding to an interface by name is only supported on Linux (and Android/Fuchsia) via `SO_BINDTODEVICE`.
+//! On Linux this might need the `CAP_NET_RAW` capability on older kernels (before 5.7).
+//! On other platforms the interface is ignored with a warning, and the socket is only bound to its IP.
+use std::net::SocketAddr;
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Whether [bind_udp] can bind to a network interface on this platform.
+pub const INTERFACE_BINDING_SUPPORTED: bool = cfg!(any(target_os = "android", target_os = "fuchsia", target_os = "linux"));
+
+/// Binds a UDP socket to `addr`.
+/// If `interface` is set, the socket is also bound to the network interface with that name, if the platform supports it.
+pub(super) fn bind_udp(addr: SocketAddr, interface: Option<&str>) -> std::io::Result<tokio::net::UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if let Some(interface) = interface {
+        bind_device(&socket, interface)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    tokio::net::UdpSocket::from_std(socket.into())
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &Socket, interface: &str) -> std::io::Result<()> {
+    log::info!("Binding the OSC UDP Socket to the network interface '{interface}'.");
+    socket.bind_device(Some(interface.as_bytes()))
+        .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to bind to the network interface '{interface}': {e}")))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_: &Socket, interface: &str) -> std::io::Result<()> {
+    log::warn!("Binding to a network interface is not supported on this platform. Ignoring the interface '{interface}' and only binding to the IP.");
+    Ok(())
+}
@@ -1,24 +1,85 @@
 use std::net::IpAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use osc_handler::osc_types_arc;
+use rosc::{OscBundle, OscMessage, OscPacket};
+use serde_derive::{Deserialize, Serialize};
 use crate::osc::sender::RawSendMessage;
-use super::OscSender;
+use super::{OscSender, StatsHandle};
+
+/// Pauses and resumes forwarding of a running Multiplexer, without rebinding its sockets.
+/// Clones control the same Multiplexer.
+#[derive(Clone, Debug)]
+pub struct MultiplexerSwitch {
+    enabled: Arc<AtomicBool>,
+}
+
+impl Default for MultiplexerSwitch {
+    fn default() -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl MultiplexerSwitch {
+    pub fn set_enabled(&self, enabled: bool) {
+        log::info!("{} the OSC Multiplexer.", if enabled {"Resuming"} else {"Pausing"});
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+/// Rewrites the address of Messages forwarded to a port, e.g. for Applications expecting a legacy name.
+///
+/// If `from` ends with `*`, it matches every address starting with the rest of `from`,
+/// and that prefix is replaced with `to` (without a trailing `*`). Otherwise only the exact address matches.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct RewriteRule {
+    /// The forward port, this rule applies to.
+    pub port: u16,
+    pub from: String,
+    pub to: String,
+}
+
+impl RewriteRule {
+    /// Returns the rewritten address, if the rule matches `addr`.
+    fn apply(&self, addr: &str) -> Option<String> {
+        match self.from.strip_suffix('*') {
+            Some(prefix) => addr.strip_prefix(prefix)
+                .map(|rest| format!("{}{rest}", self.to.strip_suffix('*').unwrap_or(&self.to))),
+            None => (addr == self.from).then(|| self.to.clone()),
+        }
+    }
+}
 
 #[derive(Clone)]
-pub(super) struct MultiplexerOsc {
-    forward_sockets: Arc<[OscSender]>,
+pub(crate) struct MultiplexerOsc {
+    /// The forward ports and the sockets sending to them.
+    forward_sockets: Arc<[(u16, OscSender)]>,
+    /// Applied to the packets forwarded to the port of each rule. The first matching rule wins.
+    rewrite_rules: Arc<[RewriteRule]>,
+    switch: MultiplexerSwitch,
+    stats: StatsHandle,
 }
 
 impl MultiplexerOsc{
-    pub async fn new(ip: IpAddr, mut forward_ports: Vec<u16>) -> std::io::Result<Self> {
-        forward_ports.dedup();
+    /// Creates a Multiplexer, that forwards to every port in `forward_ports` on `ip`.
+    /// Duplicate ports and `recv_port` are skipped. Forwarding to our own Receive Port would feed every packet back to us.
+    /// The `rewrite_rules` are only applied, if the Multiplexer is used as a [osc_handler::PacketHandler] (or through [MultiplexerOsc::forward]).
+    /// Each port may have up to `max_in_flight` sends in flight at the same time (see [OscSender::with_max_in_flight]).
+    pub async fn new(ip: IpAddr, forward_ports: Vec<u16>, rewrite_rules: Vec<RewriteRule>, recv_port: u16, max_in_flight: usize, switch: MultiplexerSwitch, stats: StatsHandle) -> std::io::Result<Self> {
+        let forward_ports = dedup_forward_ports(forward_ports, recv_port);
         let mut forward_sockets = Vec::new();
         let mut js = tokio::task::JoinSet::new();
         for port in forward_ports {
             js.spawn(async move {
                 log::info!("About to Bind OSC UDP receive Socket to {}:{}", ip,port);
                 match OscSender::new(ip,port).await{
-                    Ok(v) => Ok(v),
+                    Ok(v) => Ok((port, v.with_max_in_flight(max_in_flight))),
                     Err(e) => {
                         log::warn!("Failed to Bind and/or connect the OSC UDP receive socket: {}", e);
                         Err(e)
@@ -40,10 +101,109 @@ impl MultiplexerOsc{
                 None => break,
             }
         }
+        let rewrite_rules = rewrite_rules.into_iter()
+            .filter(|rule| !rule.from.is_empty())
+            .collect();
         Ok(Self{
             forward_sockets: Arc::from(forward_sockets),
+            rewrite_rules,
+            switch,
+            stats,
         })
     }
+
+    /// Returns a Multiplexer, that doesn't forward to `port`.
+    /// Forwarding to our own Receive Port would feed the packets back into the handlers.
+    pub fn without_port(&self, port: u16) -> Self {
+        let forward_sockets = self.forward_sockets.iter()
+            .filter(|(forward_port, _)| if *forward_port == port {
+                log::warn!("Not forwarding DexProtect's Messages to the port {port}, since that is the OSC Receive Port.");
+                false
+            } else {
+                true
+            })
+            .cloned()
+            .collect();
+        Self{
+            forward_sockets,
+            rewrite_rules: self.rewrite_rules.clone(),
+            switch: self.switch.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+
+    /// Forwards a packet, that didn't come from the Receive Port (e.g. one DexProtect sent), to all forward ports.
+    pub async fn forward(&self, packet: &rosc::OscPacket) {
+        if !self.switch.is_enabled() {
+            return;
+        }
+        match self.encode_per_port(packet) {
+            Ok(v) => {
+                let sends = self.forward_sockets.iter().zip(v).map(|((port, socket), v)|{
+                    crate::metrics::METRICS.forwarded(*port);
+                    self.stats.forwarded(*port);
+                    socket.send_raw_packet(v)
+                });
+                for (result, _) in futures::future::join_all(sends).await {
+                    if let Err(err) = result {
+                        log::warn!("Failed to forward a OSC Packet to the Multiplexer: {err}");
+                    }
+                }
+            }
+            Err(err) => log::error!("Failed to encode a OSC Message: {err}, Packet was: {packet:#?}"),
+        }
+    }
+
+    /// Encodes the packet for every forward port (in the order of `forward_sockets`), with the Rewrite Rules of that port applied.
+    /// Ports without matching rules share the same encoding.
+    fn encode_per_port(&self, packet: &OscPacket) -> Result<Vec<Arc<[u8]>>, rosc::OscError> {
+        let unchanged = Arc::<[u8]>::from(rosc::encoder::encode(packet)?);
+        self.forward_sockets.iter()
+            .map(|(port, _)| match rewrite_packet(packet, *port, &self.rewrite_rules) {
+                Some(rewritten) => Ok(Arc::from(rosc::encoder::encode(&rewritten)?)),
+                None => Ok(unchanged.clone()),
+            })
+            .collect()
+    }
+}
+
+/// Applies the Rewrite Rules for `port` to every Message in the packet.
+/// Returns None, if no rule matched, so that the packet doesn't need to be copied.
+fn rewrite_packet(packet: &OscPacket, port: u16, rules: &[RewriteRule]) -> Option<OscPacket> {
+    match packet {
+        OscPacket::Message(message) => rules.iter()
+            .filter(|rule| rule.port == port)
+            .find_map(|rule| rule.apply(&message.addr))
+            .map(|addr| OscPacket::Message(OscMessage { addr, args: message.args.clone() })),
+        OscPacket::Bundle(bundle) => {
+            let rewritten: Vec<_> = bundle.content.iter().map(|packet| rewrite_packet(packet, port, rules)).collect();
+            if rewritten.iter().all(Option::is_none) {
+                return None;
+            }
+            let content = rewritten.into_iter()
+                .zip(&bundle.content)
+                .map(|(rewritten, packet)| rewritten.unwrap_or_else(|| packet.clone()))
+                .collect();
+            Some(OscPacket::Bundle(OscBundle { timetag: bundle.timetag, content }))
+        }
+    }
+}
+
+/// Removes duplicate ports (not only consecutive ones) and `recv_port` from `forward_ports`.
+fn dedup_forward_ports(mut forward_ports: Vec<u16>, recv_port: u16) -> Vec<u16> {
+    forward_ports.sort_unstable();
+    let len = forward_ports.len();
+    forward_ports.dedup();
+    if forward_ports.len() != len {
+        log::warn!("The Osc Multiplexer Ports contain duplicates. Every port is only forwarded to once.");
+    }
+    forward_ports.retain(|port| if *port == recv_port {
+        log::warn!("Not forwarding to the port {port}, since that is the OSC Receive Port. This would cause an echo loop.");
+        false
+    } else {
+        true
+    });
+    forward_ports
 }
 
 impl osc_handler::PacketHandler for MultiplexerOsc {
@@ -51,13 +211,21 @@ impl osc_handler::PacketHandler for MultiplexerOsc {
     type Output = Vec<(Result<usize, std::io::Error>, Arc<[u8]>)>;
 
     fn handle(&mut self, message: Arc<osc_types_arc::OscPacket>) -> Self::Fut {
-        match rosc::encoder::encode(&rosc::OscPacket::from(message.as_ref())) {
+        if !self.switch.is_enabled() {
+            return Vec::new().into_iter().collect();
+        }
+        //Only copies the Message arguments, if another handler still holds the packet.
+        let packet = OscPacket::from(Arc::unwrap_or_clone(message));
+        match self.encode_per_port(&packet) {
             Ok(v) => {
-                let v = Arc::<[u8]>::from(v);
-                self.forward_sockets.iter().map(|socket|socket.send_raw_packet(v.clone())).collect()
+                self.forward_sockets.iter().zip(v).map(|((port, socket), v)|{
+                    crate::metrics::METRICS.forwarded(*port);
+                    self.stats.forwarded(*port);
+                    socket.send_raw_packet(v)
+                }).collect()
             }
             Err(err) => {
-                log::error!("Failed to encode a OSC Message: {err}, Packet was: {message:#?}");
+                log::error!("Failed to encode a OSC Message: {err}, Packet was: {packet:#?}");
                 Vec::new().into_iter().collect()
             }
         }
@@ -69,6 +237,109 @@ impl osc_handler::RawPacketHandler for MultiplexerOsc {
     type Output<'a> = Vec<(Result<usize, std::io::Error>, &'a [u8])>;
 
     fn handle<'a>(&mut self, message: &'a[u8]) -> Self::Fut<'a> {
-        self.forward_sockets.iter().map(|socket|socket.send_raw_packet(message)).collect()
+        if !self.switch.is_enabled() {
+            return Vec::new().into_iter().collect();
+        }
+        self.forward_sockets.iter().map(|(port, socket)|{
+            crate::metrics::METRICS.forwarded(*port);
+            self.stats.forwarded(*port);
+            socket.send_raw_packet(message)
+        }).collect()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use crate::osc::test_util::{block_on, fake_app, float_message, recv_packet};
+    use super::*;
+
+    const QUIET: Duration = Duration::from_millis(100);
+
+    fn port(socket: &UdpSocket) -> u16 {
+        socket.local_addr().unwrap().port()
+    }
+
+    async fn multiplexer(apps: &[&UdpSocket], rewrite_rules: Vec<RewriteRule>, switch: MultiplexerSwitch) -> MultiplexerOsc {
+        let ports = apps.iter().map(|app| port(app)).collect();
+        MultiplexerOsc::new(IpAddr::V4(Ipv4Addr::LOCALHOST), ports, rewrite_rules, 0, 0, switch, StatsHandle::default()).await.unwrap()
+    }
+
+    fn packet(addr: &str) -> OscPacket {
+        OscPacket::Message(float_message(addr, 1.0))
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn paused_multiplexer_sends_nothing() {
+        block_on(async {
+            let app = fake_app().await;
+            let switch = MultiplexerSwitch::default();
+            let mut multiplexer = multiplexer(&[&app], Vec::new(), switch.clone()).await;
+            switch.set_enabled(false);
+            let sent = osc_handler::PacketHandler::handle(&mut multiplexer, Arc::new(osc_types_arc::OscPacket::from(packet("/a")))).await;
+            assert!(sent.is_empty());
+            let raw = rosc::encoder::encode(&packet("/a")).unwrap();
+            let sent = osc_handler::RawPacketHandler::handle(&mut multiplexer, &raw).await;
+            assert!(sent.is_empty());
+            multiplexer.forward(&packet("/a")).await;
+            assert_eq!(recv_packet(&app, QUIET).await, None);
+
+            switch.set_enabled(true);
+            multiplexer.forward(&packet("/b")).await;
+            assert_eq!(recv_packet(&app, QUIET).await, Some(packet("/b")));
+        });
+    }
+
+    #[test]
+    fn rewrite_rules_only_apply_to_their_port() {
+        block_on(async {
+            let legacy = fake_app().await;
+            let other = fake_app().await;
+            let rules = vec![
+                RewriteRule { port: port(&legacy), from: "/a".to_string(), to: "/b".to_string() },
+                RewriteRule { port: port(&legacy), from: "/avatar/parameters/*".to_string(), to: "/legacy/*".to_string() },
+            ];
+            let mut multiplexer = multiplexer(&[&legacy, &other], rules, MultiplexerSwitch::default()).await;
+
+            osc_handler::PacketHandler::handle(&mut multiplexer, Arc::new(osc_types_arc::OscPacket::from(packet("/a")))).await;
+            assert_eq!(recv_packet(&legacy, QUIET).await, Some(packet("/b")));
+            assert_eq!(recv_packet(&other, QUIET).await, Some(packet("/a")));
+
+            let bundle = OscPacket::Bundle(OscBundle {
+                timetag: (0, 1).into(),
+                content: vec![packet("/avatar/parameters/Test"), packet("/c")],
+            });
+            multiplexer.forward(&bundle).await;
+            assert_eq!(recv_packet(&legacy, QUIET).await, Some(OscPacket::Bundle(OscBundle {
+                timetag: (0, 1).into(),
+                content: vec![packet("/legacy/Test"), packet("/c")],
+            })));
+            assert_eq!(recv_packet(&other, QUIET).await, Some(bundle));
+        });
+    }
+
+    #[test]
+    fn exact_rewrite_rules_dont_match_prefixes() {
+        let rule = RewriteRule { port: 9002, from: "/a".to_string(), to: "/b".to_string() };
+        assert_eq!(rule.apply("/a"), Some("/b".to_string()));
+        assert_eq!(rule.apply("/ab"), None);
+    }
+
+    #[test]
+    fn non_consecutive_duplicate_ports_are_removed() {
+        assert_eq!(dedup_forward_ports(vec![9002, 9003, 9002], 9001), vec![9002, 9003]);
+    }
+
+    #[test]
+    fn the_receive_port_is_not_forwarded_to() {
+        assert_eq!(dedup_forward_ports(vec![9002, 9001, 9003], 9001), vec![9002, 9003]);
+        block_on(async {
+            let app = fake_app().await;
+            let multiplexer = MultiplexerOsc::new(IpAddr::V4(Ipv4Addr::LOCALHOST), vec![port(&app), port(&app)], Vec::new(), port(&app), 0, MultiplexerSwitch::default(), StatsHandle::default()).await.unwrap();
+            assert!(multiplexer.forward_sockets.is_empty());
+            multiplexer.forward(&packet("/a")).await;
+            assert_eq!(recv_packet(&app, QUIET).await, None);
+        });
+    }
+}
@@ -0,0 +1,26 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use super::OscSender;
+
+/// Sends the datagrams of a recording to `recv_addr` (our own Receive Port) with the recorded timing.
+///
+/// This runs a recorded session through all Handlers again, e.g. to reproduce a bug.
+/// Returns the amount of datagrams sent.
+pub async fn replay_recording(path: PathBuf, mut recv_addr: SocketAddr) -> std::io::Result<usize> {
+    let frames = osc_handler::recording::read_recording(&tokio::fs::read(&path).await?)?;
+    //A Receiver bound to all interfaces can be reached on localhost.
+    match recv_addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => recv_addr.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        IpAddr::V6(ip) if ip.is_unspecified() => recv_addr.set_ip(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        _ => {}
+    }
+    log::info!("Replaying {} recorded datagrams from '{}' to {recv_addr}.", frames.len(), path.display());
+    let sender = OscSender::from_addr(recv_addr).await?;
+    let start = tokio::time::Instant::now();
+    for frame in &frames {
+        tokio::time::sleep_until(start + frame.offset).await;
+        sender.send_raw_packet(frame.data.as_slice()).await.0?;
+    }
+    log::info!("Finished replaying '{}'.", path.display());
+    Ok(frames.len())
+}
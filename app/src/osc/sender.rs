@@ -1,21 +1,69 @@
 use std::fmt::Debug;
 use std::future::Future;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio::net::UdpSocket;
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+
+/// The default for how many sends of one [OscSender] may be in flight at the same time, see [OscSender::with_max_in_flight].
+pub const DEFAULT_MAX_IN_FLIGHT_SENDS: usize = 1024;
+
+/// The errors of [OscSender::send_with_timeout].
+#[derive(Debug, thiserror::Error)]
+pub enum SendTimeoutError {
+    #[error("Failed to encode the OSC Packet: {0}")]
+    Encode(#[from] rosc::OscError),
+    #[error("Failed to send the OSC Packet: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Sending the OSC Packet did not finish within {}ms", .0.as_millis())]
+    Timeout(Duration),
+}
+
+/// The send socket.
+/// Logs its address once it is dropped, so that the logs show when the port is released.
+/// Since it is shared through an [Arc], this only happens once the last [OscSender] clone and send is gone.
+struct SendSocket(UdpSocket);
+
+impl std::ops::Deref for SendSocket {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for SendSocket {
+    fn drop(&mut self) {
+        match self.0.local_addr() {
+            Ok(addr) => log::debug!("Closing the OSC UDP send Socket on {addr}."),
+            Err(e) => log::debug!("Closing the OSC UDP send Socket. Its address is unknown: {e}"),
+        }
+    }
+}
 
 ///Allows for sending OSC Messages
+/// Clones send over the same socket.
+#[derive(Clone)]
 pub struct OscSender {
-    osc_send:Arc<UdpSocket>,
+    osc_send:Arc<SendSocket>,
+    /// Limits how many sends are in flight at the same time. Shared by all clones.
+    in_flight: Option<Arc<Semaphore>>,
 }
-async fn bind_and_connect_udp(ip:IpAddr, bind_port:u16, connect_port:u16, way:&str) -> std::io::Result<UdpSocket> {
-    log::info!("About to Bind OSC UDP {} Socket on port {}", way,bind_port);
-    let udp_sock = UdpSocket::bind((ip,bind_port)).await?;
-    log::info!("Bound OSC UDP {} Socket. About to connect to {}:{}.", way,ip,connect_port);
-    udp_sock.connect((ip,connect_port)).await?;
-    log::info!("Connected OSC UDP {} Socket to {}:{}.", way,ip,connect_port);
+/// Binds to the unspecified address of the same family as `addr`, so that `addr` may also be on another machine.
+/// If `interface` is set, the socket is also bound to that network interface (see [super::interface]).
+async fn bind_and_connect_udp(addr:SocketAddr, bind_port:u16, interface: Option<&str>, way:&str) -> std::io::Result<UdpSocket> {
+    let bind_ip = match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    log::info!("About to Bind OSC UDP {} Socket on {}", way,SocketAddr::new(bind_ip,bind_port));
+    let udp_sock = super::interface::bind_udp(SocketAddr::new(bind_ip,bind_port), interface)?;
+    log::info!("Bound OSC UDP {} Socket. About to connect to {}.", way,addr);
+    udp_sock.connect(addr).await?;
+    log::info!("Connected OSC UDP {} Socket to {}.", way,addr);
     Ok(udp_sock)
 }
 impl OscSender {
@@ -23,7 +71,20 @@ impl OscSender {
     /// This will bind a UDP Socket to a random port and connect it to the specified port on the specified ip.
     /// The binding and the connection can both fail, so this function returns a Result.
     pub async fn new(ip:IpAddr,port:u16) -> Result<Self, std::io::Error>{
-        let osc_send = match bind_and_connect_udp(ip, 0, port,"send").await{
+        Self::from_addr(SocketAddr::new(ip, port)).await
+    }
+
+    /// Like [OscSender::new], but takes a full socket address.
+    /// This is needed for IPv6 link-local addresses, which need a scope id.
+    pub async fn from_addr(addr:SocketAddr) -> Result<Self, std::io::Error>{
+        Self::with_bind_port(addr, None, None).await
+    }
+
+    /// Like [OscSender::from_addr], but binds the UDP Socket to `bind_port`, if given.
+    /// A fixed source port helps with firewalls, that only allow known ports.
+    /// If `interface` is set, the UDP Socket is also bound to that network interface, where supported.
+    pub async fn with_bind_port(addr:SocketAddr, bind_port: Option<u16>, interface: Option<&str>) -> Result<Self, std::io::Error>{
+        let osc_send = match bind_and_connect_udp(addr, bind_port.unwrap_or(0), interface, "send").await{
             Ok(v) => v,
             Err(e) => {
                 log::warn!("Failed to Bind and/or connect the OSC UDP send socket: {}", e);
@@ -31,9 +92,29 @@ impl OscSender {
             }
         };
         Ok(Self{
-            osc_send: Arc::new(osc_send),
+            osc_send: Arc::new(SendSocket(osc_send)),
+            in_flight: None,
         })
     }
+
+    /// Limits how many sends of this sender (and its clones) may be in flight at the same time.
+    /// Further sends wait until an earlier one finished. 0 means no limit, which is the default.
+    ///
+    /// This bounds the socket buffer pressure, when many Messages are sent at once (e.g. a big Key or a flood through the Multiplexer).
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.in_flight = (max != 0).then(|| Arc::new(Semaphore::new(max.min(Semaphore::MAX_PERMITS))));
+        self
+    }
+    /// Returns the local address the send socket is bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.osc_send.local_addr()
+    }
+
+    /// Returns the address the send socket is connected to.
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.osc_send.peer_addr()
+    }
+
     /// Sends an OSC Message and returns the amount of bytes sent if successful or any errors.
     pub fn send_message_no_logs(&self, message: &rosc::OscPacket) -> Result<RawSendMessage<Vec<u8>>, rosc::OscError> {
         Ok(self.send_raw_packet(rosc::encoder::encode(message)?))
@@ -54,30 +135,141 @@ impl OscSender {
         }
     }
     
+    /// Sends a OSC Message via [OscSender::send_message_with_logs], but gives up after `timeout`.
+    /// Returns the amount of bytes sent.
+    pub async fn send_with_timeout(&self, message: &rosc::OscPacket, timeout: Duration) -> Result<usize, SendTimeoutError> {
+        let fut = self.send_message_with_logs(message)?;
+        match tokio::time::timeout(timeout, fut).await {
+            Ok((res, _)) => Ok(res?),
+            Err(_) => {
+                log::error!("Sending a OSC Message did not finish within {}ms.", timeout.as_millis());
+                Err(SendTimeoutError::Timeout(timeout))
+            }
+        }
+    }
+
+    /// Sends all packets concurrently via [OscSender::send_message_no_logs].
+    /// Returns the result of every packet, in the order of `packets`.
+    ///
+    /// Packets, that can't be encoded, fail with [std::io::ErrorKind::InvalidData].
+    /// The limit set with [OscSender::with_max_in_flight] still applies.
+    pub fn send_all<I: IntoIterator<Item = rosc::OscPacket>>(&self, packets: I) -> impl Future<Output = Vec<Result<usize, std::io::Error>>> {
+        use futures::FutureExt;
+        packets.into_iter()
+            .map(|packet| match self.send_message_no_logs(&packet) {
+                Ok(send) => futures::future::Either::Left(send.map(|(result, _)| result)),
+                Err(e) => futures::future::Either::Right(core::future::ready(Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))),
+            })
+            .collect::<futures::future::JoinAll<_>>()
+    }
+
     pub fn send_raw_packet<A:AsRef<[u8]>>(&self, packet: A) -> RawSendMessage<A> {
         RawSendMessage{
             message: core::cell::Cell::new(Some(packet)),
             sender: self.osc_send.clone(),
+            in_flight: self.in_flight.clone(),
+            permit: core::cell::Cell::new(Permit::None),
+            writable: core::cell::Cell::new(None),
         }
     }
 }
 
+/// The permit of a [RawSendMessage] to be in flight, see [OscSender::with_max_in_flight].
+#[derive(Default)]
+enum Permit {
+    /// Not requested yet, or not needed.
+    #[default]
+    None,
+    Waiting(Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>),
+    Acquired(OwnedSemaphorePermit),
+}
+
+/// Waits until the send socket is writable, see [RawSendMessage].
+type Writable = Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>>;
+
 pub struct SendMessageLogs<A: AsRef<[u8]>+Debug> {
     fut: RawSendMessage<A>
 }
 pub struct RawSendMessage<A: AsRef<[u8]>> {
     message: core::cell::Cell<Option<A>>,
-    sender: Arc<UdpSocket>,
+    sender: Arc<SendSocket>,
+    in_flight: Option<Arc<Semaphore>>,
+    permit: core::cell::Cell<Permit>,
+    /// Waits until the socket is writable again, after a send would have blocked.
+    writable: core::cell::Cell<Option<Writable>>,
 }
 impl<A: AsRef<[u8]>> RawSendMessage<A> {
+    /// Acquires the permit to be in flight, if the sender has a limit.
+    /// The permit is only acquired once polled, so that creating many sends doesn't hold any permits.
+    fn poll_permit(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(in_flight) = &self.in_flight else {
+            return Poll::Ready(());
+        };
+        let mut waiting = match self.permit.take() {
+            Permit::Acquired(permit) => {
+                self.permit.set(Permit::Acquired(permit));
+                return Poll::Ready(());
+            }
+            Permit::Waiting(waiting) => waiting,
+            Permit::None => match in_flight.clone().try_acquire_owned() {
+                Ok(permit) => {
+                    self.permit.set(Permit::Acquired(permit));
+                    return Poll::Ready(());
+                }
+                Err(_) => Box::pin(in_flight.clone().acquire_owned()),
+            },
+        };
+        match waiting.as_mut().poll(cx) {
+            Poll::Pending => {
+                self.permit.set(Permit::Waiting(waiting));
+                Poll::Pending
+            }
+            Poll::Ready(Ok(permit)) => {
+                self.permit.set(Permit::Acquired(permit));
+                Poll::Ready(())
+            }
+            //The semaphore is never closed. If it were, sending without a permit is the best we can do.
+            Poll::Ready(Err(_)) => Poll::Ready(()),
+        }
+    }
+
     fn poll_send(&self, cx: &mut Context<'_>) -> Poll<(Result<usize, std::io::Error>, A)> {
+        if self.poll_permit(cx).is_pending() {
+            return Poll::Pending;
+        }
         // Panic is ok here because the Future trait says, that you shouldn't poll a Future once ready
         // The only way this can panic, is if the future resolves to Poll::Ready(Err(_)) and then gets polled again (1st expect)
         let message = self.message.take().expect("Future was polled again, after it was Ready");
-        self.sender.poll_send(
-            cx,
-            message.as_ref(),
-        ).map(|f|(f,message))
+        //UdpSocket::poll_send only wakes the task, that polled it last.
+        //The socket is shared by all concurrent sends, so every send waits with its own writable future instead.
+        loop {
+            if let Some(mut writable) = self.writable.take() {
+                match writable.as_mut().poll(cx) {
+                    //The message is needed again, once the socket is ready.
+                    Poll::Pending => {
+                        self.writable.set(Some(writable));
+                        self.message.set(Some(message));
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Ok(())) => {}
+                    Poll::Ready(Err(e)) => {
+                        self.permit.set(Permit::None);
+                        return Poll::Ready((Err(e), message));
+                    }
+                }
+            }
+            match self.sender.try_send(message.as_ref()) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    let sender = self.sender.clone();
+                    self.writable.set(Some(Box::pin(async move { sender.writable().await })));
+                }
+                result => {
+                    //Another send can go ahead, even if this future isn't dropped right away.
+                    self.permit.set(Permit::None);
+                    return Poll::Ready((result, message));
+                }
+            }
+        }
     }
 }
 impl<A: AsRef<[u8]>> Future for RawSendMessage<A>{
@@ -98,6 +290,7 @@ impl<A: AsRef<[u8]>+Debug> Future for SendMessageLogs<A>{
         match self.fut.poll_send(cx) {
             Poll::Pending => Poll::Pending,
             Poll::Ready((Ok(v), bytes)) => {
+                crate::metrics::METRICS.bytes_sent(v);
                 #[cfg(all(debug_assertions, feature="debug_log"))]
                 {
                     log::debug!("Sent the following OSC Message with {v} bytes:{bytes:#?}");
@@ -110,4 +303,174 @@ impl<A: AsRef<[u8]>+Debug> Future for SendMessageLogs<A>{
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use rosc::OscPacket;
+    use crate::osc::test_util::{block_on, fake_app, float_message, recv_packet, sender_to};
+    use super::*;
+
+    const QUIET: Duration = Duration::from_millis(100);
+
+    fn packet(addr: &str) -> OscPacket {
+        OscPacket::Message(float_message(addr, 1.0))
+    }
+
+    #[test]
+    fn send_with_timeout_sends() {
+        block_on(async {
+            let app = fake_app().await;
+            let sender = sender_to(&app).await;
+            let len = sender.send_with_timeout(&packet("/a"), Duration::from_secs(1)).await.unwrap();
+            assert_eq!(len, rosc::encoder::encode(&packet("/a")).unwrap().len());
+            assert_eq!(recv_packet(&app, QUIET).await, Some(packet("/a")));
+        });
+    }
+
+    #[test]
+    fn ipv6_round_trip() {
+        block_on(async {
+            //Not every machine (or container) has IPv6 loopback.
+            let Ok(app) = tokio::net::UdpSocket::bind((std::net::Ipv6Addr::LOCALHOST, 0)).await else {
+                eprintln!("Skipping, IPv6 loopback is unavailable.");
+                return;
+            };
+            let sender = sender_to(&app).await;
+            assert!(sender.local_addr().unwrap().is_ipv6());
+            sender.send_with_timeout(&packet("/a"), Duration::from_secs(1)).await.unwrap();
+            assert_eq!(recv_packet(&app, QUIET).await, Some(packet("/a")));
+        });
+    }
+
+    #[test]
+    fn send_with_timeout_gives_up_on_a_stalled_send() {
+        block_on(async {
+            const TIMEOUT: Duration = Duration::from_millis(50);
+            let app = fake_app().await;
+            let sender = sender_to(&app).await.with_max_in_flight(1);
+            //Holding the only permit stalls the send, like a socket that never becomes writable.
+            let stall = sender.in_flight.clone().unwrap().acquire_owned().await.unwrap();
+            let result = sender.send_with_timeout(&packet("/a"), TIMEOUT).await;
+            assert!(matches!(result, Err(SendTimeoutError::Timeout(timeout)) if timeout == TIMEOUT), "{result:?}");
+            drop(stall);
+            assert_eq!(recv_packet(&app, QUIET).await, None);
+        });
+    }
+
+    #[test]
+    fn send_all_returns_the_results_in_order() {
+        block_on(async {
+            let app = fake_app().await;
+            let sender = sender_to(&app).await;
+            //Addresses of different lengths, so that the results can be told apart by their size.
+            let packets = vec![packet("/a"), packet("/avatar"), packet("/avatar/parameters/a")];
+            let results = sender.send_all(packets.clone()).await;
+            let sizes = results.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+            let expected = packets.iter().map(|packet| rosc::encoder::encode(packet).unwrap().len()).collect::<Vec<_>>();
+            assert_eq!(sizes, expected);
+            for packet in packets {
+                assert_eq!(recv_packet(&app, QUIET).await, Some(packet));
+            }
+        });
+    }
+
+    #[test]
+    fn sends_wait_for_a_free_in_flight_permit() {
+        block_on(async {
+            const MAX_IN_FLIGHT: usize = 2;
+            let app = fake_app().await;
+            let sender = sender_to(&app).await.with_max_in_flight(MAX_IN_FLIGHT);
+            let in_flight = sender.in_flight.clone().unwrap();
+            let held = in_flight.clone().acquire_many_owned(MAX_IN_FLIGHT as u32).await.unwrap();
+
+            let packets: Vec<_> = (0..5).map(|i| packet(&format!("/{i}"))).collect();
+            let mut sends = std::pin::pin!(sender.send_all(packets.clone()));
+            assert!(tokio::time::timeout(QUIET, &mut sends).await.is_err(), "Sent without a permit");
+            assert_eq!(recv_packet(&app, QUIET).await, None);
+
+            drop(held);
+            let results = sends.await;
+            assert!(results.iter().all(Result::is_ok), "{results:?}");
+            for packet in packets {
+                assert_eq!(recv_packet(&app, QUIET).await, Some(packet));
+            }
+            //Every permit is given back, once the sends are done.
+            assert_eq!(in_flight.available_permits(), MAX_IN_FLIGHT);
+        });
+    }
+
+    /// Records the most permits of `in_flight` taken at once, sampled whenever the wrapped send is polled.
+    struct SampledSend {
+        send: RawSendMessage<Vec<u8>>,
+        in_flight: Arc<Semaphore>,
+        max: usize,
+        peak: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl Future for SampledSend {
+        type Output = Result<usize, std::io::Error>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let result = self.send.poll_send(cx);
+            self.peak.fetch_max(self.max - self.in_flight.available_permits(), std::sync::atomic::Ordering::Relaxed);
+            result.map(|(result, _)| result)
+        }
+    }
+
+    #[test]
+    fn in_flight_sends_never_exceed_the_limit() {
+        const MAX_IN_FLIGHT: usize = 4;
+        const SENDS: usize = 128;
+        //Several worker threads, so that the sends really run at the same time.
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(4).enable_all().build().unwrap();
+        runtime.block_on(async {
+            let app = fake_app().await;
+            let sender = sender_to(&app).await.with_max_in_flight(MAX_IN_FLIGHT);
+            let in_flight = sender.in_flight.clone().unwrap();
+            let peak = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let mut js = tokio::task::JoinSet::new();
+            for i in 0..SENDS {
+                js.spawn(SampledSend {
+                    send: sender.send_message_no_logs(&packet(&format!("/{i}"))).unwrap(),
+                    in_flight: in_flight.clone(),
+                    max: MAX_IN_FLIGHT,
+                    peak: peak.clone(),
+                });
+            }
+            while let Some(result) = js.join_next().await {
+                result.unwrap().unwrap();
+            }
+            let peak = peak.load(std::sync::atomic::Ordering::Relaxed);
+            assert!(peak <= MAX_IN_FLIGHT, "{peak} sends were in flight at once");
+            assert_eq!(in_flight.available_permits(), MAX_IN_FLIGHT);
+            let mut received = 0;
+            while recv_packet(&app, QUIET).await.is_some() {
+                received += 1;
+            }
+            assert_eq!(received, SENDS);
+        });
+    }
+
+    #[test]
+    fn send_socket_binds_to_the_chosen_port() {
+        block_on(async {
+            let app = fake_app().await;
+            let port = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap().local_addr().unwrap().port();
+            let sender = OscSender::with_bind_port(app.local_addr().unwrap(), Some(port), None).await.unwrap();
+            assert_eq!(sender.local_addr().unwrap().port(), port);
+            sender.send_with_timeout(&packet("/a"), Duration::from_secs(1)).await.unwrap();
+            assert_eq!(recv_packet(&app, QUIET).await, Some(packet("/a")));
+        });
+    }
+
+    #[test]
+    fn busy_bind_port_is_reported() {
+        block_on(async {
+            let app = fake_app().await;
+            let busy = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+            let port = busy.local_addr().unwrap().port();
+            let error = OscSender::with_bind_port(app.local_addr().unwrap(), Some(port), None).await.err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+        });
+    }
+}
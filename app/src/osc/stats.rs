@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use egui::mutex::Mutex;
+use osc_handler::receiver::ReceiverStats;
+
+/// A handle to the [Stats], shared between the Ui and the OSC Thread.
+pub type StatsHandle = Arc<Stats>;
+
+/// Statistics about the OSC traffic, shown in the Statistics panel.
+///
+/// The App keeps one for its whole lifetime and passes it to every OSC Thread,
+/// so the counts are totals since launch and survive reconnects.
+#[derive(Default)]
+pub struct Stats {
+    /// What the receive loop received and decoded.
+    pub receiver: Arc<ReceiverStats>,
+    /// The packets forwarded by the Multiplexer per port.
+    forwarded: Mutex<BTreeMap<u16, u64>>,
+}
+
+impl Stats {
+    pub(super) fn forwarded(&self, port: u16) {
+        *self.forwarded.lock().entry(port).or_default() += 1;
+    }
+
+    /// Returns the packets forwarded by the Multiplexer per port.
+    pub fn forwarded_snapshot(&self) -> BTreeMap<u16, u64> {
+        self.forwarded.lock().clone()
+    }
+}
+
+impl std::fmt::Debug for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stats")
+            .field("receiver", &self.receiver)
+            .field("forwarded", &self.forwarded_snapshot())
+            .finish()
+    }
+}
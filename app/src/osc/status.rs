@@ -0,0 +1,86 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender};
+use egui::mutex::Mutex;
+
+/// A handle to the [OscStatus], shared between the Ui and the OSC Thread.
+pub type OscStatusHandle = Arc<Mutex<OscStatus>>;
+
+/// The connection state of the OSC Thread, as shown in the Ui.
+#[derive(Debug, Clone, Default)]
+pub enum OscStatus {
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected {
+        /// The local address OSC Messages are received on.
+        recv_addr: SocketAddr,
+        /// The local and remote address of the DexProtect send socket, if DexProtect is enabled.
+        send_addr: Option<(SocketAddr, SocketAddr)>,
+    },
+    Error(String),
+}
+
+impl OscStatus {
+    pub fn color(&self) -> egui::Color32 {
+        match self {
+            OscStatus::Disconnected => egui::Color32::GRAY,
+            OscStatus::Connecting => egui::Color32::YELLOW,
+            OscStatus::Connected { .. } => egui::Color32::GREEN,
+            OscStatus::Error(_) => egui::Color32::RED,
+        }
+    }
+}
+
+impl std::fmt::Display for OscStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OscStatus::Disconnected => write!(f, "Disconnected"),
+            OscStatus::Connecting => write!(f, "Connecting..."),
+            OscStatus::Connected { recv_addr, send_addr: None } => write!(f, "Connected. Receiving on {recv_addr}"),
+            OscStatus::Connected { recv_addr, send_addr: Some((local, peer)) } => write!(f, "Connected. Receiving on {recv_addr}, sending from {local} to {peer}"),
+            OscStatus::Error(e) => write!(f, "Error: {e}"),
+        }
+    }
+}
+
+/// Something the OSC Thread wants to tell the user about, e.g. with a popup.
+#[derive(Debug, Clone)]
+pub enum OscNotice {
+    /// The Keys Folder could be accessed before, but can't be anymore (e.g. a network drive disconnected).
+    KeysFolderUnavailable {
+        path: PathBuf,
+        error: String,
+    },
+    /// An unlock of an Avatar was attempted (see [crate::osc::UnlockOutcome]).
+    Unlocked {
+        id: Arc<str>,
+        outcome: crate::osc::UnlockOutcome,
+    },
+}
+
+/// A channel for [OscNotice]s from the OSC Thread to the Ui.
+/// It outlives the OSC Thread, so that notices are not lost on a Reconnect.
+pub struct OscNotices {
+    sender: Sender<OscNotice>,
+    receiver: Receiver<OscNotice>,
+}
+
+impl Default for OscNotices {
+    fn default() -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        Self { sender, receiver }
+    }
+}
+
+impl OscNotices {
+    pub fn sender(&self) -> Sender<OscNotice> {
+        self.sender.clone()
+    }
+
+    /// Returns all notices, that were sent since the last call.
+    pub fn try_iter(&self) -> impl Iterator<Item = OscNotice> + '_ {
+        self.receiver.try_iter()
+    }
+}
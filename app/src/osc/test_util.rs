@@ -0,0 +1,55 @@
+//! Helpers for tests, that send OSC over loopback.
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use super::OscSender;
+
+/// Runs a test on a fresh runtime.
+///
+/// `#[tokio::test]` can't be used, since it allows lints, that the crate forbids.
+pub(crate) fn block_on<F: std::future::Future>(test: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(test)
+}
+
+/// Binds a UDP Socket to a random port on localhost.
+/// It stands in for VRChat or any other app, that we send to.
+pub(crate) async fn fake_app() -> UdpSocket {
+    UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap()
+}
+
+/// Creates an [OscSender], that sends to `socket`.
+pub(crate) async fn sender_to(socket: &UdpSocket) -> OscSender {
+    OscSender::from_addr(socket.local_addr().unwrap()).await.unwrap()
+}
+
+/// Receives the next OSC Packet, or returns None if nothing arrives within `timeout`.
+pub(crate) async fn recv_packet(socket: &UdpSocket, timeout: Duration) -> Option<OscPacket> {
+    let mut buf = [0u8; osc_handler::OSC_RECV_BUFFER_SIZE];
+    let len = tokio::time::timeout(timeout, socket.recv(&mut buf)).await.ok()?.unwrap();
+    Some(rosc::decoder::decode_udp(&buf[..len]).unwrap().1)
+}
+
+/// Receives OSC Packets, until nothing arrives for `quiet`.
+/// Returns all received Messages, with bundles flattened into their Messages.
+pub(crate) async fn recv_messages(socket: &UdpSocket, quiet: Duration) -> Vec<OscMessage> {
+    fn flatten(packet: OscPacket, out: &mut Vec<OscMessage>) {
+        match packet {
+            OscPacket::Message(message) => out.push(message),
+            OscPacket::Bundle(bundle) => bundle.content.into_iter().for_each(|p| flatten(p, out)),
+        }
+    }
+    let mut out = Vec::new();
+    while let Some(packet) = recv_packet(socket, quiet).await {
+        flatten(packet, &mut out);
+    }
+    out
+}
+
+pub(crate) fn float_message(addr: &str, value: f32) -> OscMessage {
+    OscMessage { addr: addr.to_string(), args: vec![OscType::Float(value)] }
+}
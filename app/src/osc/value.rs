@@ -0,0 +1,180 @@
+use std::str::FromStr;
+use rosc::OscType;
+use serde_derive::{Deserialize, Serialize};
+
+/// The types of OSC values, that can be entered as text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum OscValueKind {
+    #[default]
+    Float,
+    Int,
+    Bool,
+    String,
+    Color,
+    Blob,
+}
+
+impl OscValueKind {
+    pub const ALL: [OscValueKind; 6] = [OscValueKind::Float, OscValueKind::Int, OscValueKind::Bool, OscValueKind::String, OscValueKind::Color, OscValueKind::Blob];
+}
+
+impl std::fmt::Display for OscValueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OscValueKind::Float => write!(f, "Float"),
+            OscValueKind::Int => write!(f, "Int"),
+            OscValueKind::Bool => write!(f, "Bool"),
+            OscValueKind::String => write!(f, "String"),
+            OscValueKind::Color => write!(f, "Color"),
+            OscValueKind::Blob => write!(f, "Blob"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("'{text}' is not a number: {error}")]
+    Float{
+        text: String,
+        #[source]
+        error: std::num::ParseFloatError,
+    },
+    #[error("'{0}' is not a finite number.")]
+    NotFinite(String),
+    #[error("'{text}' is not a whole number: {error}")]
+    Int{
+        text: String,
+        #[source]
+        error: std::num::ParseIntError,
+    },
+    #[error("'{0}' is not a Bool. Use true/false, 1/0, on/off or yes/no.")]
+    Bool(String),
+    #[error("'{0}' is not a Color. Use #RRGGBB or #RRGGBBAA.")]
+    Color(String),
+    #[error("'{0}' is not a Blob. Use hex bytes, e.g. 'DE AD BE EF'.")]
+    Blob(String),
+}
+
+/// Parses the text `s` into an OSC value of the given kind.
+///
+/// Floats may use a `,` as the decimal separator, like in Key files.
+/// Surrounding whitespace is ignored, except for Strings, which are taken as they are.
+/// Colors are `#RRGGBB` or `#RRGGBBAA` (the `#` is optional) and Blobs are hex bytes, which may be separated by whitespace.
+/// Everything [format_osc_value] produces for these kinds is accepted here.
+pub fn parse_osc_value(kind: OscValueKind, s: &str) -> Result<OscType, ParseError> {
+    let text = s.trim();
+    match kind {
+        OscValueKind::Float => {
+            let value = f32::from_str(&text.replace(',', "."))
+                .map_err(|error| ParseError::Float { text: text.to_string(), error })?;
+            if !value.is_finite() {
+                return Err(ParseError::NotFinite(text.to_string()));
+            }
+            Ok(OscType::Float(value))
+        }
+        OscValueKind::Int => i32::from_str(text)
+            .map(OscType::Int)
+            .map_err(|error| ParseError::Int { text: text.to_string(), error }),
+        OscValueKind::Bool => match text.to_ascii_lowercase().as_str() {
+            "true" | "1" | "on" | "yes" => Ok(OscType::Bool(true)),
+            "false" | "0" | "off" | "no" => Ok(OscType::Bool(false)),
+            _ => Err(ParseError::Bool(text.to_string())),
+        },
+        OscValueKind::String => Ok(OscType::String(s.to_string())),
+        OscValueKind::Color => {
+            let bytes = match decode_hex(text.strip_prefix('#').unwrap_or(text)) {
+                Some(bytes) if bytes.len() == 3 || bytes.len() == 4 => bytes,
+                _ => return Err(ParseError::Color(text.to_string())),
+            };
+            Ok(OscType::Color(rosc::OscColor {
+                red: bytes[0],
+                green: bytes[1],
+                blue: bytes[2],
+                alpha: bytes.get(3).copied().unwrap_or(u8::MAX),
+            }))
+        }
+        OscValueKind::Blob => decode_hex(text.strip_prefix("0x").unwrap_or(text))
+            .map(OscType::Blob)
+            .ok_or_else(|| ParseError::Blob(text.to_string())),
+    }
+}
+
+/// Formats an OSC value as text.
+///
+/// For the kinds in [OscValueKind], the output can be parsed back with [parse_osc_value].
+/// All other values use their Debug representation.
+pub fn format_osc_value(value: &OscType) -> String {
+    match value {
+        OscType::Float(f) => f.to_string(),
+        OscType::Double(d) => d.to_string(),
+        OscType::Int(i) => i.to_string(),
+        OscType::Long(l) => l.to_string(),
+        OscType::Bool(b) => b.to_string(),
+        OscType::String(s) => s.clone(),
+        OscType::Color(c) => format!("#{:02X}{:02X}{:02X}{:02X}", c.red, c.green, c.blue, c.alpha),
+        OscType::Blob(b) => b.iter().map(|byte| format!("{byte:02X}")).collect::<Vec<_>>().join(" "),
+        other => format!("{other:?}"),
+    }
+}
+
+/// Decodes hex digits into bytes, ignoring whitespace between them.
+/// Returns `None` on non-hex characters or an odd number of digits.
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let digits = text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>()?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_kind() {
+        assert_eq!(parse_osc_value(OscValueKind::Float, " 0,5 ").unwrap(), OscType::Float(0.5));
+        assert_eq!(parse_osc_value(OscValueKind::Float, "-1.25").unwrap(), OscType::Float(-1.25));
+        assert_eq!(parse_osc_value(OscValueKind::Int, " -42 ").unwrap(), OscType::Int(-42));
+        assert_eq!(parse_osc_value(OscValueKind::Bool, "ON").unwrap(), OscType::Bool(true));
+        assert_eq!(parse_osc_value(OscValueKind::Bool, "0").unwrap(), OscType::Bool(false));
+        assert_eq!(parse_osc_value(OscValueKind::String, " as is ").unwrap(), OscType::String(" as is ".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_text() {
+        assert!(matches!(parse_osc_value(OscValueKind::Float, "half"), Err(ParseError::Float { .. })));
+        assert!(matches!(parse_osc_value(OscValueKind::Float, "inf"), Err(ParseError::NotFinite(_))));
+        assert!(matches!(parse_osc_value(OscValueKind::Float, "NaN"), Err(ParseError::NotFinite(_))));
+        assert!(matches!(parse_osc_value(OscValueKind::Int, "1.5"), Err(ParseError::Int { .. })));
+        assert!(matches!(parse_osc_value(OscValueKind::Int, "2147483648"), Err(ParseError::Int { .. })));
+        assert!(matches!(parse_osc_value(OscValueKind::Bool, "maybe"), Err(ParseError::Bool(_))));
+    }
+
+    #[test]
+    fn colors_round_trip() {
+        let color = OscType::Color(rosc::OscColor { red: 0x12, green: 0xAB, blue: 0x00, alpha: 0x80 });
+        assert_eq!(format_osc_value(&color), "#12AB0080");
+        assert_eq!(parse_osc_value(OscValueKind::Color, &format_osc_value(&color)).unwrap(), color);
+        //Without alpha, the Color is opaque.
+        assert_eq!(
+            parse_osc_value(OscValueKind::Color, "12ab00").unwrap(),
+            OscType::Color(rosc::OscColor { red: 0x12, green: 0xAB, blue: 0x00, alpha: 0xFF }),
+        );
+        assert!(matches!(parse_osc_value(OscValueKind::Color, "#12AB"), Err(ParseError::Color(_))));
+    }
+
+    #[test]
+    fn blobs_round_trip() {
+        let blob = OscType::Blob(vec![0xDE, 0xAD, 0x00, 0xEF]);
+        assert_eq!(format_osc_value(&blob), "DE AD 00 EF");
+        assert_eq!(parse_osc_value(OscValueKind::Blob, &format_osc_value(&blob)).unwrap(), blob);
+        assert_eq!(parse_osc_value(OscValueKind::Blob, "0xdead00ef").unwrap(), blob);
+        assert_eq!(parse_osc_value(OscValueKind::Blob, "").unwrap(), OscType::Blob(Vec::new()));
+        assert!(matches!(parse_osc_value(OscValueKind::Blob, "DEA"), Err(ParseError::Blob(_))));
+        assert!(matches!(parse_osc_value(OscValueKind::Blob, "XY"), Err(ParseError::Blob(_))));
+    }
+}
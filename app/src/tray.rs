@@ -0,0 +1,134 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tray_icon::menu::{Menu, MenuEvent, MenuId, MenuItem};
+
+/// The size of the generated tray icon in pixels.
+const ICON_SIZE: u32 = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum TrayError {
+    #[error("Failed to create the tray menu: {0}")]
+    Menu(#[from] tray_icon::menu::Error),
+    #[error("Failed to create the tray icon image: {0}")]
+    Icon(#[from] tray_icon::BadIcon),
+    #[error("Failed to create the tray icon: {0}")]
+    Tray(#[from] tray_icon::Error),
+    #[cfg(target_os = "linux")]
+    #[error("Failed to initialize gtk: {0}")]
+    Gtk(#[from] gtk::glib::BoolError),
+    #[cfg(target_os = "linux")]
+    #[error("The tray icon thread exited unexpectedly")]
+    ThreadExited,
+}
+
+/// State shared between the Ui and the tray menu.
+struct TrayState {
+    visible: AtomicBool,
+    quit: AtomicBool,
+}
+
+/// A system tray icon with "Show/Hide" and "Quit" entries.
+pub(crate) struct Tray {
+    state: Arc<TrayState>,
+    /// The tray icon is removed, once this is dropped.
+    /// On Linux the tray icon lives on its own gtk thread instead.
+    #[cfg(not(target_os = "linux"))]
+    _icon: tray_icon::TrayIcon,
+}
+
+impl Tray {
+    /// Creates the tray icon.
+    /// This must be called on the main thread, since Windows and macOS require that.
+    pub(crate) fn new(ctx: egui::Context) -> Result<Self, TrayError> {
+        #[cfg(target_os = "linux")]
+        let (show_hide, quit) = {
+            //On Linux, the tray icon needs gtk to be initialized and its event loop to run on the same thread.
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                if let Err(e) = gtk::init() {
+                    let _ = sender.send(Err(TrayError::from(e)));
+                    return;
+                }
+                match build_tray_icon() {
+                    Ok((_icon, show_hide, quit)) => {
+                        let _ = sender.send(Ok((show_hide, quit)));
+                        gtk::main();
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(e));
+                    }
+                }
+            });
+            receiver.recv().map_err(|_| TrayError::ThreadExited)??
+        };
+        #[cfg(not(target_os = "linux"))]
+        let (icon, show_hide, quit) = build_tray_icon()?;
+
+        let state = Arc::new(TrayState {
+            visible: AtomicBool::new(true),
+            quit: AtomicBool::new(false),
+        });
+        let handler_state = state.clone();
+        MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+            if event.id == show_hide {
+                let visible = !handler_state.visible.fetch_xor(true, Ordering::SeqCst);
+                log::info!("{} the window from the tray.", if visible {"Showing"} else {"Hiding"});
+                ctx.send_viewport_cmd(egui::ViewportCommand::Visible(visible));
+                if visible {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+                }
+            } else if event.id == quit {
+                log::info!("Quit was requested from the tray.");
+                handler_state.quit.store(true, Ordering::SeqCst);
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            ctx.request_repaint();
+        }));
+        log::info!("Created the tray icon.");
+        Ok(Self {
+            state,
+            #[cfg(not(target_os = "linux"))]
+            _icon: icon,
+        })
+    }
+
+    /// Whether "Quit" was clicked in the tray menu.
+    /// In that case, the window should really close.
+    pub(crate) fn quit_requested(&self) -> bool {
+        self.state.quit.load(Ordering::SeqCst)
+    }
+
+    /// Hides the window. It can be shown again from the tray menu.
+    pub(crate) fn hide(&self, ctx: &egui::Context) {
+        log::info!("Hiding the window to the tray.");
+        self.state.visible.store(false, Ordering::SeqCst);
+        ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+    }
+}
+
+fn build_tray_icon() -> Result<(tray_icon::TrayIcon, MenuId, MenuId), TrayError> {
+    let show_hide = MenuItem::new("Show/Hide", true, None);
+    let quit = MenuItem::new("Quit", true, None);
+    let menu = Menu::new();
+    menu.append_items(&[&show_hide, &quit])?;
+    let icon = tray_icon::TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip("DexProtectOSC-RS")
+        .with_icon(icon()?)
+        .build()?;
+    Ok((icon, show_hide.id().clone(), quit.id().clone()))
+}
+
+/// Draws a simple filled circle, so that no image file needs to be shipped.
+fn icon() -> Result<tray_icon::Icon, tray_icon::BadIcon> {
+    let center = (ICON_SIZE as f32 - 1.) / 2.;
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+            let alpha = if distance <= center { 0xFF } else { 0 };
+            rgba.extend_from_slice(&[0x30, 0xA0, 0xE0, alpha]);
+        }
+    }
+    tray_icon::Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE)
+}
@@ -0,0 +1,104 @@
+//! Object safe versions of the handler traits.
+//!
+//! [MessageHandler], [PacketHandler] and [RawPacketHandler] have associated future types,
+//! so handlers of different types can't be put into the same collection.
+//! The traits in this module box the returned futures instead and discard the outputs.
+//! Every handler implements them automatically,
+//! and `Box<dyn ...>` implements the original traits again, so it can be passed to an [crate::receiver::OscReceiver].
+//!
+//! ```
+//! use osc_handler::dyn_handler::DynMessageHandler;
+//! use osc_handler::multple_handler::{CountingHandler, StubHandler};
+//!
+//! let handlers: Vec<Box<dyn DynMessageHandler>> = vec![
+//!     Box::new(StubHandler),
+//!     Box::new(CountingHandler::new()),
+//! ];
+//! ```
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use rosc::OscMessage;
+use crate::{osc_types_arc, MessageHandler, PacketHandler, RawPacketHandler};
+
+pub type BoxFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// An object safe [MessageHandler].
+pub trait DynMessageHandler: Send + Sync {
+    fn handle_dyn(&mut self, message: Arc<OscMessage>) -> BoxFuture<'static>;
+}
+
+impl<T> DynMessageHandler for T
+where
+    T: MessageHandler + Send + Sync,
+    T::Fut: 'static,
+{
+    fn handle_dyn(&mut self, message: Arc<OscMessage>) -> BoxFuture<'static> {
+        let fut = self.handle(message);
+        Box::pin(async move {
+            fut.await;
+        })
+    }
+}
+
+impl MessageHandler for Box<dyn DynMessageHandler> {
+    type Fut = BoxFuture<'static>;
+    type Output = ();
+
+    fn handle(&mut self, message: Arc<OscMessage>) -> Self::Fut {
+        self.as_mut().handle_dyn(message)
+    }
+}
+
+/// An object safe [PacketHandler].
+pub trait DynPacketHandler: Send + Sync {
+    fn handle_dyn(&mut self, message: Arc<osc_types_arc::OscPacket>) -> BoxFuture<'static>;
+}
+
+impl<T> DynPacketHandler for T
+where
+    T: PacketHandler + Send + Sync,
+    T::Fut: 'static,
+{
+    fn handle_dyn(&mut self, message: Arc<osc_types_arc::OscPacket>) -> BoxFuture<'static> {
+        let fut = self.handle(message);
+        Box::pin(async move {
+            fut.await;
+        })
+    }
+}
+
+impl PacketHandler for Box<dyn DynPacketHandler> {
+    type Fut = BoxFuture<'static>;
+    type Output = ();
+
+    fn handle(&mut self, message: Arc<osc_types_arc::OscPacket>) -> Self::Fut {
+        self.as_mut().handle_dyn(message)
+    }
+}
+
+/// An object safe [RawPacketHandler].
+pub trait DynRawPacketHandler: Send + Sync {
+    fn handle_dyn<'a>(&mut self, message: &'a [u8]) -> BoxFuture<'a>;
+}
+
+impl<T> DynRawPacketHandler for T
+where
+    T: RawPacketHandler + Send + Sync,
+{
+    fn handle_dyn<'a>(&mut self, message: &'a [u8]) -> BoxFuture<'a> {
+        let fut = self.handle(message);
+        Box::pin(async move {
+            fut.await;
+        })
+    }
+}
+
+impl RawPacketHandler for Box<dyn DynRawPacketHandler> {
+    type Fut<'a> = BoxFuture<'a>;
+    type Output<'a> = ();
+
+    fn handle<'a>(&mut self, message: &'a [u8]) -> Self::Fut<'a> {
+        self.as_mut().handle_dyn(message)
+    }
+}
@@ -1,21 +1,25 @@
 use std::cmp::Ordering;
 
+/// A value with a key to sort by.
+/// Entries with equal keys are ordered by `seq`, so that their order is deterministic.
 pub struct KeyValue<K,V> {
     pub key: K,
     pub value: V,
     pub uuid: uuid::Uuid,
+    /// Breaks ties between equal keys. Entries with a lower `seq` come first.
+    pub seq: u64,
 }
 
 impl<K, V> KeyValue<K, V> {
     #[inline]
-    pub(crate) fn new(key: K, value: V, uuid: uuid::Uuid) -> KeyValue<K, V> {
-        KeyValue { key, value, uuid}
+    pub(crate) fn new(key: K, value: V, uuid: uuid::Uuid, seq: u64) -> KeyValue<K, V> {
+        KeyValue { key, value, uuid, seq}
     }
 }
 
 impl<K: PartialEq<K>, V> PartialEq<Self> for KeyValue<K, V> {
     fn eq(&self, other: &Self) -> bool {
-        self.key.eq(&other.key)
+        self.key.eq(&other.key) && self.seq == other.seq
     }
 }
 
@@ -24,29 +28,54 @@ impl<K:Eq,V> Eq for KeyValue<K,V>{
 
 impl<K: PartialOrd<K>, V> PartialOrd<Self> for KeyValue<K, V> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.key.partial_cmp(&other.key)
+        match self.key.partial_cmp(&other.key)? {
+            Ordering::Equal => Some(self.seq.cmp(&other.seq)),
+            ordering => Some(ordering),
+        }
     }
+}
+
+impl<K:Ord,V> Ord for KeyValue<K,V> {
 
-    fn lt(&self, other: &Self) -> bool {
-        self.key.lt(&other.key)
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key).then(self.seq.cmp(&other.seq))
     }
+}
 
-    fn le(&self, other: &Self) -> bool {
-        self.key.le(&other.key)
+#[cfg(test)]
+mod tests {
+    use sorted_vec::ReverseSortedVec;
+    use super::*;
+
+    fn entry(key: u32, seq: u64) -> KeyValue<u32, ()> {
+        KeyValue::new(key, (), uuid::Uuid::new_v4(), seq)
     }
 
-    fn gt(&self, other: &Self) -> bool {
-        self.key.gt(&other.key)
+    fn order(entries: &[KeyValue<u32, ()>]) -> Vec<(u32, u64)> {
+        entries.iter().map(|entry| (entry.key, entry.seq)).collect()
     }
 
-    fn ge(&self, other: &Self) -> bool {
-        self.key.ge(&other.key)
+    #[test]
+    fn equal_keys_are_ordered_by_seq() {
+        assert_eq!(entry(1, 0).cmp(&entry(1, 1)), Ordering::Less);
+        assert_eq!(entry(1, 1).partial_cmp(&entry(1, 0)), Some(Ordering::Greater));
+        assert_eq!(entry(0, 5).cmp(&entry(1, 0)), Ordering::Less);
+        assert!(entry(1, 0) != entry(1, 1));
+        assert!(entry(1, 0) == entry(1, 0));
     }
-}
 
-impl<K:Ord,V> Ord for KeyValue<K,V> {
+    #[test]
+    fn reverse_sorted_entries_follow_insertion_order() {
+        let mut buf = ReverseSortedVec::new();
+        for (seq, key) in [2, 1, 2, 2, 1].into_iter().enumerate() {
+            buf.push(std::cmp::Reverse(entry(key, seq as u64)));
+        }
+        //The buffer is sorted in reverse, so the first entry to apply is the last.
+        let applied: Vec<_> = buf.iter().rev().map(|entry| (entry.0.key, entry.0.seq)).collect();
+        assert_eq!(applied, vec![(1, 1), (1, 4), (2, 0), (2, 2), (2, 3)]);
 
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.key.cmp(&other.key)
+        let mut sorted = vec![entry(3, 2), entry(3, 0), entry(1, 9), entry(3, 1)];
+        sorted.sort();
+        assert_eq!(order(&sorted), vec![(1, 9), (3, 0), (3, 1), (3, 2)]);
     }
 }
@@ -2,6 +2,9 @@ pub mod receiver;
 pub mod multple_handler;
 pub mod key_value;
 pub mod osc_types_arc;
+pub mod dyn_handler;
+pub mod recording;
+pub mod router;
 
 use std::future::Future;
 use std::sync::Arc;
@@ -9,6 +12,30 @@ use std::time::SystemTime;
 use sorted_vec::ReverseSortedVec;
 
 pub const OSC_RECV_BUFFER_SIZE:usize = 8192;
+/// The smallest sensible maximum message size.
+/// Anything smaller can't even hold a moderately long address, so almost nothing would be received.
+pub const OSC_MIN_MESSAGE_SIZE:usize = 512;
+
+/// What to do with data, that could not be decoded. See [classify_decode_error].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeDisposition {
+    /// The data might be the start of a packet, that is not complete yet.
+    /// Keep it and try decoding again, once more data arrived.
+    NeedMoreData,
+    /// The data can never be decoded. Skip it.
+    Fatal,
+}
+
+/// Classifies an error from decoding OSC data (e.g. [router::Routed::error]).
+///
+/// This is what the receive loop of [receiver::OscReceiver] uses, to decide whether to buffer undecodable data.
+/// Any buffer should still be bounded, since a [DecodeDisposition::NeedMoreData] packet might never be completed.
+pub fn classify_decode_error(error: &rosc::OscError) -> DecodeDisposition {
+    match error {
+        rosc::OscError::BadPacket(_) | rosc::OscError::ReadError(nom::error::ErrorKind::Eof) => DecodeDisposition::NeedMoreData,
+        _ => DecodeDisposition::Fatal,
+    }
+}
 
 #[must_use]
 pub enum Results<F,T>
@@ -35,8 +62,40 @@ impl<F,T> Results<F,T>
             Results::NotYetApplied(_) => vec![],
         }
     }
+
+    /// Returns true, if this is or contains a bundle, that cannot be applied yet.
+    pub fn is_deferred(&self) -> bool {
+        match self {
+            Results::OscMessage(_) => false,
+            Results::OscBundle(v) => v.iter().any(Results::is_deferred),
+            Results::NotYetApplied(_) => true,
+        }
+    }
+
+    /// Returns the amount of messages, that have been applied.
+    /// Messages in bundles, that cannot be applied yet, are not counted.
+    pub fn message_count(&self) -> usize {
+        match self {
+            Results::OscMessage(_) => 1,
+            Results::OscBundle(v) => v.iter().map(Results::message_count).sum(),
+            Results::NotYetApplied(_) => 0,
+        }
+    }
+
+    /// Returns the ids of all bundles, that cannot be applied yet.
+    pub fn deferred_uuids(&self) -> Vec<uuid::Uuid> {
+        match self {
+            Results::OscMessage(_) => vec![],
+            Results::OscBundle(v) => v.iter()
+                .flat_map(Results::deferred_uuids)
+                .collect(),
+            Results::NotYetApplied(uuid) => vec![*uuid],
+        }
+    }
 }
 
+/// Buffered bundles are keyed by their timetag.
+/// Bundles with the same timetag are ordered by their arrival (see [key_value::KeyValue::seq]).
 type InnerBuf = key_value::KeyValue<time::OffsetDateTime,osc_types_arc::OscBundle>;
 type Buf = ReverseSortedVec<InnerBuf>;
 pub trait MessageHandler{
@@ -50,7 +109,7 @@ pub trait PacketHandler{
     fn handle(&mut self, message: Arc<osc_types_arc::OscPacket>) -> Self::Fut;
 }
 pub trait RawPacketHandler{
-    type Fut<'a>: Future<Output = Self::Output<'a>> + Send;
+    type Fut<'a>: Future<Output = Self::Output<'a>> + Send + 'a;
     type Output<'a>: Send;
     fn handle<'a>(&mut self, message: &'a[u8]) -> Self::Fut<'a>;
 }
@@ -62,6 +121,8 @@ pub(crate) struct MessageDestructuring<H, P, R>
         R: RawPacketHandler,
 {
     bundle_buf: Buf,
+    /// The [key_value::KeyValue::seq] of the next buffered bundle.
+    next_bundle_seq: u64,
     pub(crate) message_handler: H,
     pub(crate) packet_handler: P,
     pub(crate) raw_handler: R,
@@ -81,6 +142,7 @@ where
     ) -> Self{
         Self{
             bundle_buf: Default::default(),
+            next_bundle_seq: 0,
             message_handler,
             packet_handler,
             raw_handler,
@@ -150,7 +212,9 @@ where
         let now = time::OffsetDateTime::now_utc();
         let to_apply = {
             let partition_point = self.bundle_buf.partition_point(|x| x.0.key > now);
+            //The buffer is sorted in reverse, so the due bundles are drained from the latest to the earliest.
             self.bundle_buf.drain(partition_point..)
+                .rev()
                 .map(|x| x.0)
                 //we consume and create a new iter here to actively consume the drain iter,
                 // run the destructor of the drain and to copy the elements we need out
@@ -163,6 +227,27 @@ where
             .collect()
     }
 
+    /// Returns how many bundles are buffered, because their timetag is in the future.
+    pub(crate) fn pending_bundles(&self) -> usize {
+        self.bundle_buf.len()
+    }
+
+    /// Drops all buffered bundles without applying them.
+    /// Returns how many were dropped.
+    pub(crate) fn clear_pending_bundles(&mut self) -> usize {
+        let count = self.bundle_buf.len();
+        self.bundle_buf.clear();
+        count
+    }
+
+    /// Returns how long it is until the earliest buffered bundle is due.
+    /// Returns [std::time::Duration::ZERO], if a bundle is already due, and `None`, if no bundles are buffered.
+    pub(crate) fn time_until_next_bundle(&self) -> Option<std::time::Duration> {
+        //The buffer is sorted in reverse, so the earliest bundle is the last one.
+        let next = self.bundle_buf.last()?.0.key;
+        Some((next - time::OffsetDateTime::now_utc()).try_into().unwrap_or(std::time::Duration::ZERO))
+    }
+
     #[inline]
     fn handle_message(&mut self, message: Arc<rosc::OscMessage>) -> Results<H::Fut,H::Output> {
         let js = self.message_handler.handle(message);
@@ -186,7 +271,9 @@ where
             self.apply_bundle(bundle)
         }else{
             let uuid = uuid::Uuid::new_v4();
-            self.bundle_buf.push(std::cmp::Reverse(key_value::KeyValue::new(date_time, bundle.clone(), uuid)));
+            let seq = self.next_bundle_seq;
+            self.next_bundle_seq = self.next_bundle_seq.wrapping_add(1);
+            self.bundle_buf.push(std::cmp::Reverse(key_value::KeyValue::new(date_time, bundle.clone(), uuid, seq)));
             Results::NotYetApplied(uuid)
         }
     }
@@ -203,4 +290,148 @@ where
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::multple_handler::StubHandler;
+    use super::*;
+
+    type TestResults = Results<core::future::Ready<()>, ()>;
+
+    fn message() -> TestResults {
+        Results::OscMessage(core::future::ready(()))
+    }
+
+    #[test]
+    fn decode_errors_are_classified() {
+        use rosc::OscError;
+        let need_more_data = [
+            OscError::BadPacket("Empty packet"),
+            OscError::ReadError(nom::error::ErrorKind::Eof),
+        ];
+        for error in need_more_data {
+            assert_eq!(classify_decode_error(&error), DecodeDisposition::NeedMoreData, "{error:?}");
+        }
+        let fatal = [
+            OscError::ReadError(nom::error::ErrorKind::Tag),
+            OscError::BadMessage("Bad message"),
+            OscError::BadBundle("Bad bundle".to_string()),
+            OscError::BadAddress("no/slash".to_string()),
+            OscError::Unimplemented,
+        ];
+        for error in fatal {
+            assert_eq!(classify_decode_error(&error), DecodeDisposition::Fatal, "{error:?}");
+        }
+    }
+
+    #[test]
+    fn results_of_a_message() {
+        let results = message();
+        assert!(!results.is_deferred());
+        assert_eq!(results.message_count(), 1);
+        assert!(results.deferred_uuids().is_empty());
+    }
+
+    #[test]
+    fn results_of_nested_bundles() {
+        let deferred = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+        let results = Results::OscBundle(vec![
+            message(),
+            Results::NotYetApplied(deferred[0]),
+            Results::OscBundle(vec![
+                message(),
+                message(),
+                Results::OscBundle(vec![Results::NotYetApplied(deferred[1])]),
+            ]),
+        ]);
+        assert!(results.is_deferred());
+        assert_eq!(results.message_count(), 3);
+        assert_eq!(results.deferred_uuids(), deferred.to_vec());
+        assert_eq!(results.to_messages_vec().len(), 3);
+    }
+
+    #[test]
+    fn results_of_applied_nested_bundles() {
+        let results = Results::OscBundle(vec![
+            Results::OscBundle(vec![message()]),
+            Results::OscBundle(vec![]),
+        ]);
+        assert!(!results.is_deferred());
+        assert_eq!(results.message_count(), 1);
+        assert!(results.deferred_uuids().is_empty());
+    }
+
+    /// Keeps the address of every message it gets.
+    #[derive(Clone, Default)]
+    struct AddrHandler(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl MessageHandler for AddrHandler {
+        type Fut = core::future::Ready<()>;
+        type Output = ();
+
+        fn handle(&mut self, message: Arc<rosc::OscMessage>) -> Self::Fut {
+            self.0.lock().unwrap().push(message.addr.clone());
+            core::future::ready(())
+        }
+    }
+
+    fn bundle(timetag: rosc::OscTime, addr: &str) -> Arc<osc_types_arc::OscPacket> {
+        let message = rosc::OscMessage { addr: addr.to_string(), args: Vec::new() };
+        Arc::new(osc_types_arc::OscPacket::from(rosc::OscPacket::Bundle(rosc::OscBundle {
+            timetag,
+            content: vec![rosc::OscPacket::Message(message)],
+        })))
+    }
+
+    /// Sleeps until the wall clock, which bundles are checked against, is past `time`.
+    fn sleep_until(time: SystemTime) {
+        if let Ok(remaining) = time.duration_since(SystemTime::now()) {
+            std::thread::sleep(remaining + Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn bundles_are_applied_by_timetag_then_arrival() {
+        let handler = AddrHandler::default();
+        let mut destructuring = MessageDestructuring::new(handler.clone(), StubHandler, StubHandler);
+        //Far enough in the future, that none of them can be due before they are all buffered.
+        let now = SystemTime::now();
+        let late_at = now + Duration::from_millis(1500);
+        let early = rosc::OscTime::try_from(now + Duration::from_secs(1)).unwrap();
+        let late = rosc::OscTime::try_from(late_at).unwrap();
+        for packet in [bundle(late, "/a"), bundle(early, "/b"), bundle(late, "/c"), bundle(late, "/d"), bundle(late, "/e")] {
+            let (packet_fut, results) = destructuring.handle_packet(packet);
+            futures::executor::block_on(packet_fut);
+            assert!(results.is_deferred());
+        }
+        assert_eq!(destructuring.pending_bundles(), 5);
+
+        sleep_until(late_at);
+        for (_, results) in destructuring.check_osc_bundles() {
+            futures::executor::block_on(futures::future::join_all(results.to_messages_vec()));
+        }
+        assert_eq!(*handler.0.lock().unwrap(), ["/b", "/a", "/c", "/d", "/e"]);
+        assert_eq!(destructuring.pending_bundles(), 0);
+    }
+
+    #[test]
+    fn cleared_bundles_are_never_applied() {
+        let handler = AddrHandler::default();
+        let mut destructuring = MessageDestructuring::new(handler.clone(), StubHandler, StubHandler);
+        let due_at = SystemTime::now() + Duration::from_secs(1);
+        let due = rosc::OscTime::try_from(due_at).unwrap();
+        for addr in ["/a", "/b", "/c"] {
+            let (packet_fut, _) = destructuring.handle_packet(bundle(due, addr));
+            futures::executor::block_on(packet_fut);
+        }
+        assert_eq!(destructuring.pending_bundles(), 3);
+
+        assert_eq!(destructuring.clear_pending_bundles(), 3);
+        assert_eq!(destructuring.pending_bundles(), 0);
+        assert_eq!(destructuring.clear_pending_bundles(), 0);
+        sleep_until(due_at);
+        assert!(destructuring.check_osc_bundles().is_empty());
+        assert!(handler.0.lock().unwrap().is_empty());
+    }
+}
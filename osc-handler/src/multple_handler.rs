@@ -1,18 +1,47 @@
 use core::future::Ready;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use rosc::OscMessage;
 use crate::{MessageHandler, osc_types_arc, PacketHandler, RawPacketHandler};
 
+/// Hands everything to all of its handlers.
+///
+/// Handlers can be added and removed between calls to `handle`.
+/// Only the handlers present at the time of a call get the message.
 pub struct OscHandler<T> {
-    handlers: Box<[T]>
+    handlers: Vec<T>
 }
 
 impl<T> OscHandler<T> {
-    pub fn new(handlers: Box<[T]>) -> Self {
+    pub fn new(handlers: Vec<T>) -> Self {
         Self {
             handlers
         }
     }
+
+    /// Adds a handler, which gets everything handled after this call.
+    pub fn push(&mut self, handler: T) {
+        self.handlers.push(handler);
+    }
+
+    /// Removes and returns the handler at `index`. The handlers after it move down by one.
+    /// Returns None, if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        (index < self.handlers.len()).then(|| self.handlers.remove(index))
+    }
+
+    /// Keeps only the handlers for which `keep` returns true.
+    pub fn retain(&mut self, keep: impl FnMut(&T) -> bool) {
+        self.handlers.retain(keep);
+    }
+
+    pub fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
 }
 impl<O:Send, T:MessageHandler<Output=O>+Send> MessageHandler for OscHandler<T> {
     type Fut = futures::future::JoinAll<T::Fut>;
@@ -70,4 +99,159 @@ impl RawPacketHandler for StubHandler {
     fn handle(&mut self, _: &[u8]) -> Self::Fut<'static> {
         core::future::ready(())
     }
-}
\ No newline at end of file
+}
+
+/// Counts the messages, packets and raw packets it receives, but otherwise drops them.
+///
+/// Clones share the same counters,
+/// so a clone can be kept around to read the counts via [CountingHandler::snapshot].
+#[derive(Clone, Debug, Default)]
+pub struct CountingHandler {
+    counts: Arc<Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    messages: AtomicU64,
+    packets: AtomicU64,
+    raw_packets: AtomicU64,
+    raw_bytes: AtomicU64,
+}
+
+/// The counts of a [CountingHandler] at one point in time.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CountingSnapshot {
+    pub messages: u64,
+    pub packets: u64,
+    pub raw_packets: u64,
+    /// The total size of all raw packets in bytes.
+    pub raw_bytes: u64,
+}
+
+impl CountingSnapshot {
+    /// Returns the counts, that were added between `earlier` and `self`.
+    pub fn since(&self, earlier: &CountingSnapshot) -> CountingSnapshot {
+        CountingSnapshot {
+            messages: self.messages.saturating_sub(earlier.messages),
+            packets: self.packets.saturating_sub(earlier.packets),
+            raw_packets: self.raw_packets.saturating_sub(earlier.raw_packets),
+            raw_bytes: self.raw_bytes.saturating_sub(earlier.raw_bytes),
+        }
+    }
+}
+
+impl CountingHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current counts.
+    pub fn snapshot(&self) -> CountingSnapshot {
+        CountingSnapshot {
+            messages: self.counts.messages.load(Ordering::Relaxed),
+            packets: self.counts.packets.load(Ordering::Relaxed),
+            raw_packets: self.counts.raw_packets.load(Ordering::Relaxed),
+            raw_bytes: self.counts.raw_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl MessageHandler for CountingHandler {
+    type Fut = Ready<()>;
+    type Output = ();
+
+    fn handle(&mut self, _: Arc<OscMessage>) -> Self::Fut {
+        self.counts.messages.fetch_add(1, Ordering::Relaxed);
+        core::future::ready(())
+    }
+}
+
+impl PacketHandler for CountingHandler {
+    type Fut = Ready<()>;
+    type Output = ();
+
+    fn handle(&mut self, _: Arc<osc_types_arc::OscPacket>) -> Self::Fut {
+        self.counts.packets.fetch_add(1, Ordering::Relaxed);
+        core::future::ready(())
+    }
+}
+
+impl RawPacketHandler for CountingHandler {
+    type Fut<'a> = Ready<()>;
+    type Output<'a> = ();
+
+    fn handle(&mut self, message: &[u8]) -> Self::Fut<'static> {
+        self.counts.raw_packets.fetch_add(1, Ordering::Relaxed);
+        self.counts.raw_bytes.fetch_add(message.len() as u64, Ordering::Relaxed);
+        core::future::ready(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use super::*;
+
+    fn message() -> Arc<OscMessage> {
+        Arc::new(OscMessage { addr: "/avatar/parameters/Test".to_string(), args: vec![rosc::OscType::Float(1.0)] })
+    }
+
+    #[test]
+    fn counting_handler_counts() {
+        let counter = CountingHandler::new();
+        let mut handler = counter.clone();
+        let raw = rosc::encoder::encode(&rosc::OscPacket::Message((*message()).clone())).unwrap();
+        for _ in 0..3 {
+            block_on(MessageHandler::handle(&mut handler, message()));
+        }
+        for _ in 0..2 {
+            block_on(PacketHandler::handle(&mut handler, Arc::new(osc_types_arc::OscPacket::Message(message()))));
+        }
+        for _ in 0..4 {
+            block_on(RawPacketHandler::handle(&mut handler, raw.as_slice()));
+        }
+        assert_eq!(counter.snapshot(), CountingSnapshot {
+            messages: 3,
+            packets: 2,
+            raw_packets: 4,
+            raw_bytes: 4 * raw.len() as u64,
+        });
+    }
+
+    #[test]
+    fn counting_snapshot_since() {
+        let counter = CountingHandler::new();
+        let mut handler = counter.clone();
+        block_on(MessageHandler::handle(&mut handler, message()));
+        let earlier = counter.snapshot();
+        block_on(MessageHandler::handle(&mut handler, message()));
+        block_on(RawPacketHandler::handle(&mut handler, &[0; 8]));
+        assert_eq!(counter.snapshot().since(&earlier), CountingSnapshot {
+            messages: 1,
+            packets: 0,
+            raw_packets: 1,
+            raw_bytes: 8,
+        });
+    }
+
+    #[test]
+    fn pushed_handlers_get_later_messages() {
+        let first = CountingHandler::new();
+        let second = CountingHandler::new();
+        let mut handler = OscHandler::new(vec![first.clone()]);
+        block_on(MessageHandler::handle(&mut handler, message()));
+
+        handler.push(second.clone());
+        assert_eq!(handler.len(), 2);
+        let outputs = block_on(MessageHandler::handle(&mut handler, message()));
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(first.snapshot().messages, 2);
+        assert_eq!(second.snapshot().messages, 1);
+
+        assert!(handler.remove(0).is_some());
+        assert!(handler.remove(1).is_none());
+        block_on(MessageHandler::handle(&mut handler, message()));
+        assert_eq!(first.snapshot().messages, 2);
+        assert_eq!(second.snapshot().messages, 2);
+    }
+}
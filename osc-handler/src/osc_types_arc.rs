@@ -1,3 +1,8 @@
+//! OSC types, that can be shared between handlers without copying.
+//!
+//! Messages are shared as a whole through an [Arc], so cloning a packet never copies the arguments of a message.
+//! The arguments themselves (e.g. a large [rosc::OscType::Blob]) are not shared individually:
+//! converting to a [rosc::OscPacket] copies them, unless the owned conversion is used and the message is not shared anymore.
 use std::sync::Arc;
 use rosc::OscTime;
 
@@ -36,6 +41,7 @@ impl From<&rosc::OscBundle> for OscBundle {
     }
 }
 
+/// Copies the arguments of every message.
 impl From<&OscBundle> for rosc::OscBundle {
     fn from(value: &OscBundle) -> Self {
         Self{
@@ -62,6 +68,7 @@ impl From<rosc::OscPacket> for OscPacket {
     }
 }
 
+/// Copies the arguments of every message. Prefer the owned conversion, if the packet is not needed anymore.
 impl From<&OscPacket> for rosc::OscPacket {
     fn from(value: &OscPacket) -> Self {
         match value {
@@ -69,4 +76,46 @@ impl From<&OscPacket> for rosc::OscPacket {
             OscPacket::Bundle(b) => rosc::OscPacket::Bundle(rosc::OscBundle::from(b)),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Only copies a message, if it is still shared.
+/// The content of a bundle is shared as a whole, so the messages in a bundle are always copied.
+impl From<OscPacket> for rosc::OscPacket {
+    fn from(value: OscPacket) -> Self {
+        match value {
+            OscPacket::Message(v) => rosc::OscPacket::Message(Arc::unwrap_or_clone(v)),
+            OscPacket::Bundle(b) => rosc::OscPacket::Bundle(rosc::OscBundle::from(&b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob_message() -> rosc::OscMessage {
+        rosc::OscMessage { addr: "/blob".to_string(), args: vec![rosc::OscType::Blob(vec![0; 1 << 20])] }
+    }
+
+    fn blob_ptr(packet: &rosc::OscPacket) -> *const u8 {
+        match packet {
+            rosc::OscPacket::Message(rosc::OscMessage { args, .. }) => match args.as_slice() {
+                [rosc::OscType::Blob(blob)] => blob.as_ptr(),
+                other => panic!("Expected a single Blob, got {other:?}"),
+            },
+            other => panic!("Expected a Message, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unshared_messages_are_moved_out() {
+        let packet = rosc::OscPacket::Message(blob_message());
+        let blob = blob_ptr(&packet);
+        let shared = OscPacket::from(packet);
+        let cloned = shared.clone();
+        //Still shared, so the conversion has to copy.
+        let copied = rosc::OscPacket::from(cloned);
+        assert_ne!(blob_ptr(&copied), blob);
+        assert_eq!(blob_ptr(&rosc::OscPacket::from(shared)), blob);
+    }
+}
@@ -1,25 +1,196 @@
-use std::convert::Infallible;
-use std::net::IpAddr;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::net::UdpSocket;
 use tokio::time::MissedTickBehavior;
-use crate::multple_handler::OscHandler;
+use core::iter::Empty;
+use crate::multple_handler::{OscHandler, StubHandler};
 use super::{MessageDestructuring, MessageHandler, PacketHandler, RawPacketHandler};
 
-const DEFAULT_ALLOC:usize = 1024;
+/// The default interval of the heartbeat log.
+/// The heartbeat is only enabled by default in debug builds, to avoid log noise in release builds.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Option<Duration> = if cfg!(debug_assertions) { Some(Duration::from_secs(30)) } else { None };
+/// The default of the longest time the receive loop sleeps, before checking the buffered bundles again.
+pub const DEFAULT_MAX_BUNDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long an incomplete packet is kept in the receive buffer without any new data arriving.
+/// After that, the sender is assumed to have stalled and the incomplete data is discarded.
+pub const PARTIAL_PACKET_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Statistics of a receive loop.
+///
+/// Pass one to [OscReceiverBuilder::stats] and keep a clone of the [Arc] around to read them via [ReceiverStats::snapshot].
+#[derive(Debug, Default)]
+pub struct ReceiverStats {
+    datagrams: AtomicU64,
+    bytes: AtomicU64,
+    packets: AtomicU64,
+    messages: AtomicU64,
+    decode_errors: AtomicU64,
+    bundles_buffered: AtomicU64,
+    bundles_fired: AtomicU64,
+    bundles_cleared: AtomicU64,
+    bundles_pending: AtomicU64,
+}
+
+/// The counts of a [ReceiverStats] at one point in time.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReceiverStatsSnapshot {
+    /// The received UDP datagrams.
+    pub datagrams: u64,
+    /// The total size of all received datagrams in bytes.
+    pub bytes: u64,
+    /// The decoded OSC Packets.
+    pub packets: u64,
+    /// The OSC Messages passed to the message handlers, including the ones from buffered bundles.
+    pub messages: u64,
+    /// How often received data could not be decoded and was discarded.
+    pub decode_errors: u64,
+    /// The bundles, that had a timetag in the future and were buffered.
+    pub bundles_buffered: u64,
+    /// The buffered bundles, that have been applied once their timetag was reached.
+    pub bundles_fired: u64,
+    /// The buffered bundles, that were dropped with [ReceiverControl::clear_pending_bundles].
+    pub bundles_cleared: u64,
+    /// The bundles, that are buffered right now.
+    /// Unlike the other fields this is not a total, so [ReceiverStatsSnapshot::since] keeps the later value.
+    pub bundles_pending: u64,
+}
+
+impl ReceiverStatsSnapshot {
+    /// Returns the counts, that were added between `earlier` and `self`.
+    pub fn since(&self, earlier: &ReceiverStatsSnapshot) -> ReceiverStatsSnapshot {
+        ReceiverStatsSnapshot {
+            datagrams: self.datagrams.saturating_sub(earlier.datagrams),
+            bytes: self.bytes.saturating_sub(earlier.bytes),
+            packets: self.packets.saturating_sub(earlier.packets),
+            messages: self.messages.saturating_sub(earlier.messages),
+            decode_errors: self.decode_errors.saturating_sub(earlier.decode_errors),
+            bundles_buffered: self.bundles_buffered.saturating_sub(earlier.bundles_buffered),
+            bundles_fired: self.bundles_fired.saturating_sub(earlier.bundles_fired),
+            bundles_cleared: self.bundles_cleared.saturating_sub(earlier.bundles_cleared),
+            bundles_pending: self.bundles_pending,
+        }
+    }
+}
+
+impl ReceiverStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current counts.
+    pub fn snapshot(&self) -> ReceiverStatsSnapshot {
+        ReceiverStatsSnapshot {
+            datagrams: self.datagrams.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            packets: self.packets.load(Ordering::Relaxed),
+            messages: self.messages.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            bundles_buffered: self.bundles_buffered.load(Ordering::Relaxed),
+            bundles_fired: self.bundles_fired.load(Ordering::Relaxed),
+            bundles_cleared: self.bundles_cleared.load(Ordering::Relaxed),
+            bundles_pending: self.bundles_pending.load(Ordering::Relaxed),
+        }
+    }
+
+    fn received(&self, datagrams: u64, bytes: u64, packets: u64, messages: u64, bundles_buffered: u64) {
+        self.datagrams.fetch_add(datagrams, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.packets.fetch_add(packets, Ordering::Relaxed);
+        self.messages.fetch_add(messages, Ordering::Relaxed);
+        self.bundles_buffered.fetch_add(bundles_buffered, Ordering::Relaxed);
+    }
+
+    fn bundles_fired(&self, bundles: u64, messages: u64) {
+        self.bundles_fired.fetch_add(bundles, Ordering::Relaxed);
+        self.messages.fetch_add(messages, Ordering::Relaxed);
+    }
+
+    fn decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bundles_cleared(&self, bundles: u64) {
+        self.bundles_cleared.fetch_add(bundles, Ordering::Relaxed);
+    }
+
+    fn set_bundles_pending(&self, bundles: usize) {
+        self.bundles_pending.store(bundles as u64, Ordering::Relaxed);
+    }
+}
+
+/// Controls a running receive loop. Get one with [OscReceiver::control].
+#[derive(Clone, Debug, Default)]
+pub struct ReceiverControl {
+    clear_bundles: Arc<tokio::sync::Notify>,
+}
+
+impl ReceiverControl {
+    /// Asks the receive loop to drop all buffered bundles without applying them.
+    /// How many were dropped is counted in [ReceiverStatsSnapshot::bundles_cleared].
+    pub fn clear_pending_bundles(&self) {
+        self.clear_bundles.notify_one();
+    }
+}
+
+/// Gets the data, that the receive loop could not decode and discards.
+/// This allows capturing malformed packets, to diagnose them later.
+pub trait BadPacketSink: Send + Sync {
+    /// `reason` describes, why `data` could not be decoded.
+    fn bad_packet(&self, data: &[u8], reason: &dyn std::fmt::Display);
+}
+
+/// The receive socket.
+/// Logs its address once it is dropped, so that the logs show when the port is released.
+struct RecvSocket(UdpSocket);
+
+impl core::ops::Deref for RecvSocket {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for RecvSocket {
+    fn drop(&mut self) {
+        match self.0.local_addr() {
+            Ok(addr) => log::debug!("Closing the OSC UDP receive Socket on {addr}."),
+            Err(e) => log::debug!("Closing the OSC UDP receive Socket. Its address is unknown: {e}"),
+        }
+    }
+}
+
+/// Raises `max_message_size` to [crate::OSC_MIN_MESSAGE_SIZE], since a smaller receive buffer would drop almost everything.
+fn clamp_max_message_size(max_message_size: usize) -> usize {
+    if max_message_size < crate::OSC_MIN_MESSAGE_SIZE {
+        log::warn!("The maximum message size of {max_message_size} bytes is too small. Using {} bytes instead.", crate::OSC_MIN_MESSAGE_SIZE);
+        return crate::OSC_MIN_MESSAGE_SIZE;
+    }
+    max_message_size
+}
 
 ///Allows for sending OSC Messages
 pub struct OscReceiver<I1, I2, I3> {
-    osc_recv:UdpSocket,
+    osc_recv:RecvSocket,
     max_message_size: usize,
+    heartbeat: Option<Duration>,
+    max_bundle_check_interval: Duration,
+    stats: Arc<ReceiverStats>,
+    bad_packet_sink: Option<Arc<dyn BadPacketSink>>,
+    control: ReceiverControl,
     message_handlers: I1,
     packet_handlers: I2,
     raw_packet_handlers: I3,
 }
 impl<I1, I2, I3> OscReceiver<I1, I2, I3> {
-    /// Creates a new OSC Sender.
-    /// This will bind a UDP Socket to a random port and connect it to the specified port on the specified ip.
-    /// The binding and the connection can both fail, so this function returns a Result.
+    /// Creates a new OSC Receiver.
+    /// This will bind a UDP Socket to the specified port on the specified ip.
+    /// The binding can fail, so this function returns a Result.
+    ///
+    /// This is a shorthand for [OscReceiverBuilder].
     pub async fn new(
         ip:IpAddr,
         port:u16,
@@ -27,35 +198,305 @@ impl<I1, I2, I3> OscReceiver<I1, I2, I3> {
         message_handlers: I1,
         packet_handlers: I2,
         raw_packet_handlers: I3,
-    ) -> Result<Self, std::io::Error>{
-        let osc_recv = match UdpSocket::bind((ip, port)).await {
-            Ok(v) => v,
-            Err(e) => {
-                log::warn!("Failed to Bind and/or connect the OSC UDP receive socket: {}", e);
-                Err(e)?
-            }
-        };
-        log::info!("Bound OSC UDP receive Socket.");
-        Ok(Self{
-            osc_recv,
-            max_message_size,
+    ) -> Result<Self, std::io::Error>
+    where
+        I1: Iterator,
+        I2: Iterator,
+        I3: Iterator,
+    {
+        OscReceiverBuilder::new()
+            .bind(ip, port)
+            .max_message_size(max_message_size)
+            .message_handlers(message_handlers)
+            .packet_handlers(packet_handlers)
+            .raw_packet_handlers(raw_packet_handlers)
+            .build()
+            .await
+    }
+
+    /// Creates a new OSC Receiver, that receives on an already bound UDP Socket.
+    /// This is useful, if the socket is bound elsewhere (e.g. passed in by the service manager),
+    /// or bound to port 0 to let the OS pick a free port.
+    /// A `max_message_size` below [crate::OSC_MIN_MESSAGE_SIZE] is raised to it.
+    pub fn from_socket(
+        osc_recv: UdpSocket,
+        max_message_size: usize,
+        message_handlers: I1,
+        packet_handlers: I2,
+        raw_packet_handlers: I3,
+    ) -> Self {
+        Self{
+            osc_recv: RecvSocket(osc_recv),
+            max_message_size: clamp_max_message_size(max_message_size),
+            heartbeat: DEFAULT_HEARTBEAT_INTERVAL,
+            max_bundle_check_interval: DEFAULT_MAX_BUNDLE_CHECK_INTERVAL,
+            stats: Default::default(),
+            bad_packet_sink: None,
+            control: Default::default(),
             message_handlers,
             packet_handlers,
             raw_packet_handlers,
-        })
+        }
+    }
+
+    /// Sets how often the receive loop logs (at debug level), how much it processed since the last heartbeat.
+    /// This shows, that the receive loop is still alive, even if nothing is received.
+    /// `None` disables the heartbeat. Defaults to [DEFAULT_HEARTBEAT_INTERVAL].
+    ///
+    /// The heartbeat is checked on the receive loop's 1 second tick, so intervals below 1 second are rounded up.
+    pub fn with_heartbeat(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Sets the longest time the receive loop sleeps, before checking the buffered bundles again.
+    ///
+    /// Bundles with a timetag in the future are applied once their time has come,
+    /// as the receive loop sleeps until the earliest buffered bundle is due.
+    /// This only caps that sleep, so that the buffer is still checked periodically
+    /// (e.g. if the system clock jumps). Defaults to [DEFAULT_MAX_BUNDLE_CHECK_INTERVAL].
+    pub fn with_max_bundle_check_interval(mut self, interval: Duration) -> Self {
+        self.max_bundle_check_interval = interval;
+        self
+    }
+
+    /// Sets where the receive loop counts, what it received.
+    /// Keep a clone of `stats` around to read them. Defaults to a fresh [ReceiverStats].
+    pub fn with_stats(mut self, stats: Arc<ReceiverStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Sets where undecodable data is passed to, before it is discarded. Defaults to `None`.
+    pub fn with_bad_packet_sink(mut self, sink: Option<Arc<dyn BadPacketSink>>) -> Self {
+        self.bad_packet_sink = sink;
+        self
     }
+
+    /// Returns the local address the receive socket is bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.osc_recv.local_addr()
+    }
+
+    /// Returns a handle to control the receive loop, once it is started with [OscReceiver::listen].
+    pub fn control(&self) -> ReceiverControl {
+        self.control.clone()
+    }
+}
+
+/// Where the [OscReceiverBuilder] gets its UDP Socket from.
+enum SocketSource {
+    Bind(SocketAddr),
+    Socket(UdpSocket),
 }
 
+/// Builds an [OscReceiver].
+///
+/// Only the address to bind to (or an already bound socket) is required.
+/// Everything else has a default:
+/// - `max_message_size`: [crate::OSC_RECV_BUFFER_SIZE]
+/// - `heartbeat`: [DEFAULT_HEARTBEAT_INTERVAL]
+/// - `max_bundle_check_interval`: [DEFAULT_MAX_BUNDLE_CHECK_INTERVAL]
+/// - `stats`: a fresh [ReceiverStats]
+/// - `bad_packet_sink`: none
+/// - handlers: none
+///
+/// ```no_run
+/// # async fn example() -> std::io::Result<()> {
+/// use osc_handler::multple_handler::StubHandler;
+/// use osc_handler::receiver::OscReceiverBuilder;
+///
+/// let receiver = OscReceiverBuilder::new()
+///     .bind(std::net::Ipv4Addr::LOCALHOST.into(), 9001)
+///     .message_handlers([StubHandler])
+///     .build()
+///     .await?;
+/// let mut js = tokio::task::JoinSet::new();
+/// receiver.listen(&mut js, std::future::pending());
+/// # Ok(())
+/// # }
+/// ```
+pub struct OscReceiverBuilder<I1, I2, I3> {
+    source: Option<SocketSource>,
+    max_message_size: usize,
+    heartbeat: Option<Duration>,
+    max_bundle_check_interval: Duration,
+    stats: Arc<ReceiverStats>,
+    bad_packet_sink: Option<Arc<dyn BadPacketSink>>,
+    message_handlers: I1,
+    packet_handlers: I2,
+    raw_packet_handlers: I3,
+}
+
+impl OscReceiverBuilder<Empty<StubHandler>, Empty<StubHandler>, Empty<StubHandler>> {
+    pub fn new() -> Self {
+        Self{
+            source: None,
+            max_message_size: crate::OSC_RECV_BUFFER_SIZE,
+            heartbeat: DEFAULT_HEARTBEAT_INTERVAL,
+            max_bundle_check_interval: DEFAULT_MAX_BUNDLE_CHECK_INTERVAL,
+            stats: Default::default(),
+            bad_packet_sink: None,
+            message_handlers: core::iter::empty(),
+            packet_handlers: core::iter::empty(),
+            raw_packet_handlers: core::iter::empty(),
+        }
+    }
+}
+
+impl Default for OscReceiverBuilder<Empty<StubHandler>, Empty<StubHandler>, Empty<StubHandler>> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I1, I2, I3> OscReceiverBuilder<I1, I2, I3> {
+    /// Binds a new UDP Socket to the specified port on the specified ip, once [OscReceiverBuilder::build] is called.
+    /// This replaces a previously set [OscReceiverBuilder::socket].
+    pub fn bind(self, ip: IpAddr, port: u16) -> Self {
+        self.bind_addr(SocketAddr::new(ip, port))
+    }
+
+    /// Like [OscReceiverBuilder::bind], but takes a full socket address.
+    /// This is needed for IPv6 link-local addresses, which need a scope id.
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.source = Some(SocketSource::Bind(addr));
+        self
+    }
+
+    /// Receives on an already bound UDP Socket.
+    /// This replaces a previously set [OscReceiverBuilder::bind].
+    pub fn socket(mut self, socket: UdpSocket) -> Self {
+        self.source = Some(SocketSource::Socket(socket));
+        self
+    }
+
+    /// Sets the maximum size of a received OSC Packet in bytes.
+    /// Sizes below [crate::OSC_MIN_MESSAGE_SIZE] are raised to it.
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = clamp_max_message_size(max_message_size);
+        self
+    }
+
+    /// See [OscReceiver::with_heartbeat].
+    pub fn heartbeat(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// See [OscReceiver::with_max_bundle_check_interval].
+    pub fn max_bundle_check_interval(mut self, interval: Duration) -> Self {
+        self.max_bundle_check_interval = interval;
+        self
+    }
+
+    /// See [OscReceiver::with_stats].
+    pub fn stats(mut self, stats: Arc<ReceiverStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// See [OscReceiver::with_bad_packet_sink].
+    pub fn bad_packet_sink(mut self, sink: Option<Arc<dyn BadPacketSink>>) -> Self {
+        self.bad_packet_sink = sink;
+        self
+    }
+
+    /// Sets the handlers, that get every received OSC Message.
+    pub fn message_handlers<I: IntoIterator>(self, message_handlers: I) -> OscReceiverBuilder<I::IntoIter, I2, I3> {
+        OscReceiverBuilder{
+            source: self.source,
+            max_message_size: self.max_message_size,
+            heartbeat: self.heartbeat,
+            max_bundle_check_interval: self.max_bundle_check_interval,
+            stats: self.stats,
+            bad_packet_sink: self.bad_packet_sink,
+            message_handlers: message_handlers.into_iter(),
+            packet_handlers: self.packet_handlers,
+            raw_packet_handlers: self.raw_packet_handlers,
+        }
+    }
+
+    /// Sets the handlers, that get every received OSC Packet.
+    pub fn packet_handlers<I: IntoIterator>(self, packet_handlers: I) -> OscReceiverBuilder<I1, I::IntoIter, I3> {
+        OscReceiverBuilder{
+            source: self.source,
+            max_message_size: self.max_message_size,
+            heartbeat: self.heartbeat,
+            max_bundle_check_interval: self.max_bundle_check_interval,
+            stats: self.stats,
+            bad_packet_sink: self.bad_packet_sink,
+            message_handlers: self.message_handlers,
+            packet_handlers: packet_handlers.into_iter(),
+            raw_packet_handlers: self.raw_packet_handlers,
+        }
+    }
+
+    /// Sets the handlers, that get the raw bytes of every received OSC Packet.
+    pub fn raw_packet_handlers<I: IntoIterator>(self, raw_packet_handlers: I) -> OscReceiverBuilder<I1, I2, I::IntoIter> {
+        OscReceiverBuilder{
+            source: self.source,
+            max_message_size: self.max_message_size,
+            heartbeat: self.heartbeat,
+            max_bundle_check_interval: self.max_bundle_check_interval,
+            stats: self.stats,
+            bad_packet_sink: self.bad_packet_sink,
+            message_handlers: self.message_handlers,
+            packet_handlers: self.packet_handlers,
+            raw_packet_handlers: raw_packet_handlers.into_iter(),
+        }
+    }
+
+    /// Creates the [OscReceiver].
+    /// This binds the UDP Socket, if [OscReceiverBuilder::bind] was used.
+    /// The binding can fail, so this function returns a Result.
+    /// If neither [OscReceiverBuilder::bind] nor [OscReceiverBuilder::socket] were called, an [std::io::ErrorKind::InvalidInput] error is returned.
+    pub async fn build(self) -> Result<OscReceiver<I1, I2, I3>, std::io::Error> {
+        let osc_recv = match self.source {
+            Some(SocketSource::Socket(socket)) => socket,
+            Some(SocketSource::Bind(addr)) => match UdpSocket::bind(addr).await {
+                Ok(v) => {
+                    log::info!("Bound OSC UDP receive Socket.");
+                    v
+                },
+                Err(e) => {
+                    log::warn!("Failed to Bind and/or connect the OSC UDP receive socket: {}", e);
+                    Err(e)?
+                }
+            },
+            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "No address to bind to or socket was specified for the OSC Receiver")),
+        };
+        Ok(OscReceiver::from_socket(
+            osc_recv,
+            self.max_message_size,
+            self.message_handlers,
+            self.packet_handlers,
+            self.raw_packet_handlers,
+        ).with_heartbeat(self.heartbeat)
+            .with_max_bundle_check_interval(self.max_bundle_check_interval)
+            .with_stats(self.stats)
+            .with_bad_packet_sink(self.bad_packet_sink))
+    }
+}
 
 impl<
     H1:MessageHandler + Sync + Send + 'static, I1:Iterator<Item = H1>,
     H2:PacketHandler + Sync + Send + 'static, I2:Iterator<Item = H2>,
     H3:RawPacketHandler + Sync + Send + 'static, I3:Iterator<Item = H3>,
 > OscReceiver<I1, I2, I3> {
-    pub fn listen(self, js: &mut tokio::task::JoinSet<Infallible>) {
+    /// Spawns the receive loop onto the given [tokio::task::JoinSet].
+    ///
+    /// The loop runs until `shutdown` completes.
+    /// Packets that are already being handled (and the sends they cause) are finished before the loop exits.
+    pub fn listen(self, js: &mut tokio::task::JoinSet<()>, shutdown: impl Future<Output = ()> + Send + 'static) {
         let Self {
             osc_recv,
             max_message_size,
+            heartbeat,
+            max_bundle_check_interval,
+            stats,
+            bad_packet_sink,
+            control,
             message_handlers,
             packet_handlers,
             raw_packet_handlers,
@@ -68,71 +509,432 @@ impl<
         js.spawn(async move {
             let mut periodic = tokio::time::interval(Duration::from_secs(1));
             periodic.set_missed_tick_behavior(MissedTickBehavior::Skip);
-            let mut buf = Vec::with_capacity(DEFAULT_ALLOC);
+            //Every datagram is received whole into `scratch` and then appended to `buf`.
+            //Both buffers are reused for the entire lifetime of the loop.
+            let mut scratch = vec![0u8; max_message_size];
+            let mut buf = vec![0u8; max_message_size];
+            //How many bytes at the start of `buf` are received data.
+            let mut filled = 0;
+            tokio::pin!(shutdown);
+            let mut last_heartbeat = (tokio::time::Instant::now(), stats.snapshot());
+            //When data was last appended to a non-empty `buf`. `None`, if `buf` is empty.
+            let mut partial_since: Option<tokio::time::Instant> = None;
 
             loop {
+                //OSC over UDP should contain exactly one OSC Packet per datagram.
+                //Some senders however split one packet over multiple datagrams, or put multiple packets into one,
+                //a bit like packets would be framed over a stream (e.g. with SLIP).
+                //To handle both, received data is appended to `buf`, decoded packets are removed from the front
+                //and any undecodable rest is kept, until either more data completes it or `max_message_size` is reached.
+                //If a datagram doesn't fit after the kept data, the kept data is discarded first, so `buf` never grows.
+                //Sleep until the earliest buffered bundle is due, but at most `max_bundle_check_interval`.
+                //This is recomputed on every iteration, so bundles buffered by a just received packet are taken into account.
+                let bundle_check = tokio::time::Instant::now() + handler.time_until_next_bundle()
+                    .map_or(max_bundle_check_interval, |until| until.min(max_bundle_check_interval));
                 tokio::select! {
                     biased;
+                    _ = &mut shutdown => {
+                        log::info!("Shutdown requested. Stopping the OSC receive loop.");
+                        //The buffered bundles are dropped with the loop.
+                        stats.set_bundles_pending(0);
+                        return;
+                    },
+                    _ = control.clear_bundles.notified() => {
+                        let cleared = handler.clear_pending_bundles();
+                        log::info!("Cleared {cleared} pending bundles.");
+                        stats.bundles_cleared(cleared as u64);
+                        stats.set_bundles_pending(0);
+                    },
+                    _ = tokio::time::sleep_until(partial_since.unwrap_or_else(tokio::time::Instant::now) + PARTIAL_PACKET_TIMEOUT), if partial_since.is_some() => {
+                        log::warn!("No more data arrived for an incomplete OSC Packet within {}s. Discarding {} bytes.", PARTIAL_PACKET_TIMEOUT.as_secs(), filled);
+                        stats.decode_error();
+                        if let Some(sink) = &bad_packet_sink {
+                            sink.bad_packet(&buf[..filled], &format_args!("No more data arrived within {}s", PARTIAL_PACKET_TIMEOUT.as_secs()));
+                        }
+                        handler.raw_handler.handle(&buf[..filled]).await;
+                        filled = 0;
+                        partial_since = None;
+                    },
+                    _ = tokio::time::sleep_until(bundle_check) => {
+                        let bundles = handler.check_osc_bundles();
+                        let bundle_count = bundles.len() as u64;
+                        let messages = bundles.into_iter()
+                            .flat_map(|(_,r)| r.to_messages_vec())
+                            .collect::<Vec<_>>();
+                        stats.bundles_fired(bundle_count, messages.len() as u64);
+                        stats.set_bundles_pending(handler.pending_bundles());
+                        for f in messages{
+                            f.await;
+                        }
+                    },
                     _ = periodic.tick() => {
-                        for (_,r) in handler.check_osc_bundles(){
-                            for f in r.to_messages_vec(){
-                                f.await;
+                        if let Some(interval) = heartbeat {
+                            if last_heartbeat.0.elapsed() >= interval {
+                                let snapshot = stats.snapshot();
+                                let counts = snapshot.since(&last_heartbeat.1);
+                                log::debug!(
+                                    "OSC receive loop is alive. In the last {}s: {} datagrams ({} bytes), {} packets, {} messages.",
+                                    last_heartbeat.0.elapsed().as_secs(), counts.datagrams, counts.bytes, counts.packets, counts.messages
+                                );
+                                last_heartbeat = (tokio::time::Instant::now(), snapshot);
                             }
                         }
                     },
-                    out = osc_recv.recv_buf(&mut buf) => {
-                        buf = match out {
+                    out = osc_recv.recv(&mut scratch) => {
+                        match out {
                             Err(e) => {
                                 log::error!("Error receiving udp packet. Skipping Packet: {}",e);
-                                if !buf.is_empty() {
-                                    handler.raw_handler.handle(buf.as_slice()).await;
+                                if filled > 0 {
+                                    handler.raw_handler.handle(&buf[..filled]).await;
                                 }
-                                Vec::with_capacity(DEFAULT_ALLOC)
+                                filled = 0;
+                                partial_since = None;
                             }
-                            Ok(_) => {
-                                let (rest, jsr, fut, e) = handler.handle_raw_packets(buf.as_slice());
-                                futures::future::join(
-                                    fut.into_iter().map(|(jp, res)|{
-                                        futures::future::join(jp, res.to_messages_vec().into_iter().collect::<futures::future::JoinAll<_>>())
-                                    }).collect::<futures::future::JoinAll<_>>(),
-                                    jsr,
-                                ).await;
-
-                                match e {
-                                    None => {
-                                        let mut new_buf = Vec::with_capacity(DEFAULT_ALLOC);
-                                        new_buf.extend_from_slice(rest);
-                                        new_buf
-                                    },
-                                    Some(rosc::OscError::BadPacket(reason)) => {
-                                        log::trace!("OSC packet not decodable yet? Reason: {reason}");
-                                        if buf.len() >= max_message_size {
-                                            handler.raw_handler.handle(buf.as_slice()).await;
-                                            Vec::with_capacity(DEFAULT_ALLOC)
-                                        } else{
-                                            continue;
+                            Ok(0) => {
+                                //Empty datagrams are sometimes used as probes or keepalives. There is nothing to decode.
+                                #[cfg(all(debug_assertions, feature="debug_log"))]
+                                log::trace!("Ignoring an empty UDP datagram.");
+                                stats.received(1, 0, 0, 0, 0);
+                            }
+                            Ok(len) => {
+                                if filled + len > max_message_size {
+                                    log::warn!("A datagram of {len} bytes doesn't fit after {filled} bytes of an incomplete OSC Packet. Discarding the incomplete Packet.");
+                                    stats.decode_error();
+                                    if let Some(sink) = &bad_packet_sink {
+                                        sink.bad_packet(&buf[..filled], &"Incomplete OSC Packet, that was followed by a datagram, that doesn't fit");
+                                    }
+                                    handler.raw_handler.handle(&buf[..filled]).await;
+                                    filled = 0;
+                                }
+                                buf[filled..filled + len].copy_from_slice(&scratch[..len]);
+                                filled += len;
+                                let (rest, jsr, fut, e) = handler.handle_raw_packets(&buf[..filled]);
+                                let consumed = filled - rest.len();
+                                let packets = fut.len() as u64;
+                                let mut messages = 0;
+                                let mut bundles_buffered = 0;
+                                let fut = fut.into_iter().map(|(jp, res)|{
+                                    #[cfg(all(debug_assertions, feature="debug_log"))]
+                                    log::trace!("Received OSC Packet with {} applied messages and {} deferred bundles.", res.message_count(), res.deferred_uuids().len());
+                                    bundles_buffered += res.deferred_uuids().len() as u64;
+                                    let res = res.to_messages_vec();
+                                    messages += res.len() as u64;
+                                    futures::future::join(jp, res.into_iter().collect::<futures::future::JoinAll<_>>())
+                                }).collect::<futures::future::JoinAll<_>>();
+                                stats.received(1, len as u64, packets, messages, bundles_buffered);
+                                stats.set_bundles_pending(handler.pending_bundles());
+                                futures::future::join(fut, jsr).await;
+
+                                let wait_for_more_data = match &e {
+                                    None => false,
+                                    Some(e) => match crate::classify_decode_error(e) {
+                                        crate::DecodeDisposition::NeedMoreData => {
+                                            log::trace!("OSC packet not decodable yet. Waiting for more data. Reason: {e}");
+                                            true
                                         }
-                                    },
-                                    Some(rosc::OscError::ReadError(nom::error::ErrorKind::Eof)) => {
-                                        log::trace!("Got EOF Read error when trying to deserialize packet. Waiting for more data");
-                                        if buf.len() >= max_message_size {
-                                            handler.raw_handler.handle(buf.as_slice()).await;
-                                            Vec::with_capacity(DEFAULT_ALLOC)
-                                        } else{
-                                            continue;
+                                        crate::DecodeDisposition::Fatal => {
+                                            log::error!("Error handling raw packet. Clearing internal receive buffer and skipping packet: {e}");
+                                            false
                                         }
                                     },
-                                    Some(e) => {
-                                        log::error!("Error handling raw packet. Clearing internal receive buffer and skipping packet: {e}");
-                                        handler.raw_handler.handle(buf.as_slice()).await;
-                                        Vec::with_capacity(max_message_size)
+                                };
+                                //The decoded packets have already been passed to the raw handlers.
+                                buf.copy_within(consumed..filled, 0);
+                                filled -= consumed;
+                                if !wait_for_more_data || filled >= max_message_size {
+                                    if filled > 0 {
+                                        stats.decode_error();
+                                        if let Some(sink) = &bad_packet_sink {
+                                            match &e {
+                                                Some(e) => sink.bad_packet(&buf[..filled], e),
+                                                None => sink.bad_packet(&buf[..filled], &"Undecodable data"),
+                                            }
+                                        }
+                                        handler.raw_handler.handle(&buf[..filled]).await;
                                     }
+                                    filled = 0;
                                 }
+                                //Every receive restarts the timeout, so a buffer that is still growing is never discarded.
+                                partial_since = if filled == 0 { None } else { Some(tokio::time::Instant::now()) };
                             }
-                        };
+                        }
                     }
                 }
             }
         });
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use rosc::{OscMessage, OscPacket, OscType};
+    use tokio::sync::mpsc;
+    use super::*;
+
+    /// Passes every message it gets on to a channel.
+    struct ChannelHandler(mpsc::UnboundedSender<Arc<OscMessage>>);
+
+    impl MessageHandler for ChannelHandler {
+        type Fut = core::future::Ready<()>;
+        type Output = ();
+
+        fn handle(&mut self, message: Arc<OscMessage>) -> Self::Fut {
+            let _ = self.0.send(message);
+            core::future::ready(())
+        }
+    }
+
+    /// Keeps everything the receive loop discards.
+    #[derive(Default)]
+    struct CapturingSink(std::sync::Mutex<Vec<Vec<u8>>>);
+
+    impl BadPacketSink for CapturingSink {
+        fn bad_packet(&self, data: &[u8], _reason: &dyn std::fmt::Display) {
+            self.0.lock().unwrap().push(data.to_vec());
+        }
+    }
+
+    fn message() -> OscMessage {
+        OscMessage { addr: "/avatar/parameters/Test".to_string(), args: vec![OscType::Float(0.5)] }
+    }
+
+    async fn local_socket() -> UdpSocket {
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap()
+    }
+
+    type TestReceiver = OscReceiver<core::iter::Once<ChannelHandler>, Empty<StubHandler>, Empty<StubHandler>>;
+
+    /// Starts a receive loop on an ephemeral port, that passes all messages to the returned channel.
+    async fn start(js: &mut tokio::task::JoinSet<()>, shutdown: impl Future<Output = ()> + Send + 'static) -> (SocketAddr, mpsc::UnboundedReceiver<Arc<OscMessage>>) {
+        start_with(js, shutdown, |receiver| receiver).await
+    }
+
+    /// Like [start], but `configure` can change the receiver before it is started.
+    async fn start_with(js: &mut tokio::task::JoinSet<()>, shutdown: impl Future<Output = ()> + Send + 'static, configure: impl FnOnce(TestReceiver) -> TestReceiver) -> (SocketAddr, mpsc::UnboundedReceiver<Arc<OscMessage>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let receiver = configure(OscReceiver::from_socket(
+            local_socket().await,
+            crate::OSC_RECV_BUFFER_SIZE,
+            core::iter::once(ChannelHandler(tx)),
+            core::iter::empty::<StubHandler>(),
+            core::iter::empty::<StubHandler>(),
+        ));
+        let addr = receiver.local_addr().unwrap();
+        receiver.listen(js, shutdown);
+        (addr, rx)
+    }
+
+    /// Sends an encoded OSC Packet to `addr`.
+    async fn send(addr: SocketAddr, packet: &OscPacket) {
+        local_socket().await.send_to(&rosc::encoder::encode(packet).unwrap(), addr).await.unwrap();
+    }
+
+    /// A bundle with [message], that is due `delay` from now.
+    fn bundle_in(delay: Duration) -> OscPacket {
+        let timetag = rosc::OscTime::try_from(std::time::SystemTime::now() + delay).unwrap();
+        OscPacket::Bundle(rosc::OscBundle { timetag, content: vec![OscPacket::Message(message())] })
+    }
+
+    #[tokio::test]
+    async fn from_socket_round_trip() {
+        let mut js = tokio::task::JoinSet::new();
+        let (addr, mut rx) = start(&mut js, std::future::pending()).await;
+
+        send(addr, &OscPacket::Message(message())).await;
+
+        let received = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        assert_eq!(*received, message());
+        js.abort_all();
+    }
+
+    #[tokio::test]
+    async fn bundles_are_applied_when_due() {
+        const DELAY: Duration = Duration::from_millis(200);
+        //Generous for slow machines, but far below the check interval, so the loop must have slept until the bundle was due.
+        const TOLERANCE: Duration = Duration::from_secs(2);
+        const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+        let mut js = tokio::task::JoinSet::new();
+        let (addr, mut rx) = start_with(&mut js, std::future::pending(), |receiver| receiver.with_max_bundle_check_interval(CHECK_INTERVAL)).await;
+
+        let sent = tokio::time::Instant::now();
+        send(addr, &bundle_in(DELAY)).await;
+        let received = tokio::time::timeout(DELAY + TOLERANCE * 2, rx.recv()).await.unwrap().unwrap();
+        let elapsed = sent.elapsed();
+        assert_eq!(*received, message());
+        //OscTime only has a resolution of about 233 picoseconds, so the bundle may fire a tiny bit early.
+        assert!(elapsed + Duration::from_millis(1) >= DELAY, "Applied after {elapsed:?}, before the bundle was due");
+        assert!(elapsed <= DELAY + TOLERANCE, "Applied after {elapsed:?}, more than {TOLERANCE:?} late");
+        js.abort_all();
+    }
+
+    #[tokio::test]
+    async fn truncated_packets_are_flushed_after_the_timeout() {
+        let sink = Arc::new(CapturingSink::default());
+        let stats = Arc::new(ReceiverStats::new());
+        let mut js = tokio::task::JoinSet::new();
+        let (addr, mut rx) = start_with(&mut js, std::future::pending(), |receiver| receiver
+            .with_bad_packet_sink(Some(sink.clone()))
+            .with_stats(stats.clone())
+        ).await;
+
+        let packet = rosc::encoder::encode(&OscPacket::Message(message())).unwrap();
+        //Everything but the argument.
+        let truncated = &packet[..packet.len() - 4];
+        local_socket().await.send_to(truncated, addr).await.unwrap();
+
+        tokio::time::sleep(PARTIAL_PACKET_TIMEOUT / 2).await;
+        assert!(sink.0.lock().unwrap().is_empty(), "Flushed before the timeout");
+        tokio::time::sleep(PARTIAL_PACKET_TIMEOUT).await;
+        assert_eq!(*sink.0.lock().unwrap(), vec![truncated.to_vec()]);
+        assert_eq!(stats.snapshot().decode_errors, 1);
+
+        //The flushed data doesn't corrupt the next packet.
+        send(addr, &OscPacket::Message(message())).await;
+        let received = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        assert_eq!(*received, message());
+        js.abort_all();
+    }
+
+    #[tokio::test]
+    async fn datagrams_that_dont_fit_after_a_partial_packet_are_received_whole() {
+        let sink = Arc::new(CapturingSink::default());
+        let stats = Arc::new(ReceiverStats::new());
+        let mut js = tokio::task::JoinSet::new();
+        let (addr, mut rx) = start_with(&mut js, std::future::pending(), |receiver| receiver
+            .with_bad_packet_sink(Some(sink.clone()))
+            .with_stats(stats.clone())
+        ).await;
+
+        let packet = rosc::encoder::encode(&OscPacket::Message(message())).unwrap();
+        let truncated = &packet[..packet.len() - 4];
+        let large = OscMessage { addr: "/large".to_string(), args: vec![OscType::Blob(vec![7; crate::OSC_RECV_BUFFER_SIZE - 32])] };
+        let encoded = rosc::encoder::encode(&OscPacket::Message(large.clone())).unwrap();
+        assert!(truncated.len() + encoded.len() > crate::OSC_RECV_BUFFER_SIZE);
+        assert!(encoded.len() <= crate::OSC_RECV_BUFFER_SIZE);
+
+        let socket = local_socket().await;
+        socket.send_to(truncated, addr).await.unwrap();
+        socket.send_to(&encoded, addr).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        assert_eq!(*received, large);
+        assert_eq!(*sink.0.lock().unwrap(), vec![truncated.to_vec()]);
+        assert_eq!(stats.snapshot().decode_errors, 1);
+        js.abort_all();
+    }
+
+    #[tokio::test]
+    async fn small_max_message_sizes_are_raised() {
+        let receiver = OscReceiverBuilder::new()
+            .socket(local_socket().await)
+            .max_message_size(0)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(receiver.max_message_size, crate::OSC_MIN_MESSAGE_SIZE);
+        let receiver = OscReceiver::from_socket(
+            local_socket().await,
+            crate::OSC_MIN_MESSAGE_SIZE - 1,
+            core::iter::empty::<StubHandler>(),
+            core::iter::empty::<StubHandler>(),
+            core::iter::empty::<StubHandler>(),
+        );
+        assert_eq!(receiver.max_message_size, crate::OSC_MIN_MESSAGE_SIZE);
+        assert_eq!(OscReceiverBuilder::new().max_message_size(4096).max_message_size, 4096);
+    }
+
+    #[tokio::test]
+    async fn stats_count_received_packets() {
+        let stats = Arc::new(ReceiverStats::new());
+        let mut js = tokio::task::JoinSet::new();
+        let (addr, mut rx) = start_with(&mut js, std::future::pending(), |receiver| receiver.with_stats(stats.clone())).await;
+        let message = OscPacket::Message(message());
+        let bundle = OscPacket::Bundle(rosc::OscBundle { timetag: (0, 1).into(), content: vec![message.clone(), message.clone()] });
+
+        send(addr, &message).await;
+        send(addr, &bundle).await;
+        for _ in 0..3 {
+            tokio::time::timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        }
+        let bytes = rosc::encoder::encode(&message).unwrap().len() + rosc::encoder::encode(&bundle).unwrap().len();
+        assert_eq!(stats.snapshot(), ReceiverStatsSnapshot {
+            datagrams: 2,
+            bytes: bytes as u64,
+            packets: 2,
+            messages: 3,
+            ..ReceiverStatsSnapshot::default()
+        });
+        js.abort_all();
+    }
+
+    #[tokio::test]
+    async fn empty_datagrams_are_ignored() {
+        let sink = Arc::new(CapturingSink::default());
+        let stats = Arc::new(ReceiverStats::new());
+        let mut js = tokio::task::JoinSet::new();
+        let (addr, mut rx) = start_with(&mut js, std::future::pending(), |receiver| receiver
+            .with_bad_packet_sink(Some(sink.clone()))
+            .with_stats(stats.clone())
+        ).await;
+
+        local_socket().await.send_to(&[], addr).await.unwrap();
+        //The loop still handles the next packet.
+        send(addr, &OscPacket::Message(message())).await;
+        let received = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+        assert_eq!(*received, message());
+        assert!(rx.try_recv().is_err(), "The empty datagram reached the handlers");
+        assert!(sink.0.lock().unwrap().is_empty());
+        let stats = stats.snapshot();
+        assert_eq!(stats.datagrams, 2);
+        assert_eq!(stats.packets, 1);
+        assert_eq!(stats.decode_errors, 0);
+        js.abort_all();
+    }
+
+    #[tokio::test]
+    async fn pending_bundles_can_be_cleared() {
+        let stats = Arc::new(ReceiverStats::new());
+        let mut js = tokio::task::JoinSet::new();
+        let mut control = None;
+        let (addr, mut rx) = start_with(&mut js, std::future::pending(), |receiver| {
+            control = Some(receiver.control());
+            receiver.with_stats(stats.clone())
+        }).await;
+        let control = control.unwrap();
+
+        for _ in 0..3 {
+            send(addr, &bundle_in(Duration::from_millis(300))).await;
+        }
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while stats.snapshot().bundles_pending < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+
+        control.clear_pending_bundles();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while stats.snapshot().bundles_cleared < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+        assert_eq!(stats.snapshot().bundles_pending, 0);
+        //None of them are applied, once they would have been due.
+        assert!(tokio::time::timeout(Duration::from_millis(500), rx.recv()).await.is_err());
+        js.abort_all();
+    }
+
+    #[tokio::test]
+    async fn listen_stops_on_shutdown() {
+        let (shutdown, stopped) = tokio::sync::oneshot::channel::<()>();
+        let mut js = tokio::task::JoinSet::new();
+        let (addr, _rx) = start(&mut js, async move {
+            let _ = stopped.await;
+        }).await;
+        assert_ne!(addr.port(), 0);
+
+        shutdown.send(()).unwrap();
+        let stopped = tokio::time::timeout(Duration::from_secs(1), js.join_next()).await
+            .expect("The receive loop didn't stop after the shutdown");
+        assert!(matches!(stopped, Some(Ok(()))), "{stopped:?}");
+        assert!(js.is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//! Recording of received raw packets, and replaying them into handlers.
+//!
+//! A recording starts with [MAGIC], followed by one frame per received datagram:
+//! - the time since the recording started in microseconds (u64, little endian)
+//! - the length of the datagram in bytes (u32, little endian)
+//! - the datagram itself
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use crate::router::OscRouter;
+use crate::{MessageHandler, PacketHandler, RawPacketHandler};
+
+/// The first bytes of every recording.
+pub const MAGIC: &[u8; 8] = b"OSCREC1\n";
+const FRAME_HEADER_SIZE: usize = 12;
+
+/// One recorded datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// When the datagram was received, relative to the start of the recording.
+    pub offset: Duration,
+    pub data: Vec<u8>,
+}
+
+/// Writes every raw packet it gets into a recording.
+///
+/// Every frame is written immediately, so that the recording is usable even if the application crashes.
+pub struct Recorder {
+    file: File,
+    start: tokio::time::Instant,
+}
+
+impl Recorder {
+    /// Creates (or truncates) the recording at `path`.
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let mut file = File::create(path)?;
+        file.write_all(MAGIC)?;
+        log::info!("Recording received OSC Packets to '{}'.", path.display());
+        Ok(Self {
+            file,
+            start: tokio::time::Instant::now(),
+        })
+    }
+}
+
+impl RawPacketHandler for Recorder {
+    type Fut<'a> = core::future::Ready<()>;
+    type Output<'a> = ();
+
+    fn handle<'a>(&mut self, message: &'a [u8]) -> Self::Fut<'a> {
+        let Ok(len) = u32::try_from(message.len()) else {
+            log::warn!("Not recording a packet with {} bytes, since it is too large.", message.len());
+            return core::future::ready(());
+        };
+        let offset = u64::try_from(self.start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + message.len());
+        frame.extend_from_slice(&offset.to_le_bytes());
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(message);
+        if let Err(e) = self.file.write_all(&frame) {
+            log::warn!("Failed to record a received packet: {e}");
+        }
+        core::future::ready(())
+    }
+}
+
+/// Reads the frames of a recording.
+/// A truncated last frame (e.g. because the application crashed while writing it) is skipped.
+pub fn read_recording(mut data: &[u8]) -> std::io::Result<Vec<Frame>> {
+    data = data.strip_prefix(MAGIC.as_slice())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "The file is not an OSC recording."))?;
+    let mut frames = Vec::new();
+    while data.len() >= FRAME_HEADER_SIZE {
+        let (header, rest) = data.split_at(FRAME_HEADER_SIZE);
+        let (offset, len) = header.split_at(8);
+        let offset = u64::from_le_bytes(offset.try_into().unwrap_or_default());
+        let len = u32::from_le_bytes(len.try_into().unwrap_or_default()) as usize;
+        if rest.len() < len {
+            break;
+        }
+        let (frame, rest) = rest.split_at(len);
+        frames.push(Frame {
+            offset: Duration::from_micros(offset),
+            data: frame.to_vec(),
+        });
+        data = rest;
+    }
+    if !data.is_empty() {
+        log::warn!("The OSC recording ends with {} bytes of a truncated frame. Skipping them.", data.len());
+    }
+    Ok(frames)
+}
+
+/// Feeds recorded frames into the handlers, as if they were received at the recorded times.
+///
+/// Bundles with a timetag in the future are applied once they are due, like in the receive loop.
+/// Returns after the last frame and all buffered bundles have been handled.
+pub async fn replay<
+    H1: MessageHandler + Send, I1: IntoIterator<Item = H1>,
+    H2: PacketHandler + Send, I2: IntoIterator<Item = H2>,
+    H3: RawPacketHandler + Send, I3: IntoIterator<Item = H3>,
+>(frames: Vec<Frame>, message_handlers: I1, packet_handlers: I2, raw_packet_handlers: I3) {
+    let mut router = OscRouter::from_handlers(message_handlers, packet_handlers, raw_packet_handlers);
+    let start = tokio::time::Instant::now();
+    for frame in frames {
+        tokio::time::sleep_until(start + frame.offset).await;
+        router.run_due_bundles().await;
+        let routed = router.feed(&frame.data);
+        let rest = routed.rest.len();
+        if let Some(e) = routed.run().await {
+            log::warn!("Failed to decode {rest} bytes of a recorded frame: {e}");
+        }
+    }
+    while let Some(until) = router.time_until_next_bundle() {
+        tokio::time::sleep(until).await;
+        router.run_due_bundles().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use rosc::{OscMessage, OscPacket, OscType};
+    use crate::multple_handler::{CountingHandler, StubHandler};
+    use super::*;
+
+    /// Keeps every message it gets.
+    #[derive(Clone, Default)]
+    struct CollectingHandler(Arc<Mutex<Vec<OscMessage>>>);
+
+    impl MessageHandler for CollectingHandler {
+        type Fut = core::future::Ready<()>;
+        type Output = ();
+
+        fn handle(&mut self, message: Arc<OscMessage>) -> Self::Fut {
+            self.0.lock().unwrap().push(message.as_ref().clone());
+            core::future::ready(())
+        }
+    }
+
+    fn message(addr: &str, value: f32) -> OscMessage {
+        OscMessage { addr: addr.to_string(), args: vec![OscType::Float(value)] }
+    }
+
+    #[tokio::test]
+    async fn record_then_replay_yields_the_same_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.oscrec");
+        let messages = vec![message("/a", 1.0), message("/b", 0.5), message("/a", 0.0)];
+        let datagrams: Vec<_> = messages.iter()
+            .map(|message| rosc::encoder::encode(&OscPacket::Message(message.clone())).unwrap())
+            .collect();
+
+        let mut recorder = Recorder::create(&path).unwrap();
+        for datagram in &datagrams {
+            futures::executor::block_on(recorder.handle(datagram));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(recorder);
+
+        let frames = read_recording(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(frames.iter().map(|frame| frame.data.clone()).collect::<Vec<_>>(), datagrams);
+        assert!(frames.windows(2).all(|frames| frames[0].offset < frames[1].offset), "{frames:?}");
+
+        let collected = CollectingHandler::default();
+        let counting = CountingHandler::default();
+        replay(frames, [collected.clone()], core::iter::empty::<StubHandler>(), [counting.clone()]).await;
+        assert_eq!(*collected.0.lock().unwrap(), messages);
+        assert_eq!(counting.snapshot().raw_packets, 3);
+    }
+
+    #[test]
+    fn truncated_frames_are_skipped() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[1, 2]);
+        data.extend_from_slice(&5u64.to_le_bytes());
+        data.extend_from_slice(&100u32.to_le_bytes());
+        data.extend_from_slice(&[3]);
+        assert_eq!(read_recording(&data).unwrap(), vec![Frame { offset: Duration::ZERO, data: vec![1, 2] }]);
+        assert!(read_recording(b"not a recording").is_err());
+    }
+}
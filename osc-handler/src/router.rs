@@ -0,0 +1,263 @@
+//! Routing of OSC data to handlers, without any networking.
+//!
+//! [OscRouter] is for feeding data from any source (e.g. a socket you manage yourself, a file or a test).
+//! To receive from a UDP socket in a background task, use [crate::receiver::OscReceiverBuilder] instead.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use osc_handler::MessageHandler;
+//! use osc_handler::multple_handler::StubHandler;
+//! use osc_handler::router::OscRouter;
+//!
+//! /// Keeps the address of every message it gets.
+//! #[derive(Default)]
+//! struct Addresses(Vec<String>);
+//!
+//! impl MessageHandler for Addresses {
+//!     type Fut = core::future::Ready<()>;
+//!     type Output = ();
+//!
+//!     fn handle(&mut self, message: Arc<rosc::OscMessage>) -> Self::Fut {
+//!         self.0.push(message.addr.clone());
+//!         core::future::ready(())
+//!     }
+//! }
+//!
+//! let message = rosc::OscMessage { addr: "/avatar/change".to_string(), args: Vec::new() };
+//! let encoded = rosc::encoder::encode(&rosc::OscPacket::Message(message)).unwrap();
+//!
+//! let mut router = OscRouter::new(Addresses::default(), StubHandler, StubHandler);
+//! let routed = router.feed(&encoded);
+//! assert!(routed.rest.is_empty());
+//! assert_eq!(routed.message_count(), 1);
+//! assert!(futures::executor::block_on(routed.run()).is_none());
+//! assert_eq!(router.message_handler().0, ["/avatar/change"]);
+//! ```
+use crate::multple_handler::OscHandler;
+use crate::{MessageDestructuring, MessageHandler, PacketHandler, RawPacketHandler, Results};
+
+/// The packet handler and the [Results] of the message handler for every decoded packet.
+type PacketResults<H, P> = Vec<(<P as PacketHandler>::Fut, Results<<H as MessageHandler>::Fut, <H as MessageHandler>::Output>)>;
+/// The id and the [Results] of the message handler for every bundle, that was applied.
+type BundleResults<H> = Vec<(uuid::Uuid, Results<<H as MessageHandler>::Fut, <H as MessageHandler>::Output>)>;
+
+/// Decodes OSC data and hands it to a message, a packet and a raw packet handler.
+///
+/// Bundles with a timetag in the future are buffered, until [OscRouter::check_bundles] is called after they are due.
+/// Use [OscRouter::time_until_next_bundle] to know when that is.
+pub struct OscRouter<H, P, R>
+    where
+        H: MessageHandler,
+        P: PacketHandler,
+        R: RawPacketHandler,
+{
+    inner: MessageDestructuring<H, P, R>,
+}
+
+impl<H, P, R> OscRouter<H, P, R>
+where
+    H: MessageHandler,
+    P: PacketHandler,
+    R: RawPacketHandler,
+{
+    pub fn new(message_handler: H, packet_handler: P, raw_handler: R) -> Self {
+        Self {
+            inner: MessageDestructuring::new(message_handler, packet_handler, raw_handler),
+        }
+    }
+
+    /// Decodes all packets in `data` and hands them to the handlers.
+    ///
+    /// Nothing is processed until the returned Futures are awaited, e.g. with [Routed::run].
+    pub fn feed<'a>(&mut self, data: &'a [u8]) -> Routed<'a, H, P, R> {
+        let (rest, raw, packets, error) = self.inner.handle_raw_packets(data);
+        Routed {
+            rest,
+            raw,
+            packets,
+            error,
+        }
+    }
+
+    /// Hands all buffered bundles, that are due, to the message handler.
+    /// Returns the ids of the bundles (see [Results::NotYetApplied]) with their [Results].
+    ///
+    /// The returned [Results] contain Futures, that MUST be awaited, if any sort of processing is desired.
+    #[must_use]
+    pub fn check_bundles(&mut self) -> BundleResults<H> {
+        self.inner.check_osc_bundles()
+    }
+
+    /// Hands all buffered bundles, that are due, to the message handler and waits until they are handled.
+    /// Returns how many bundles were applied.
+    pub async fn run_due_bundles(&mut self) -> usize {
+        let bundles = self.check_bundles();
+        let count = bundles.len();
+        let messages = bundles.into_iter()
+            .flat_map(|(_, r)| r.to_messages_vec())
+            .collect::<Vec<_>>();
+        futures::future::join_all(messages).await;
+        count
+    }
+
+    /// Returns how long it is until the earliest buffered bundle is due.
+    /// Returns [std::time::Duration::ZERO], if a bundle is already due, and `None`, if no bundles are buffered.
+    pub fn time_until_next_bundle(&self) -> Option<std::time::Duration> {
+        self.inner.time_until_next_bundle()
+    }
+
+    /// Returns how many bundles are buffered, because their timetag is in the future.
+    pub fn pending_bundles(&self) -> usize {
+        self.inner.pending_bundles()
+    }
+
+    /// Drops all buffered bundles without applying them.
+    /// Returns how many were dropped.
+    pub fn clear_pending_bundles(&mut self) -> usize {
+        self.inner.clear_pending_bundles()
+    }
+
+    pub fn message_handler(&mut self) -> &mut H {
+        &mut self.inner.message_handler
+    }
+
+    pub fn packet_handler(&mut self) -> &mut P {
+        &mut self.inner.packet_handler
+    }
+
+    pub fn raw_handler(&mut self) -> &mut R {
+        &mut self.inner.raw_handler
+    }
+}
+
+impl<H1: MessageHandler + Send, H2: PacketHandler + Send, H3: RawPacketHandler + Send> OscRouter<OscHandler<H1>, OscHandler<H2>, OscHandler<H3>> {
+    /// Creates a router, that hands everything to all the given handlers.
+    pub fn from_handlers(
+        message_handlers: impl IntoIterator<Item = H1>,
+        packet_handlers: impl IntoIterator<Item = H2>,
+        raw_packet_handlers: impl IntoIterator<Item = H3>,
+    ) -> Self {
+        Self::new(
+            OscHandler::new(message_handlers.into_iter().collect()),
+            OscHandler::new(packet_handlers.into_iter().collect()),
+            OscHandler::new(raw_packet_handlers.into_iter().collect()),
+        )
+    }
+}
+
+/// The outcome of [OscRouter::feed].
+#[must_use]
+pub struct Routed<'a, H, P, R>
+    where
+        H: MessageHandler,
+        P: PacketHandler,
+        R: RawPacketHandler,
+{
+    /// The data at the end, that could not be decoded. Empty, if everything was decoded.
+    pub rest: &'a [u8],
+    /// The raw packet handler for all the data, that was decoded.
+    pub raw: R::Fut<'a>,
+    /// The packet handler and the [Results] of the message handler for every decoded packet.
+    pub packets: PacketResults<H, P>,
+    /// Why decoding stopped before the end of the data, if it did.
+    pub error: Option<rosc::OscError>,
+}
+
+impl<'a, H, P, R> Routed<'a, H, P, R>
+where
+    H: MessageHandler,
+    P: PacketHandler,
+    R: RawPacketHandler,
+{
+    /// Returns the amount of messages, that have been handed to the message handler.
+    /// Messages in bundles, that are not due yet, are not counted.
+    pub fn message_count(&self) -> usize {
+        self.packets.iter().map(|(_, r)| r.message_count()).sum()
+    }
+
+    /// Waits until all handlers are done.
+    /// Returns why decoding stopped before the end of the data, if it did.
+    pub async fn run(self) -> Option<rosc::OscError> {
+        let Self { raw, packets, error, .. } = self;
+        let packets = packets.into_iter()
+            .map(|(packet, results)| futures::future::join(packet, futures::future::join_all(results.to_messages_vec())))
+            .collect::<futures::future::JoinAll<_>>();
+        futures::future::join(packets, raw).await;
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+    use crate::multple_handler::StubHandler;
+    use crate::{classify_decode_error, DecodeDisposition};
+    use super::*;
+
+    /// Keeps the address of every message it gets.
+    #[derive(Clone, Default)]
+    struct AddrHandler(Arc<Mutex<Vec<String>>>);
+
+    impl MessageHandler for AddrHandler {
+        type Fut = core::future::Ready<()>;
+        type Output = ();
+
+        fn handle(&mut self, message: Arc<rosc::OscMessage>) -> Self::Fut {
+            self.0.lock().unwrap().push(message.addr.clone());
+            core::future::ready(())
+        }
+    }
+
+    fn message(addr: &str) -> rosc::OscPacket {
+        rosc::OscPacket::Message(rosc::OscMessage { addr: addr.to_string(), args: vec![rosc::OscType::Float(1.0)] })
+    }
+
+    #[test]
+    fn partial_packets_are_returned_as_rest() {
+        let handler = AddrHandler::default();
+        let mut router = OscRouter::new(handler.clone(), StubHandler, StubHandler);
+        let first = rosc::encoder::encode(&message("/a")).unwrap();
+        let second = rosc::encoder::encode(&message("/b")).unwrap();
+        let mut data = first.clone();
+        data.extend_from_slice(&second[..second.len() - 4]);
+
+        let routed = router.feed(&data);
+        assert_eq!(routed.rest, &second[..second.len() - 4]);
+        assert_eq!(routed.message_count(), 1);
+        let error = routed.error.as_ref().map(classify_decode_error);
+        assert_eq!(error, Some(DecodeDisposition::NeedMoreData));
+        assert!(futures::executor::block_on(routed.run()).is_some());
+        assert_eq!(*handler.0.lock().unwrap(), ["/a"]);
+
+        let routed = router.feed(&second);
+        assert!(routed.rest.is_empty());
+        assert!(futures::executor::block_on(routed.run()).is_none());
+        assert_eq!(*handler.0.lock().unwrap(), ["/a", "/b"]);
+    }
+
+    #[test]
+    fn due_bundles_are_run() {
+        let handler = AddrHandler::default();
+        let mut router = OscRouter::new(handler.clone(), StubHandler, StubHandler);
+        assert_eq!(router.time_until_next_bundle(), None);
+        //Far enough in the future, that it can't be due before it is checked below.
+        let timetag = rosc::OscTime::try_from(SystemTime::now() + Duration::from_secs(1)).unwrap();
+        let bundle = rosc::OscPacket::Bundle(rosc::OscBundle { timetag, content: vec![message("/a"), message("/b")] });
+        let encoded = rosc::encoder::encode(&bundle).unwrap();
+
+        let routed = router.feed(&encoded);
+        assert_eq!(routed.message_count(), 0);
+        assert!(futures::executor::block_on(routed.run()).is_none());
+        assert_eq!(router.pending_bundles(), 1);
+        assert_eq!(futures::executor::block_on(router.run_due_bundles()), 0);
+        assert!(handler.0.lock().unwrap().is_empty());
+
+        let until_due = router.time_until_next_bundle().unwrap();
+        std::thread::sleep(until_due + Duration::from_millis(10));
+        assert_eq!(futures::executor::block_on(router.run_due_bundles()), 1);
+        assert_eq!(*handler.0.lock().unwrap(), ["/a", "/b"]);
+        assert_eq!(router.pending_bundles(), 0);
+        assert_eq!(router.time_until_next_bundle(), None);
+    }
+}